@@ -0,0 +1,655 @@
+use std::{
+    fmt, fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::Parser;
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    constants::{
+        CHANNEL_MAX_MEMORY_BYTES, CHANNEL_RESEND_TIME, CONNECTION_AVAILABLE_BYTES_PER_TICK,
+        MAX_MESSAGES_LENGTH, RECONNECT_GRACE_PERIOD, SESSION_IDLE_TIMEOUT,
+        SESSION_MAX_CREATED_PER_PLAYER, SESSION_MAX_OPEN, SESSION_MAX_PLAYERS, SESSION_TICK_RATE,
+        SHUTDOWN_DRAIN_PERIOD, TICK_RATE, TRANSPORT_CLIENT_TIMEOUT,
+        TRANSPORT_CONNECT_TOKEN_MAX_SERVERS, TRANSPORT_KEY_BYTES, TRANSPORT_MAX_CLIENTS,
+        TRANSPORT_MAX_PACKET_BYTES, TRANSPORT_SEND_RATE,
+    },
+    ecs::components::{MovementSettings, ReconnectGracePeriod},
+    server::{
+        channel::{ChannelConfig, DefaultChannel, SendType},
+        connection::ConnectionConfig,
+        transport::{
+            conditioner::{ConditionerConfig, NetworkConditions},
+            server::{
+                auth::AuthProvider,
+                auth_worker::AuthWorkerConfig,
+                rate_limit::RateLimitConfig,
+                server::{DuplicateLoginPolicy, ServerConfig},
+            },
+            transport::ServerTransport,
+        },
+    },
+    sessions::SessionConfig,
+};
+
+/// Tick rates above this would make the main loop spin.
+const MAX_TICK_RATE: u32 = 1000;
+
+/// Command line of the server. The options override the values of the configuration file.
+#[derive(Debug, Default, Parser)]
+#[command(version, about)]
+pub struct Cli {
+    /// TOML configuration file, every value has a default when not given.
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+    /// Replays a packet capture offline instead of serving clients, with the configured settings.
+    #[arg(long, value_name = "FILE")]
+    pub replay: Option<PathBuf>,
+    /// Overrides `server.bind_address`.
+    #[arg(long, value_name = "ADDR")]
+    pub bind: Option<SocketAddr>,
+    /// Overrides `server.max_clients`.
+    #[arg(long)]
+    pub max_clients: Option<usize>,
+    /// Overrides `server.tick_rate`.
+    #[arg(long)]
+    pub tick_rate: Option<u32>,
+    /// Overrides `sessions.max_players`.
+    #[arg(long)]
+    pub session_max_players: Option<usize>,
+    /// Overrides `server.capture`.
+    #[arg(long, value_name = "FILE")]
+    pub capture: Option<PathBuf>,
+    /// Overrides any value of the configuration file, e.g. `--set movement.gravity=12`.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub overrides: Vec<String>,
+}
+
+impl Cli {
+    /// The values to override, the specific options are applied after `--set`.
+    fn overrides(&self) -> Result<Vec<(String, toml::Value)>, ConfigError> {
+        let mut overrides = self
+            .overrides
+            .iter()
+            .map(|entry| {
+                let (key, value) = entry.split_once('=').ok_or_else(|| ConfigError::Override {
+                    key: entry.clone(),
+                    reason: "expected KEY=VALUE".to_string(),
+                })?;
+                Ok((key.trim().to_string(), parse_override_value(value.trim())))
+            })
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+
+        let mut set = |key: &str, value: toml::Value| overrides.push((key.to_string(), value));
+        if let Some(bind) = self.bind {
+            set("server.bind_address", toml::Value::String(bind.to_string()));
+        }
+        if let Some(max_clients) = self.max_clients {
+            set("server.max_clients", integer(max_clients as u64));
+        }
+        if let Some(tick_rate) = self.tick_rate {
+            set("server.tick_rate", integer(tick_rate.into()));
+        }
+        if let Some(max_players) = self.session_max_players {
+            set("sessions.max_players", integer(max_players as u64));
+        }
+        if let Some(capture) = &self.capture {
+            let path = capture.to_string_lossy().into_owned();
+            set("server.capture", toml::Value::String(path));
+        }
+
+        Ok(overrides)
+    }
+}
+
+fn integer(value: u64) -> toml::Value {
+    toml::Value::Integer(value.try_into().unwrap_or(i64::MAX))
+}
+
+/// Values are read as TOML, values that are not valid TOML are taken as strings so they don't
+/// need quotes.
+fn parse_override_value(value: &str) -> toml::Value {
+    format!("value = {value}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    Override {
+        key: String,
+        reason: String,
+    },
+    Overrides(toml::de::Error),
+    Invalid {
+        key: &'static str,
+        reason: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use ConfigError::*;
+
+        match self {
+            Read { path, error } => write!(fmt, "failed to read {}: {error}", path.display()),
+            Parse { path, error } => write!(fmt, "failed to parse {}: {error}", path.display()),
+            Override { key, reason } => write!(fmt, "invalid override {key}: {reason}"),
+            Overrides(error) => write!(fmt, "invalid overrides: {}", error.message()),
+            Invalid { key, reason } => write!(fmt, "invalid {key}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Settings of the server, read from a TOML file with one table per section.
+/// Secrets, like `CONNECT_TOKEN_KEY` and `ADMIN_TOKEN`, stay in the environment.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerSection,
+    pub rate_limit: RateLimitConfig,
+    pub auth: AuthWorkerConfig,
+    pub sessions: SessionsSection,
+    pub connection: ConnectionSection,
+    pub movement: MovementSettings,
+    pub admin: AdminSection,
+    /// Degrades the traffic of the server when present, to test bad connections.
+    pub network_simulation: Option<NetworkSimulationSection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    pub bind_address: SocketAddr,
+    /// Addresses clients connect to, the bind address when empty.
+    pub public_addresses: Vec<SocketAddr>,
+    pub max_clients: usize,
+    /// Transport updates per second.
+    pub tick_rate: u32,
+    pub keep_alive_interval_ms: u64,
+    pub client_timeout_secs: u64,
+    pub duplicate_login: DuplicateLoginPolicy,
+    /// Writes every packet to this new file, to replay them with `--replay`. Captures hold the
+    /// seed of the server keys, anyone with one can decrypt the captured traffic, so the file
+    /// is only readable by its owner and an existing file is never overwritten.
+    pub capture: Option<PathBuf>,
+}
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        Self {
+            bind_address: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5000),
+            public_addresses: vec![],
+            max_clients: 64,
+            tick_rate: TICK_RATE,
+            keep_alive_interval_ms: TRANSPORT_SEND_RATE.as_millis() as u64,
+            client_timeout_secs: TRANSPORT_CLIENT_TIMEOUT.as_secs(),
+            duplicate_login: DuplicateLoginPolicy::default(),
+            capture: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionsSection {
+    /// Session updates per second.
+    pub tick_rate: u32,
+    pub max_players: usize,
+    /// Clients can not create sessions once this many are open, counting the main one.
+    pub max_open: usize,
+    /// Open sessions a player can have created.
+    pub max_created_per_player: usize,
+    pub idle_timeout_secs: u64,
+    pub reconnect_grace_secs: f64,
+    pub shutdown_drain_secs: u64,
+}
+
+impl Default for SessionsSection {
+    fn default() -> Self {
+        Self {
+            tick_rate: SESSION_TICK_RATE,
+            max_players: SESSION_MAX_PLAYERS,
+            max_open: SESSION_MAX_OPEN,
+            max_created_per_player: SESSION_MAX_CREATED_PER_PLAYER,
+            idle_timeout_secs: SESSION_IDLE_TIMEOUT.as_secs(),
+            reconnect_grace_secs: RECONNECT_GRACE_PERIOD.as_secs_f64(),
+            shutdown_drain_secs: SHUTDOWN_DRAIN_PERIOD.as_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionSection {
+    pub available_bytes_per_tick: u64,
+    pub unreliable_max_memory_bytes: usize,
+    pub reliable_max_memory_bytes: usize,
+    pub reliable_resend_ms: u64,
+}
+
+impl Default for ConnectionSection {
+    fn default() -> Self {
+        Self {
+            available_bytes_per_tick: CONNECTION_AVAILABLE_BYTES_PER_TICK,
+            unreliable_max_memory_bytes: CHANNEL_MAX_MEMORY_BYTES,
+            reliable_max_memory_bytes: CHANNEL_MAX_MEMORY_BYTES,
+            reliable_resend_ms: CHANNEL_RESEND_TIME.as_millis() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminSection {
+    /// Listens here when `ADMIN_TOKEN` is set.
+    pub address: SocketAddr,
+}
+
+impl Default for AdminSection {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 5001),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkSimulationSection {
+    pub latency_ms: u64,
+    pub jitter_ms: u64,
+    pub loss: f64,
+    pub duplicate: f64,
+    pub reorder: f64,
+    /// Only these client addresses are affected, every address when empty.
+    pub addresses: Vec<SocketAddr>,
+}
+
+impl Config {
+    /// Reads the configuration file given on the command line, applies the overrides and
+    /// validates the result.
+    pub fn load(cli: &Cli) -> Result<Config, ConfigError> {
+        let (mut config, mut table) = match &cli.config {
+            Some(path) => {
+                let text = fs::read_to_string(path).map_err(|error| ConfigError::Read {
+                    path: path.clone(),
+                    error,
+                })?;
+                // Parsed from the text first, so errors point at the line of the file
+                let parse_error = |error| ConfigError::Parse {
+                    path: path.clone(),
+                    error,
+                };
+                let config = toml::from_str::<Config>(&text).map_err(parse_error)?;
+                (config, text.parse::<toml::Table>().map_err(parse_error)?)
+            }
+            None => (Config::default(), toml::Table::new()),
+        };
+
+        let overrides = cli.overrides()?;
+        if !overrides.is_empty() {
+            for (key, value) in overrides {
+                set_value(&mut table, &key, value)?;
+            }
+            config =
+                Config::deserialize(toml::Value::Table(table)).map_err(ConfigError::Overrides)?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key, reason: &str| {
+            Err(ConfigError::Invalid {
+                key,
+                reason: reason.to_string(),
+            })
+        };
+
+        let server = &self.server;
+        if server.max_clients == 0 || server.max_clients > TRANSPORT_MAX_CLIENTS {
+            return invalid(
+                "server.max_clients",
+                &format!("must be between 1 and {TRANSPORT_MAX_CLIENTS}"),
+            );
+        }
+        if server.public_addresses.len() > TRANSPORT_CONNECT_TOKEN_MAX_SERVERS {
+            return invalid(
+                "server.public_addresses",
+                &format!("at most {TRANSPORT_CONNECT_TOKEN_MAX_SERVERS} addresses"),
+            );
+        }
+        if server.tick_rate == 0 || server.tick_rate > MAX_TICK_RATE {
+            return invalid(
+                "server.tick_rate",
+                &format!("must be between 1 and {MAX_TICK_RATE}"),
+            );
+        }
+        if self.client_timeout() <= self.auth.timeout {
+            return invalid(
+                "server.client_timeout_secs",
+                "must be longer than auth.timeout_secs",
+            );
+        }
+        if server.keep_alive_interval_ms == 0 || self.keep_alive_interval() >= self.client_timeout()
+        {
+            return invalid(
+                "server.keep_alive_interval_ms",
+                "must be above 0 and shorter than server.client_timeout_secs",
+            );
+        }
+
+        let rate_limit = &self.rate_limit;
+        if !(rate_limit.ip_packets_per_second > 0. && rate_limit.client_packets_per_second > 0.) {
+            return invalid("rate_limit", "packets per second must be above 0");
+        }
+        if !(rate_limit.ip_burst >= 1. && rate_limit.client_burst >= 1.) {
+            return invalid("rate_limit", "bursts must be at least 1");
+        }
+        if rate_limit.max_pending_per_ip == 0 {
+            return invalid("rate_limit.max_pending_per_ip", "must be at least 1");
+        }
+
+        if self.auth.queue_size == 0 || self.auth.max_concurrent == 0 {
+            return invalid("auth", "queue_size and max_concurrent must be at least 1");
+        }
+
+        let sessions = &self.sessions;
+        if sessions.tick_rate == 0 || sessions.tick_rate > MAX_TICK_RATE {
+            return invalid(
+                "sessions.tick_rate",
+                &format!("must be between 1 and {MAX_TICK_RATE}"),
+            );
+        }
+        if sessions.max_players == 0 {
+            return invalid("sessions.max_players", "must be at least 1");
+        }
+        if sessions.max_open == 0 {
+            return invalid("sessions.max_open", "must be at least 1");
+        }
+        if Duration::try_from_secs_f64(sessions.reconnect_grace_secs).is_err() {
+            return invalid(
+                "sessions.reconnect_grace_secs",
+                "must be 0 or more and fit in a duration",
+            );
+        }
+
+        let connection = &self.connection;
+        if connection.available_bytes_per_tick < TRANSPORT_MAX_PACKET_BYTES as u64 {
+            return invalid(
+                "connection.available_bytes_per_tick",
+                &format!("must be at least one packet, {TRANSPORT_MAX_PACKET_BYTES} bytes"),
+            );
+        }
+        if connection.unreliable_max_memory_bytes < MAX_MESSAGES_LENGTH
+            || connection.reliable_max_memory_bytes < MAX_MESSAGES_LENGTH
+        {
+            return invalid(
+                "connection",
+                &format!("channels must hold at least one message, {MAX_MESSAGES_LENGTH} bytes"),
+            );
+        }
+        if connection.reliable_resend_ms == 0 {
+            return invalid("connection.reliable_resend_ms", "must be above 0");
+        }
+
+        let movement = &self.movement;
+        if ![movement.velocity_mul, movement.jump_speed, movement.gravity]
+            .iter()
+            .all(|value| value.is_finite())
+        {
+            return invalid("movement", "values must be finite numbers");
+        }
+
+        if let Some(simulation) = &self.network_simulation {
+            if ![simulation.loss, simulation.duplicate, simulation.reorder]
+                .iter()
+                .all(|percentage| (0. ..=100.).contains(percentage))
+            {
+                return invalid(
+                    "network_simulation",
+                    "loss, duplicate and reorder are percentages between 0 and 100",
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn public_addresses(&self) -> Vec<SocketAddr> {
+        match self.server.public_addresses.is_empty() {
+            true => vec![self.server.bind_address],
+            false => self.server.public_addresses.clone(),
+        }
+    }
+
+    /// Time between two updates of the transport.
+    pub fn tick(&self) -> Duration {
+        Duration::from_secs(1) / self.server.tick_rate
+    }
+
+    pub fn keep_alive_interval(&self) -> Duration {
+        Duration::from_millis(self.server.keep_alive_interval_ms)
+    }
+
+    pub fn client_timeout(&self) -> Duration {
+        Duration::from_secs(self.server.client_timeout_secs)
+    }
+
+    pub fn session_idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.sessions.idle_timeout_secs)
+    }
+
+    pub fn shutdown_drain_period(&self) -> Duration {
+        Duration::from_secs(self.sessions.shutdown_drain_secs)
+    }
+
+    pub fn connection_config(&self) -> ConnectionConfig {
+        let connection = &self.connection;
+        let channels: Vec<ChannelConfig> = DefaultChannel::config()
+            .into_iter()
+            .map(|mut channel| {
+                match &mut channel.send_type {
+                    SendType::Unreliable | SendType::UnreliableSequenced => {
+                        channel.max_memory_usage_bytes = connection.unreliable_max_memory_bytes;
+                    }
+                    SendType::ReliableOrdered { resend_time }
+                    | SendType::ReliableUnordered { resend_time } => {
+                        channel.max_memory_usage_bytes = connection.reliable_max_memory_bytes;
+                        *resend_time = Duration::from_millis(connection.reliable_resend_ms);
+                    }
+                }
+                channel
+            })
+            .collect();
+
+        ConnectionConfig {
+            available_bytes_per_tick: connection.available_bytes_per_tick,
+            server_channels_config: channels.clone(),
+            client_channels_config: channels,
+        }
+    }
+
+    /// Settings of the transport server, it picks its own random seed.
+    pub fn server_config(
+        &self,
+        current_time: Duration,
+        connect_token_key: Option<[u8; TRANSPORT_KEY_BYTES]>,
+        auth_provider: Arc<dyn AuthProvider>,
+    ) -> ServerConfig {
+        ServerConfig {
+            current_time,
+            max_clients: self.server.max_clients,
+            public_addresses: self.public_addresses(),
+            connect_token_key,
+            auth_provider,
+            auth_worker: self.auth.clone(),
+            rate_limit: self.rate_limit.clone(),
+            duplicate_login: self.server.duplicate_login,
+            keep_alive_interval: self.keep_alive_interval(),
+            client_timeout: self.client_timeout(),
+            rng_seed: None,
+        }
+    }
+
+    /// Applies the settings of the sessions to the transport, before it creates any.
+    pub fn configure_sessions(&self, transport: &mut ServerTransport) {
+        transport.set_session_idle_timeout(self.session_idle_timeout());
+        transport.set_max_players_per_session(self.sessions.max_players);
+        transport.set_max_open_sessions(self.sessions.max_open);
+        transport.set_max_sessions_created_per_player(self.sessions.max_created_per_player);
+        transport.set_session_config(self.session_config());
+    }
+
+    pub fn session_config(&self) -> SessionConfig {
+        SessionConfig {
+            tick: Duration::from_secs(1) / self.sessions.tick_rate,
+            connection: self.connection_config(),
+            reconnect_grace_period: ReconnectGracePeriod(Duration::from_secs_f64(
+                self.sessions.reconnect_grace_secs,
+            )),
+            movement: self.movement,
+        }
+    }
+
+    pub fn conditioner_config(&self) -> Option<ConditionerConfig> {
+        self.network_simulation
+            .as_ref()
+            .map(|simulation| ConditionerConfig {
+                conditions: NetworkConditions {
+                    latency: Duration::from_millis(simulation.latency_ms),
+                    jitter: Duration::from_millis(simulation.jitter_ms),
+                    loss: simulation.loss,
+                    duplicate: simulation.duplicate,
+                    reorder: simulation.reorder,
+                },
+                addresses: simulation.addresses.clone(),
+            })
+    }
+}
+
+/// Sets a dotted key like `server.max_clients`, creating the tables on the way.
+fn set_value(table: &mut toml::Table, key: &str, value: toml::Value) -> Result<(), ConfigError> {
+    let invalid = |reason: &str| ConfigError::Override {
+        key: key.to_string(),
+        reason: reason.to_string(),
+    };
+
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().filter(|part| !part.is_empty());
+    let Some(last) = last else {
+        return Err(invalid("empty key"));
+    };
+
+    let mut table = table;
+    for part in parts {
+        table = table
+            .entry(part)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| invalid(&format!("{part} is not a table")))?;
+    }
+    table.insert(last.to_string(), value);
+    Ok(())
+}
+
+/// Reads a [`Duration`] given as whole seconds.
+pub fn duration_secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    u64::deserialize(deserializer).map(Duration::from_secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(config: &str, overrides: &[&str]) -> Result<Config, ConfigError> {
+        let path =
+            std::env::temp_dir().join(format!("denaria-config-{}.toml", rand::random::<u64>()));
+        fs::write(&path, config).unwrap();
+        let cli = Cli {
+            config: Some(path.clone()),
+            overrides: overrides.iter().map(|o| o.to_string()).collect(),
+            ..Default::default()
+        };
+        let config = Config::load(&cli);
+        fs::remove_file(path).unwrap();
+        config
+    }
+
+    #[test]
+    fn example_config_has_the_defaults() {
+        let example: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+        assert_eq!(example, Config::default());
+        assert!(example.validate().is_ok());
+    }
+
+    #[test]
+    fn loads_config_with_overrides() {
+        let config = load(
+            "[server]\nmax_clients = 8\nduplicate_login = \"reject_new\"\n\n[movement]\ngravity = 4.5\n",
+            &["server.max_clients=16", "rate_limit.block_duration_secs=5"],
+        )
+        .unwrap();
+
+        assert_eq!(config.server.max_clients, 16);
+        assert_eq!(
+            config.server.duplicate_login,
+            DuplicateLoginPolicy::RejectNew
+        );
+        assert_eq!(config.movement.gravity, 4.5);
+        assert_eq!(
+            config.movement.jump_speed,
+            MovementSettings::default().jump_speed
+        );
+        assert_eq!(config.rate_limit.block_duration, Duration::from_secs(5));
+        assert_eq!(config.public_addresses(), vec![config.server.bind_address]);
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let error = |config, overrides: &[&str]| load(config, overrides).unwrap_err().to_string();
+
+        assert!(error("[server]\nmax_client = 8\n", &[]).contains("unknown field `max_client`"));
+        assert!(error("", &["server.max_client=8"]).starts_with("invalid overrides: unknown field"));
+        assert!(error("[server\n", &[]).starts_with("failed to parse"));
+        assert_eq!(
+            error("", &["server.max_clients=0"]),
+            format!("invalid server.max_clients: must be between 1 and {TRANSPORT_MAX_CLIENTS}")
+        );
+        assert_eq!(
+            error("[server]\nclient_timeout_secs = 2\n", &[]),
+            "invalid server.client_timeout_secs: must be longer than auth.timeout_secs"
+        );
+        assert!(
+            error("", &["network_simulation.loss=150"]).starts_with("invalid network_simulation")
+        );
+        for grace in ["-1", "nan", "1e300"] {
+            assert!(
+                error("", &[&format!("sessions.reconnect_grace_secs={grace}")])
+                    .starts_with("invalid sessions.reconnect_grace_secs")
+            );
+        }
+        assert_eq!(
+            error("", &["server.max_clients"]),
+            "invalid override server.max_clients: expected KEY=VALUE"
+        );
+    }
+}
@@ -7,14 +7,79 @@ pub const TRANSPORT_MAX_PACKET_BYTES: usize = 1400;
 /// The maximum number of bytes that a payload can have when generating a payload packet.
 pub const TRANSPORT_MAX_PAYLOAD_BYTES: usize = 1300;
 pub const MAX_MESSAGES_LENGTH: usize = 1200;
+/// Messages longer than [`MAX_MESSAGES_LENGTH`] are split in slices of this size, sent one per packet.
+pub const SLICE_SIZE: usize = MAX_MESSAGES_LENGTH;
+/// Unreliable sliced messages are dropped when none of their missing slices arrived for this long.
+pub const SLICE_DISCARD_AFTER: Duration = Duration::from_secs(3);
+/// Bytes a connection can send per tick, at 60hz this becomes 28.8 Mbps.
+pub const CONNECTION_AVAILABLE_BYTES_PER_TICK: u64 = 60_000;
+/// Bytes a channel holds before it drops new messages or disconnects the client.
+pub const CHANNEL_MAX_MEMORY_BYTES: usize = 5 * 1024 * 1024;
+/// Reliable messages are sent again when not acked after this long.
+pub const CHANNEL_RESEND_TIME: Duration = Duration::from_millis(300);
+/// Connected clients are sent a keep alive when nothing else was sent to them for this long.
 pub const TRANSPORT_SEND_RATE: Duration = Duration::from_millis(250);
+/// Connected clients are disconnected when nothing was received from them for this long.
+pub const TRANSPORT_CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Connection requests must start with this prefix, it changes whenever the handshake or packet format does.
+pub const TRANSPORT_PROTOCOL_PREFIX: [u8; 3] = *b"DN1";
+/// Size of the cookie the server hands out in a connection challenge.
+pub const TRANSPORT_COOKIE_BYTES: usize = 32;
+/// Cookies are tied to a time bucket of this length, a cookie is accepted during its own bucket and the next one.
+pub const TRANSPORT_COOKIE_BUCKET: Duration = Duration::from_secs(10);
+/// Size of the public keys exchanged in the handshake and of the derived connection keys.
+pub const TRANSPORT_KEY_BYTES: usize = 32;
+/// Size of the authentication tag appended to encrypted packets.
+pub const TRANSPORT_MAC_BYTES: usize = 16;
+/// Connection requests smaller than this are dropped, so a challenge is never bigger than the request that caused it.
+pub const TRANSPORT_MIN_CONNECTION_REQUEST_BYTES: usize = 64;
+/// Size of the application data the backend can attach to a connect token.
+pub const TRANSPORT_USER_DATA_BYTES: usize = 256;
+/// Maximum number of server addresses a connect token can list.
+pub const TRANSPORT_CONNECT_TOKEN_MAX_SERVERS: usize = 32;
+/// Authentications waiting for the auth worker before new clients are denied.
+pub const TRANSPORT_AUTH_QUEUE_SIZE: usize = 256;
+/// Authentications the auth worker runs at the same time.
+pub const TRANSPORT_AUTH_MAX_CONCURRENT: usize = 32;
+/// Authentications taking longer than this fail, it must stay below the pending client timeout.
+pub const TRANSPORT_AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Packets per second allowed from one IP address, enough for a few players behind the same NAT.
+pub const TRANSPORT_RATE_LIMIT_IP_PACKETS_PER_SECOND: f64 = 600.;
+pub const TRANSPORT_RATE_LIMIT_IP_BURST: f64 = 1200.;
+/// Packets per second allowed from one connected client, clients send a few packets per tick.
+pub const TRANSPORT_RATE_LIMIT_CLIENT_PACKETS_PER_SECOND: f64 = 240.;
+pub const TRANSPORT_RATE_LIMIT_CLIENT_BURST: f64 = 480.;
+/// Pending connections one IP address can have at a time.
+pub const TRANSPORT_RATE_LIMIT_MAX_PENDING_PER_IP: usize = 16;
+/// An address going over its rate with this many packets within a second gets blocked.
+pub const TRANSPORT_RATE_LIMIT_BLOCK_AFTER: u32 = 1000;
+pub const TRANSPORT_RATE_LIMIT_BLOCK_DURATION: Duration = Duration::from_secs(30);
+
+/// Players whose connection dropped keep their entity this long, so they can reconnect to it.
+pub const RECONNECT_GRACE_PERIOD: Duration = Duration::from_secs(15);
 
 pub static VELOCITY_MUL: f32 = 0.3;
 pub static JUMP_SPEED: f32 = 5.5;
 pub static GRAVITY: f32 = 9.8;
 
+/// Updates per second of the transport.
+pub const TICK_RATE: u32 = 60;
+/// Time between two updates of the transport in tests.
+#[cfg(test)]
 pub static TICK_DELTA: Duration = Duration::from_millis(16);
 
 pub static DEBUG_CAMERA_SENSITIVITY: f32 = 0.01;
 
 pub static MAIN_SESSION_ID: u32 = 0;
+/// Updates per second of a session.
+pub const SESSION_TICK_RATE: u32 = 30;
+/// Sessions other than the main one are shut down after being empty this long.
+pub const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// Players in a session before connecting players are sent to another one.
+pub const SESSION_MAX_PLAYERS: usize = 32;
+/// Open sessions, counting the main one, above which clients can not create more.
+pub const SESSION_MAX_OPEN: usize = 16;
+/// Open sessions a player can have created.
+pub const SESSION_MAX_CREATED_PER_PLAYER: usize = 1;
+/// How long clients are warned before the server shuts down.
+pub const SHUTDOWN_DRAIN_PERIOD: Duration = Duration::from_secs(10);
@@ -1,5 +1,8 @@
-use bevy::prelude::{Bundle, Component, Entity, Resource};
-use std::collections::HashMap;
+use bevy::prelude::{Bundle, Component, Entity, Resource, Timer, TimerMode};
+use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
+
+use crate::constants::{GRAVITY, JUMP_SPEED, RECONNECT_GRACE_PERIOD, VELOCITY_MUL};
 
 #[derive(Default, Component)]
 pub struct Player {
@@ -16,6 +19,21 @@ pub struct MoveInput {
     pub z: f32,
 }
 
+/// Marks a player whose connection dropped. The entity is despawned when the timer finishes,
+/// unless the player connects again before that.
+#[derive(Debug, Component)]
+pub struct LinkDead {
+    pub timer: Timer,
+}
+
+impl LinkDead {
+    pub fn new(grace_period: Duration) -> Self {
+        LinkDead {
+            timer: Timer::new(grace_period, TimerMode::Once),
+        }
+    }
+}
+
 #[derive(Bundle)]
 pub struct PlayerBundle {
     pub player: Player,
@@ -49,3 +67,33 @@ impl PlayerLookup {
         }
     }
 }
+
+/// How long the entity of a player whose connection dropped is kept, zero despawns it right away.
+#[derive(Debug, Clone, Copy, Resource)]
+pub struct ReconnectGracePeriod(pub Duration);
+
+impl Default for ReconnectGracePeriod {
+    fn default() -> Self {
+        ReconnectGracePeriod(RECONNECT_GRACE_PERIOD)
+    }
+}
+
+/// How the inputs of the players move their character.
+#[derive(Debug, Clone, Copy, PartialEq, Resource, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MovementSettings {
+    /// Horizontal distance moved per tick for a full move input.
+    pub velocity_mul: f32,
+    pub jump_speed: f32,
+    pub gravity: f32,
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            velocity_mul: VELOCITY_MUL,
+            jump_speed: JUMP_SPEED,
+            gravity: GRAVITY,
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use bevy::prelude::*;
 
+use crate::server::{error::DisconnectReason, server::ClientId};
+
 #[derive(Debug, Event)]
 pub struct MoveEvent {
     pub entity: Entity,
@@ -22,7 +24,14 @@ pub struct SpawnEvent {
     pub player_id: String,
 }
 
+#[derive(Event)]
+pub struct ConnectEvent {
+    pub client_id: ClientId,
+    pub player_id: String,
+}
+
 #[derive(Event)]
 pub struct DisconnectEvent {
     pub player_id: String,
+    pub reason: DisconnectReason,
 }
@@ -4,16 +4,22 @@ use bevy::prelude::*;
 use bevy_rapier3d::prelude::*;
 
 use crate::{
-    constants::{GRAVITY, JUMP_SPEED, VELOCITY_MUL},
     ecs::{
-        components::{MoveInput, Player, PlayerBundle, PlayerLookup, VerticalVelocity},
-        events::{DisconnectEvent, LookEvent, SpawnEvent},
+        components::{
+            LinkDead, MoveInput, MovementSettings, Player, PlayerBundle, PlayerLookup,
+            ReconnectGracePeriod, VerticalVelocity,
+        },
+        events::{ConnectEvent, DisconnectEvent, LookEvent, SpawnEvent},
+    },
+    server::{
+        channel::DefaultChannel, error::DisconnectReason, message_out::MessageOut,
+        server::DenariaServer,
     },
-    server::{channel::DefaultChannel, message_out::MessageOut, server::DenariaServer},
 };
 
 pub fn handle_character_movement(
     time: Res<Time>,
+    settings: Res<MovementSettings>,
     mut query: Query<(
         &mut KinematicCharacterController,
         &mut MoveInput,
@@ -23,12 +29,12 @@ pub fn handle_character_movement(
 ) {
     let delta_time = time.delta_seconds();
     for (mut controller, mut move_input, mut v_velocity, output) in query.iter_mut() {
-        let mut movement = Vec3::new(move_input.x, 0.0, move_input.z) * VELOCITY_MUL;
+        let mut movement = Vec3::new(move_input.x, 0.0, move_input.z) * settings.velocity_mul;
 
         if output.map(|o| o.grounded).unwrap_or(false) {
-            v_velocity.0 = move_input.y * JUMP_SPEED;
+            v_velocity.0 = move_input.y * settings.jump_speed;
         } else {
-            v_velocity.0 -= GRAVITY * delta_time * controller.custom_mass.unwrap_or(1.0);
+            v_velocity.0 -= settings.gravity * delta_time * controller.custom_mass.unwrap_or(1.0);
         }
 
         move_input.x = 0.0;
@@ -87,23 +93,191 @@ pub fn handle_spawn_events(
     }
 }
 
+/// Reattaches reconnecting players to the entity they left behind.
+pub fn handle_connect_events(
+    mut commands: Commands,
+    mut connect_events: EventReader<ConnectEvent>,
+    player_lookup: Res<PlayerLookup>,
+    query: Query<(&Player, &Transform)>,
+    mut server: ResMut<DenariaServer>,
+) {
+    for event in connect_events.read() {
+        let Some(entity) = player_lookup.map.get(&event.player_id) else {
+            continue;
+        };
+        let Ok((player, transform)) = query.get(*entity) else {
+            continue;
+        };
+        tracing::info!("Player {} reconnected, resuming its entity", player.id);
+        commands.entity(*entity).remove::<LinkDead>();
+
+        // The entity is not added again, so its spawn is only sent to the reconnected client
+        if let Some(spawn_message) =
+            MessageOut::spawn_message(player.id.clone(), transform.translation, transform.rotation)
+        {
+            server.send_message(
+                event.client_id,
+                DefaultChannel::ReliableOrdered,
+                spawn_message.data,
+            );
+        }
+    }
+}
+
 pub fn handle_disconnect_events(
     mut commands: Commands,
     mut disconnect_events: EventReader<DisconnectEvent>,
     mut player_lookup: ResMut<PlayerLookup>,
+    grace_period: Res<ReconnectGracePeriod>,
     mut server: ResMut<DenariaServer>,
 ) {
     if disconnect_events.len() > 0 {
         let mut disconnect_player_ids: Vec<&String> = vec![];
         for event in disconnect_events.read() {
+            // The player is already connected again with another client
+            if server
+                .client_id_by_player_id(event.player_id.clone())
+                .is_ok()
+            {
+                continue;
+            }
             if let Some(entity) = player_lookup.map.get(&event.player_id) {
+                // Dropped connections keep their entity for a while, in case they come back
+                if event.reason == DisconnectReason::Transport && !grace_period.0.is_zero() {
+                    commands
+                        .entity(*entity)
+                        .insert(LinkDead::new(grace_period.0));
+                    continue;
+                }
                 commands.entity(*entity).despawn();
                 player_lookup.map.remove(&event.player_id);
                 disconnect_player_ids.push(&event.player_id);
             }
         }
-        let disconnect_event = MessageOut::disconnect_message(disconnect_player_ids).unwrap();
-        tracing::trace!("Disconnect event: {:?}", disconnect_event);
-        server.broadcast_message(DefaultChannel::ReliableOrdered, disconnect_event.data);
+        broadcast_disconnects(&mut server, disconnect_player_ids);
+    }
+}
+
+/// Despawns the link-dead players whose grace period is over.
+pub fn despawn_link_dead_players(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &Player, &mut LinkDead)>,
+    mut player_lookup: ResMut<PlayerLookup>,
+    mut server: ResMut<DenariaServer>,
+) {
+    let mut disconnect_player_ids: Vec<&String> = vec![];
+    for (entity, player, mut link_dead) in query.iter_mut() {
+        if link_dead.timer.tick(time.delta()).just_finished() {
+            tracing::info!("Player {} did not reconnect, despawning it", player.id);
+            commands.entity(entity).despawn();
+            player_lookup.map.remove(&player.id);
+            disconnect_player_ids.push(&player.id);
+        }
+    }
+    broadcast_disconnects(&mut server, disconnect_player_ids);
+}
+
+fn broadcast_disconnects(server: &mut DenariaServer, player_ids: Vec<&String>) {
+    if player_ids.is_empty() {
+        return;
+    }
+    let disconnect_event = MessageOut::disconnect_message(player_ids).unwrap();
+    tracing::trace!("Disconnect event: {:?}", disconnect_event);
+    server.broadcast_message(DefaultChannel::ReliableOrdered, disconnect_event.data);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::server::{connection::ConnectionConfig, server::ClientId};
+
+    use super::*;
+
+    fn test_app(grace_period: Duration) -> App {
+        let (_, from_transport_rx) = crossbeam::channel::unbounded();
+        let (to_transport_tx, _) = crossbeam::channel::unbounded();
+        let mut app = App::new();
+        app.insert_resource(DenariaServer::new(
+            0,
+            ConnectionConfig::default(),
+            from_transport_rx,
+            to_transport_tx,
+        ))
+        .insert_resource(PlayerLookup::new())
+        .insert_resource(ReconnectGracePeriod(grace_period))
+        .insert_resource(Time::<()>::default())
+        .add_event::<ConnectEvent>()
+        .add_event::<DisconnectEvent>()
+        .add_systems(
+            Update,
+            (
+                handle_connect_events,
+                handle_disconnect_events,
+                despawn_link_dead_players.after(handle_connect_events),
+            ),
+        );
+        app
+    }
+
+    fn spawn_player(app: &mut App, player_id: &str) -> Entity {
+        let entity = app
+            .world_mut()
+            .spawn((
+                Player {
+                    id: player_id.to_string(),
+                },
+                Transform::default(),
+            ))
+            .id();
+        app.world_mut()
+            .resource_mut::<PlayerLookup>()
+            .map
+            .insert(player_id.to_string(), entity);
+        entity
+    }
+
+    fn disconnect(app: &mut App, player_id: &str, reason: DisconnectReason) {
+        app.world_mut().send_event(DisconnectEvent {
+            player_id: player_id.to_string(),
+            reason,
+        });
+    }
+
+    fn advance(app: &mut App, duration: Duration) {
+        app.world_mut().resource_mut::<Time>().advance_by(duration);
+        app.update();
+    }
+
+    #[test]
+    fn link_dead_players_resume_or_despawn_after_grace_period() {
+        let mut app = test_app(Duration::from_secs(10));
+        let resumed = spawn_player(&mut app, "resumed");
+        let dropped = spawn_player(&mut app, "dropped");
+        let left = spawn_player(&mut app, "left");
+
+        disconnect(&mut app, "resumed", DisconnectReason::Transport);
+        disconnect(&mut app, "dropped", DisconnectReason::Transport);
+        disconnect(&mut app, "left", DisconnectReason::DisconnectedByClient);
+        app.update();
+
+        assert!(app.world().get::<LinkDead>(resumed).is_some());
+        assert!(app.world().get::<LinkDead>(dropped).is_some());
+        assert!(app.world().get_entity(left).is_none());
+
+        advance(&mut app, Duration::from_secs(5));
+        app.world_mut().send_event(ConnectEvent {
+            client_id: ClientId::from_raw(1),
+            player_id: "resumed".to_string(),
+        });
+        advance(&mut app, Duration::from_secs(5));
+        assert!(app.world().get::<LinkDead>(resumed).is_none());
+
+        advance(&mut app, Duration::from_secs(1));
+        let lookup = &app.world().resource::<PlayerLookup>().map;
+        assert_eq!(lookup.get("resumed"), Some(&resumed));
+        assert!(!lookup.contains_key("dropped"));
+        assert!(app.world().get_entity(dropped).is_none());
     }
 }
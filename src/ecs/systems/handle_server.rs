@@ -1,10 +1,9 @@
-use bevy::prelude::{EventWriter, Query, Res, ResMut};
+use bevy::prelude::{AppExit, EventWriter, Query, Res, ResMut, Time};
 
 use crate::{
-    constants::TICK_DELTA,
     ecs::{
         components::{MoveInput, PlayerLookup},
-        events::{DisconnectEvent, LookEvent, SpawnEvent},
+        events::{ConnectEvent, DisconnectEvent, LookEvent, SpawnEvent},
     },
     server::{
         channel::DefaultChannel,
@@ -14,10 +13,12 @@ use crate::{
 };
 
 pub fn handle_server_events(
+    time: Res<Time>,
     mut server: ResMut<DenariaServer>,
+    mut connect_event: EventWriter<ConnectEvent>,
     mut disconnect_event: EventWriter<DisconnectEvent>,
 ) {
-    server.update(TICK_DELTA);
+    server.update(time.delta());
     server.process_server_transport_messages();
 
     // Check for client connections/disconnections
@@ -25,6 +26,12 @@ pub fn handle_server_events(
         match event {
             ServerEvent::ClientConnected { client_id } => {
                 println!("Client {client_id} connected");
+                if let Ok(player_id) = server.player_id(client_id) {
+                    connect_event.send(ConnectEvent {
+                        client_id,
+                        player_id: player_id.clone(),
+                    });
+                }
             }
             ServerEvent::ClientDisconnected {
                 client_id,
@@ -32,7 +39,7 @@ pub fn handle_server_events(
                 reason,
             } => {
                 println!("Client {client_id} disconnected: {reason}");
-                disconnect_event.send(DisconnectEvent { player_id });
+                disconnect_event.send(DisconnectEvent { player_id, reason });
             }
         }
     }
@@ -45,68 +52,81 @@ pub fn handle_server_messages(
     mut move_query: Query<&mut MoveInput>,
     mut look_event: EventWriter<LookEvent>,
 ) {
-    // Receive message from channel
+    // Receive message from channel, Move and Rotation inputs come on their sequenced channels
 
     server.clients_id().iter().for_each(|client_id| {
-        while let Some((message, player_id)) =
-            server.receive_message(*client_id, DefaultChannel::Unreliable)
-        {
-            let event_in = match MessageIn::new(message.to_vec(), player_id.clone()) {
-                Ok(event) => event,
-                Err(e) => {
-                    tracing::error!("Failed to create MessageIn: {}", e);
-                    continue;
-                }
-            };
+        for channel in [
+            DefaultChannel::Unreliable,
+            DefaultChannel::Movement,
+            DefaultChannel::Rotation,
+        ] {
+            let channel = u8::from(channel);
+            while let Some((message, player_id)) = server.receive_message(*client_id, channel) {
+                let event_in = match MessageIn::new(message.to_vec(), player_id.clone()) {
+                    Ok(event) => event,
+                    Err(e) => {
+                        tracing::error!("Failed to create MessageIn: {}", e);
+                        continue;
+                    }
+                };
 
-            match event_in.event_type {
-                MessageInType::Rotation => {
-                    if let Some(player_entity) = player_lookup.map.get(player_id) {
-                        match event_in.to_look_event(*player_entity) {
-                            Ok(event) => {
-                                look_event.send(event);
-                            }
-                            Err(_) => {
-                                tracing::error!("Failed to create LookEvent");
+                match event_in.event_type {
+                    MessageInType::Rotation => {
+                        if let Some(player_entity) = player_lookup.map.get(player_id) {
+                            match event_in.to_look_event(*player_entity) {
+                                Ok(event) => {
+                                    look_event.send(event);
+                                }
+                                Err(_) => {
+                                    tracing::error!("Failed to create LookEvent");
+                                }
                             }
                         }
                     }
-                }
-                MessageInType::Move => {
-                    if let Some(player_entity) = player_lookup.map.get(player_id) {
-                        match event_in.to_move_event(*player_entity) {
-                            Ok(event) => {
-                                if let Ok(mut move_entity) = move_query.get_mut(event.entity) {
-                                    move_entity.x = event.x;
-                                    move_entity.z = event.y;
+                    MessageInType::Move => {
+                        if let Some(player_entity) = player_lookup.map.get(player_id) {
+                            match event_in.to_move_event(*player_entity) {
+                                Ok(event) => {
+                                    if let Ok(mut move_entity) = move_query.get_mut(event.entity) {
+                                        move_entity.x = event.x;
+                                        move_entity.z = event.y;
+                                    }
+                                }
+                                Err(_) => {
+                                    tracing::error!("Failed to create MoveEvent");
                                 }
-                            }
-                            Err(_) => {
-                                tracing::error!("Failed to create MoveEvent");
                             }
                         }
                     }
-                }
-                MessageInType::Jump => {
-                    if let Some(player_entity) = player_lookup.map.get(player_id) {
-                        match event_in.to_jump_event(*player_entity) {
-                            Ok(event) => {
-                                if let Ok(mut move_entity) = move_query.get_mut(event.entity) {
-                                    move_entity.y = 1.0;
+                    MessageInType::Jump => {
+                        if let Some(player_entity) = player_lookup.map.get(player_id) {
+                            match event_in.to_jump_event(*player_entity) {
+                                Ok(event) => {
+                                    if let Ok(mut move_entity) = move_query.get_mut(event.entity) {
+                                        move_entity.y = 1.0;
+                                    }
                                 }
+                                Err(_) => {}
                             }
-                            Err(_) => {}
                         }
                     }
-                }
-                MessageInType::Spawn => match event_in.to_spawn_event() {
-                    Ok(event) => {
-                        spawn_event.send(event);
+                    MessageInType::Spawn => match event_in.to_spawn_event() {
+                        Ok(event) => {
+                            spawn_event.send(event);
+                        }
+                        Err(_) => {}
+                    },
+                    MessageInType::SessionCreate => server.create_session(*client_id),
+                    MessageInType::SessionJoin => match event_in.to_session_id() {
+                        Ok(session_id) => server.join_session(*client_id, session_id),
+                        Err(_) => {
+                            tracing::error!("Failed to read the session to join");
+                        }
+                    },
+                    MessageInType::SessionList => server.list_sessions(*client_id),
+                    MessageInType::Invalid => {
+                        tracing::error!("Invalid MessageInType");
                     }
-                    Err(_) => {}
-                },
-                MessageInType::Invalid => {
-                    tracing::error!("Invalid MessageInType");
                 }
             }
         }
@@ -119,3 +139,13 @@ pub fn handle_outgoing_messages(mut server: ResMut<DenariaServer>) {
         server.send_packets_to_server_transport(client_id, packets);
     }
 }
+
+pub fn notify_session_started(server: Res<DenariaServer>) {
+    server.session_started();
+}
+
+pub fn exit_on_shutdown(server: Res<DenariaServer>, mut app_exit: EventWriter<AppExit>) {
+    if server.is_shutting_down() {
+        app_exit.send(AppExit::Success);
+    }
+}
@@ -22,10 +22,10 @@ pub fn on_transform_change(
     }
     if positions.len() > 0 {
         if let Some(position_message) = MessageOut::position_message(positions) {
-            server.broadcast_message(DefaultChannel::Unreliable, position_message.data);
+            server.broadcast_message(DefaultChannel::Movement, position_message.data);
         }
         if let Some(rotation_message) = MessageOut::rotation_message(rotations) {
-            server.broadcast_message(DefaultChannel::Unreliable, rotation_message.data);
+            server.broadcast_message(DefaultChannel::Rotation, rotation_message.data);
         }
     }
 }
@@ -18,7 +18,7 @@ use tokio::runtime::Runtime;
 
 use crate::ecs::{
     components::PlayerLookup,
-    events::{DisconnectEvent, JumpEvent, LookEvent, MoveEvent, SpawnEvent},
+    events::{ConnectEvent, DisconnectEvent, JumpEvent, LookEvent, MoveEvent, SpawnEvent},
 };
 
 pub fn setup(mut commands: Commands) {
@@ -30,6 +30,7 @@ pub fn setup(mut commands: Commands) {
     commands.insert_resource(level_objects);
 
     commands.insert_resource(Events::<SpawnEvent>::default());
+    commands.insert_resource(Events::<ConnectEvent>::default());
     commands.insert_resource(Events::<DisconnectEvent>::default());
     commands.insert_resource(Events::<LookEvent>::default());
     commands.insert_resource(Events::<MoveEvent>::default());
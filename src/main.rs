@@ -1,18 +1,32 @@
 use std::{
-    io,
-    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    fs::File,
+    io::{self, BufReader, BufWriter},
+    net::{SocketAddr, UdpSocket},
+    process::ExitCode,
     time::SystemTime,
 };
+mod config;
 mod constants;
 mod ecs;
 mod server;
 mod sessions;
 
-use constants::{MAIN_SESSION_ID, TICK_DELTA};
-use server::transport::{server::server::ServerConfig, transport::ServerTransport};
+use clap::Parser;
+use config::{Cli, Config};
+use constants::{MAIN_SESSION_ID, TRANSPORT_KEY_BYTES};
+use server::transport::{
+    admin::AdminServer,
+    capture::create_capture_file,
+    conditioner::ConditionedSocket,
+    replay::replay,
+    server::auth::auth_provider_from_env,
+    signal::ShutdownSignal,
+    socket::{TransportSocket, UdpTransportSocket},
+    transport::ServerTransport,
+};
 use tracing_subscriber::EnvFilter;
 
-fn main() -> io::Result<()> {
+fn main() -> io::Result<ExitCode> {
     let subscriber = tracing_subscriber::fmt::Subscriber::builder()
         .with_env_filter(EnvFilter::from_default_env())
         .with_thread_ids(true)
@@ -24,28 +38,119 @@ fn main() -> io::Result<()> {
     // Now the tracing macros can be used throughout your application
     tracing::info!("This will dynamically update on the terminal");
 
+    dotenvy::dotenv().ok();
+
+    let cli = Cli::parse();
+    let config = match Config::load(&cli) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("Invalid configuration: {e}");
+            return Ok(ExitCode::FAILURE);
+        }
+    };
+
+    // Replays a packet capture offline instead of serving clients
+    if let Some(path) = &cli.replay {
+        let report = replay(
+            BufReader::new(File::open(path)?),
+            &config,
+            connect_token_key()?,
+            true,
+        )?;
+        tracing::info!("Replayed {}: {}", path.display(), report);
+        return Ok(ExitCode::SUCCESS);
+    }
+
     // Setup transport layer
-    const SERVER_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 5000);
-    let socket: UdpSocket = UdpSocket::bind(SERVER_ADDR)?;
-    let server_config = ServerConfig {
-        current_time: SystemTime::now()
+    let socket = UdpTransportSocket::new(UdpSocket::bind(config.server.bind_address)?)?;
+    tracing::info!("Listening on {}", socket.local_addr()?);
+    let server_config = config.server_config(
+        SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap(),
-        max_clients: 64,
-        public_addresses: vec![SERVER_ADDR],
+        connect_token_key()?,
+        auth_provider_from_env()?,
+    );
+
+    let socket: Box<dyn TransportSocket> = match config.conditioner_config() {
+        Some(conditioner_config) => {
+            tracing::warn!("Simulating network conditions: {:?}", conditioner_config);
+            Box::new(ConditionedSocket::from_config(socket, conditioner_config))
+        }
+        None => Box::new(socket),
     };
 
-    let mut transport = ServerTransport::new(server_config, socket)?;
+    let mut transport = match &config.server.capture {
+        Some(path) => {
+            tracing::info!("Capturing packets to {}", path.display());
+            ServerTransport::with_capture(
+                server_config,
+                socket,
+                BufWriter::new(create_capture_file(path)?),
+            )?
+        }
+        None => ServerTransport::new(server_config, socket)?,
+    };
 
+    config.configure_sessions(&mut transport);
     // create default session with player_ids from player1 to player10
     transport.create_session(MAIN_SESSION_ID);
 
-    loop {
-        transport.update(TICK_DELTA).unwrap();
+    let admin = admin_server(config.admin.address)?;
+    let shutdown_signal = ShutdownSignal::listen()?;
+
+    // Runs until the clients were warned of a shutdown for the drain period
+    let tick = config.tick();
+    while !transport.is_drained() {
+        transport.update(tick).unwrap();
 
         transport.send_packets();
 
-        // make this loop run 60 times per second
-        std::thread::sleep(std::time::Duration::from_millis(16));
+        if let Some(admin) = &admin {
+            admin.handle_requests(&mut transport);
+        }
+        // The signal stays received, the shutdown starts only once
+        if shutdown_signal.is_received() && !transport.is_shutting_down() {
+            tracing::info!("Received shutdown signal");
+            transport.begin_shutdown(config.shutdown_drain_period());
+        }
+
+        std::thread::sleep(tick);
     }
+
+    tracing::info!("Disconnecting clients and stopping sessions");
+    if transport.shutdown() {
+        tracing::info!("Server stopped");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+/// Starts the admin endpoint when `ADMIN_TOKEN` is set.
+fn admin_server(addr: SocketAddr) -> io::Result<Option<AdminServer>> {
+    let Ok(token) = std::env::var("ADMIN_TOKEN") else {
+        return Ok(None);
+    };
+
+    let admin = AdminServer::bind(addr, token.trim().to_string())?;
+    tracing::info!("Admin endpoint listening on {}", admin.local_addr());
+    Ok(Some(admin))
+}
+
+/// Reads the hex encoded key shared with the backend that signs connect tokens.
+fn connect_token_key() -> io::Result<Option<[u8; TRANSPORT_KEY_BYTES]>> {
+    let Ok(key) = std::env::var("CONNECT_TOKEN_KEY") else {
+        return Ok(None);
+    };
+
+    let mut bytes = [0u8; TRANSPORT_KEY_BYTES];
+    hex::decode_to_slice(key.trim(), &mut bytes).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("CONNECT_TOKEN_KEY must be {TRANSPORT_KEY_BYTES} hex encoded bytes: {e}"),
+        )
+    })?;
+
+    Ok(Some(bytes))
 }
@@ -1,17 +1,38 @@
 pub(crate) mod reliable;
+pub(crate) mod slice;
 pub(crate) mod unreliable;
 
 use std::time::Duration;
 
+use crate::constants::{CHANNEL_MAX_MEMORY_BYTES, CHANNEL_RESEND_TIME};
+
 /// Delivery garantee of a channel
 #[derive(Debug, Clone)]
 pub enum SendType {
     // Messages can be lost or received out of order.
     Unreliable,
+    /// Messages can be lost, the ones older than the newest message received are dropped.
+    UnreliableSequenced,
     /// Messages are guaranteed to be received and in the same order they were sent.
     ReliableOrdered {
         resend_time: Duration,
     },
+    /// Messages are guaranteed to be received, in any order, so a lost message does not hold
+    /// back the ones sent after it.
+    ReliableUnordered {
+        resend_time: Duration,
+    },
+}
+
+impl SendType {
+    /// How long reliable messages wait for an ack before being sent again, zero for unreliable ones.
+    pub fn resend_time(&self) -> Duration {
+        match self {
+            SendType::Unreliable | SendType::UnreliableSequenced => Duration::ZERO,
+            SendType::ReliableOrdered { resend_time }
+            | SendType::ReliableUnordered { resend_time } => *resend_time,
+        }
+    }
 }
 
 /// Configuration of a channel for a server or client
@@ -20,6 +41,7 @@ pub enum SendType {
 pub struct ChannelConfig {
     /// Channel identifier, must be unique within its own list,
     /// but it can be repeated between the server and client lists.
+    /// Any u8, packets have a whole byte for it.
     pub channel_id: u8,
     /// Maximum number of bytes that the channel may hold without acknowledgement of messages before becoming full.
     /// Unreliable channels will drop new messages when this value is reached.
@@ -30,10 +52,15 @@ pub struct ChannelConfig {
 }
 
 /// Utility enumerator when using the default channels configuration.
-/// The default configuration has 3 channels: unreliable, reliable ordered, and reliable unordered.
+/// The default configuration has 5 channels: unreliable, reliable ordered, reliable unordered,
+/// and an unreliable sequenced channel for each kind of state update, movement and rotation.
+/// A sequenced channel only keeps the newest message, so updates sharing one would drop each other.
 pub enum DefaultChannel {
     Unreliable,
     ReliableOrdered,
+    ReliableUnordered,
+    Movement,
+    Rotation,
 }
 
 impl From<DefaultChannel> for u8 {
@@ -41,6 +68,9 @@ impl From<DefaultChannel> for u8 {
         match channel {
             DefaultChannel::Unreliable => 0,
             DefaultChannel::ReliableOrdered => 1,
+            DefaultChannel::ReliableUnordered => 2,
+            DefaultChannel::Movement => 3,
+            DefaultChannel::Rotation => 4,
         }
     }
 }
@@ -50,16 +80,33 @@ impl DefaultChannel {
         vec![
             ChannelConfig {
                 channel_id: 0,
-                max_memory_usage_bytes: 5 * 1024 * 1024,
+                max_memory_usage_bytes: CHANNEL_MAX_MEMORY_BYTES,
                 send_type: SendType::Unreliable,
             },
             ChannelConfig {
                 channel_id: 1,
-                max_memory_usage_bytes: 5 * 1024 * 1024,
+                max_memory_usage_bytes: CHANNEL_MAX_MEMORY_BYTES,
                 send_type: SendType::ReliableOrdered {
-                    resend_time: Duration::from_millis(300),
+                    resend_time: CHANNEL_RESEND_TIME,
                 },
             },
+            ChannelConfig {
+                channel_id: 2,
+                max_memory_usage_bytes: CHANNEL_MAX_MEMORY_BYTES,
+                send_type: SendType::ReliableUnordered {
+                    resend_time: CHANNEL_RESEND_TIME,
+                },
+            },
+            ChannelConfig {
+                channel_id: 3,
+                max_memory_usage_bytes: CHANNEL_MAX_MEMORY_BYTES,
+                send_type: SendType::UnreliableSequenced,
+            },
+            ChannelConfig {
+                channel_id: 4,
+                max_memory_usage_bytes: CHANNEL_MAX_MEMORY_BYTES,
+                send_type: SendType::UnreliableSequenced,
+            },
         ]
     }
 }
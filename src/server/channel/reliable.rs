@@ -1,5 +1,5 @@
 use std::{
-    collections::{btree_map, BTreeMap},
+    collections::{btree_map, BTreeMap, BTreeSet, HashMap},
     time::Duration,
 };
 
@@ -7,22 +7,36 @@ use bytes::Bytes;
 
 use crate::{
     constants::MAX_MESSAGES_LENGTH,
-    server::{error::ChannelError, packet::Packet},
+    server::{
+        error::ChannelError,
+        packet::{Packet, SliceMessage},
+    },
 };
 
+use super::slice::{num_slices, slice_message, slices_memory_usage, SliceConstructor};
+
+/// Memory charged for each message id kept by an unordered channel.
+const RECEIVED_MESSAGE_ID_BYTES: usize = std::mem::size_of::<u64>();
+
 #[derive(Debug)]
 enum UnackedMessage {
     Small {
         message: Bytes,
         last_sent: Option<Duration>,
     },
+    /// Messages longer than [`MAX_MESSAGES_LENGTH`], each slice is acked and resent on its own.
+    Sliced {
+        message: Bytes,
+        num_acked_slices: usize,
+        acked: Vec<bool>,
+        last_sent: Vec<Option<Duration>>,
+    },
 }
 
 #[derive(Debug)]
 pub struct SendChannelReliable {
     channel_id: u8,
     unacked_messages: BTreeMap<u64, UnackedMessage>,
-    next_package_sequence_id: u16,
     next_message_id: u64,
     resend_time: Duration,
     max_memory_usage_bytes: usize,
@@ -32,11 +46,18 @@ pub struct SendChannelReliable {
 #[derive(Debug)]
 enum ReliableOrder {
     Ordered,
+    /// Messages are received as soon as they arrive, the ids received after the oldest pending
+    /// one are kept to discard the messages received again. They count towards the memory
+    /// limit, so a message that never arrives can not make them grow forever.
+    Unordered {
+        received_message_ids: BTreeSet<u64>,
+    },
 }
 
 #[derive(Debug)]
 pub struct ReceiveChannelReliable {
     messages: BTreeMap<u64, Bytes>,
+    slices: HashMap<u64, SliceConstructor>,
     oldest_pending_message_id: u64,
     reliable_order: ReliableOrder,
     memory_usage_bytes: usize,
@@ -48,7 +69,6 @@ impl SendChannelReliable {
         Self {
             channel_id,
             unacked_messages: BTreeMap::new(),
-            next_package_sequence_id: 0,
             next_message_id: 0,
             resend_time,
             max_memory_usage_bytes,
@@ -64,9 +84,12 @@ impl SendChannelReliable {
         size_bytes + self.memory_usage_bytes <= self.max_memory_usage_bytes
     }
 
+    /// Packets are numbered from `next_sequence_id`, shared by the reliable channels of the
+    /// connection since their packets are acked together.
     pub fn get_packets_to_send(
         &mut self,
         available_bytes: &mut u64,
+        next_sequence_id: &mut u16,
         current_time: Duration,
     ) -> Vec<Packet> {
         if self.unacked_messages.is_empty() {
@@ -94,22 +117,21 @@ impl SendChannelReliable {
 
                     *available_bytes -= message.len() as u64;
 
-                    // Generate packet with small messages if you cannot fit
-                    let serialized_size = message.len()
-                        + octets::varint_len(message.len() as u64)
-                        + octets::varint_len(message_id);
+                    // Generate packet with small messages if you cannot fit,
+                    // messages are prefixed with their u64 id and u16 length
+                    let serialized_size = message.len() + 10;
                     if small_messages_bytes + serialized_size > MAX_MESSAGES_LENGTH {
                         packets.push(Packet::SmallReliable {
                             channel_id: self.channel_id,
                             packet_type: 0,
                             packet_process_time: 0,
-                            sequence_id: self.next_package_sequence_id,
+                            sequence_id: *next_sequence_id,
                             acked_seq_id: u16::MAX,
                             acked_mask: 0,
                             messages: std::mem::take(&mut small_messages),
                         });
                         small_messages_bytes = 0;
-                        self.next_package_sequence_id += 1;
+                        *next_sequence_id = next_sequence_id.wrapping_add(1);
                     }
 
                     small_messages_bytes += serialized_size;
@@ -118,6 +140,43 @@ impl SendChannelReliable {
 
                     continue;
                 }
+                UnackedMessage::Sliced {
+                    message,
+                    acked,
+                    last_sent,
+                    ..
+                } => {
+                    for (slice_index, last_sent) in last_sent.iter_mut().enumerate() {
+                        if acked[slice_index] {
+                            continue;
+                        }
+
+                        if let Some(last_sent) = last_sent {
+                            if current_time - *last_sent < self.resend_time {
+                                continue;
+                            }
+                        }
+
+                        let slice = slice_message(message_id, message, slice_index);
+                        if *available_bytes < slice.payload.len() as u64 {
+                            // Skip slice, no bytes available to send this slice
+                            continue;
+                        }
+                        *available_bytes -= slice.payload.len() as u64;
+
+                        packets.push(Packet::ReliableSlice {
+                            channel_id: self.channel_id,
+                            packet_type: 2,
+                            packet_process_time: 0,
+                            sequence_id: *next_sequence_id,
+                            acked_seq_id: u16::MAX,
+                            acked_mask: 0,
+                            slice,
+                        });
+                        *next_sequence_id = next_sequence_id.wrapping_add(1);
+                        *last_sent = Some(current_time);
+                    }
+                }
             }
         }
 
@@ -127,12 +186,12 @@ impl SendChannelReliable {
                 channel_id: self.channel_id,
                 packet_type: 0,
                 packet_process_time: 0,
-                sequence_id: self.next_package_sequence_id,
+                sequence_id: *next_sequence_id,
                 acked_seq_id: u16::MAX,
                 acked_mask: 0,
                 messages: std::mem::take(&mut small_messages),
             });
-            self.next_package_sequence_id += 1;
+            *next_sequence_id = next_sequence_id.wrapping_add(1);
         }
         packets
     }
@@ -143,9 +202,19 @@ impl SendChannelReliable {
         }
 
         self.memory_usage_bytes += message.len();
-        let unacked_message = UnackedMessage::Small {
-            message,
-            last_sent: None,
+        let unacked_message = if message.len() > MAX_MESSAGES_LENGTH {
+            let num_slices = num_slices(message.len());
+            UnackedMessage::Sliced {
+                message,
+                num_acked_slices: 0,
+                acked: vec![false; num_slices],
+                last_sent: vec![None; num_slices],
+            }
+        } else {
+            UnackedMessage::Small {
+                message,
+                last_sent: None,
+            }
         };
 
         self.unacked_messages
@@ -156,14 +225,38 @@ impl SendChannelReliable {
     }
 
     pub fn process_message_ack(&mut self, message_id: u64) {
-        if self.unacked_messages.contains_key(&message_id) {
-            tracing::trace!("MESSAGE ID: {:?} IS ACKEDD!!!", message_id);
-            let unacked_message = self.unacked_messages.remove(&message_id).unwrap();
-            let UnackedMessage::Small {
+        if let btree_map::Entry::Occupied(entry) = self.unacked_messages.entry(message_id) {
+            if let UnackedMessage::Small {
                 message: payload, ..
-            } = unacked_message;
+            } = entry.get()
+            {
+                tracing::trace!("MESSAGE ID: {:?} IS ACKEDD!!!", message_id);
+                self.memory_usage_bytes -= payload.len();
+                entry.remove();
+            }
+        }
+    }
+
+    pub fn process_slice_message_ack(&mut self, message_id: u64, slice_index: usize) {
+        let Some(UnackedMessage::Sliced {
+            message,
+            num_acked_slices,
+            acked,
+            ..
+        }) = self.unacked_messages.get_mut(&message_id)
+        else {
+            return;
+        };
+
+        if slice_index >= acked.len() || acked[slice_index] {
+            return;
+        }
+        acked[slice_index] = true;
+        *num_acked_slices += 1;
 
-            self.memory_usage_bytes -= payload.len();
+        if *num_acked_slices == acked.len() {
+            self.memory_usage_bytes -= message.len();
+            self.unacked_messages.remove(&message_id);
         }
     }
 }
@@ -173,12 +266,13 @@ impl ReceiveChannelReliable {
         let ordered = ordered.unwrap_or(true);
         let reliable_order = match ordered {
             true => ReliableOrder::Ordered,
-            false => {
-                unreachable!("Reliable unordered is not supported yet");
-            }
+            false => ReliableOrder::Unordered {
+                received_message_ids: BTreeSet::new(),
+            },
         };
         Self {
             messages: BTreeMap::new(),
+            slices: HashMap::new(),
             oldest_pending_message_id: 0,
             reliable_order,
             memory_usage_bytes: 0,
@@ -203,6 +297,59 @@ impl ReceiveChannelReliable {
                     entry.insert(message);
                 }
             }
+            ReliableOrder::Unordered {
+                received_message_ids,
+            } => {
+                if received_message_ids.contains(&message_id) {
+                    return Ok(());
+                }
+                let memory_usage = message.len() + RECEIVED_MESSAGE_ID_BYTES;
+                if self.memory_usage_bytes + memory_usage > self.max_memory_usage_bytes {
+                    return Err(ChannelError::ReliableChannelMaxMemoryReached);
+                }
+                self.memory_usage_bytes += memory_usage;
+
+                received_message_ids.insert(message_id);
+                self.messages.insert(message_id, message);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_received(&self, message_id: u64) -> bool {
+        message_id < self.oldest_pending_message_id
+            || match &self.reliable_order {
+                ReliableOrder::Ordered => self.messages.contains_key(&message_id),
+                ReliableOrder::Unordered {
+                    received_message_ids,
+                } => received_message_ids.contains(&message_id),
+            }
+    }
+
+    pub fn process_slice(&mut self, slice: SliceMessage) -> Result<(), ChannelError> {
+        let message_id = slice.message_id;
+        if self.is_received(message_id) {
+            // Discard slice of a message already received
+            return Ok(());
+        }
+
+        if !self.slices.contains_key(&message_id) {
+            let memory_usage = slices_memory_usage(slice.num_slices);
+            if self.memory_usage_bytes.saturating_add(memory_usage) > self.max_memory_usage_bytes {
+                return Err(ChannelError::ReliableChannelMaxMemoryReached);
+            }
+
+            self.memory_usage_bytes += memory_usage;
+            let constructor = SliceConstructor::new(message_id, slice.num_slices);
+            self.slices.insert(message_id, constructor);
+        }
+
+        let constructor = self.slices.get_mut(&message_id).unwrap();
+        if let Some(message) = constructor.process_slice(&slice)? {
+            let constructor = self.slices.remove(&message_id).unwrap();
+            self.memory_usage_bytes -= constructor.memory_usage();
+            self.process_message(message, message_id)?;
         }
 
         Ok(())
@@ -219,6 +366,50 @@ impl ReceiveChannelReliable {
                 self.memory_usage_bytes -= message.len();
                 Some(message)
             }
+            ReliableOrder::Unordered {
+                received_message_ids,
+            } => {
+                let (message_id, message) = self.messages.pop_first()?;
+
+                if self.oldest_pending_message_id == message_id {
+                    // Skip the messages received out of order, until one that was not received
+                    while received_message_ids.remove(&self.oldest_pending_message_id) {
+                        self.oldest_pending_message_id += 1;
+                        self.memory_usage_bytes -= RECEIVED_MESSAGE_ID_BYTES;
+                    }
+                }
+
+                self.memory_usage_bytes -= message.len();
+                Some(message)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unordered_ids_count_towards_the_memory_limit() {
+        let mut channel = ReceiveChannelReliable::new(48, Some(false));
+
+        // Message 0 is lost, the ids received after it are kept until it arrives
+        for message_id in 1..=4 {
+            channel
+                .process_message(Bytes::from(vec![0]), message_id)
+                .unwrap();
+            assert!(channel.receive_message().is_some());
         }
+        assert_eq!(channel.memory_usage_bytes, 4 * RECEIVED_MESSAGE_ID_BYTES);
+        assert_eq!(
+            channel.process_message(Bytes::from(vec![0; 9]), 5),
+            Err(ChannelError::ReliableChannelMaxMemoryReached)
+        );
+
+        channel.process_message(Bytes::from(vec![0]), 0).unwrap();
+        assert!(channel.receive_message().is_some());
+        assert_eq!(channel.memory_usage_bytes, 0);
+        assert_eq!(channel.oldest_pending_message_id, 5);
     }
 }
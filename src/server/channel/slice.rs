@@ -0,0 +1,132 @@
+use bytes::Bytes;
+
+use crate::{
+    constants::SLICE_SIZE,
+    server::{error::ChannelError, packet::SliceMessage},
+};
+
+/// Number of slices needed to send a message of `len` bytes.
+pub fn num_slices(len: usize) -> usize {
+    len.div_ceil(SLICE_SIZE)
+}
+
+/// Returns the slice `slice_index` of the message, the last slice may be shorter than [`SLICE_SIZE`].
+pub fn slice_message(message_id: u64, message: &Bytes, slice_index: usize) -> SliceMessage {
+    let start = slice_index * SLICE_SIZE;
+    let end = (start + SLICE_SIZE).min(message.len());
+
+    SliceMessage {
+        message_id,
+        slice_index: slice_index as u32,
+        num_slices: num_slices(message.len()) as u32,
+        payload: message.slice(start..end),
+    }
+}
+
+/// Bytes held while receiving a message of `num_slices` slices, saturating so a forged slice
+/// count can never overflow a memory check.
+pub fn slices_memory_usage(num_slices: u32) -> usize {
+    (num_slices as usize).saturating_mul(SLICE_SIZE)
+}
+
+/// Puts a sliced message back together as its slices arrive, in any order.
+#[derive(Debug)]
+pub struct SliceConstructor {
+    message_id: u64,
+    num_slices: usize,
+    num_received_slices: usize,
+    received: Vec<bool>,
+    sliced_data: Vec<u8>,
+    /// Length of the message, known once the last slice arrived.
+    len: usize,
+}
+
+impl SliceConstructor {
+    /// The memory used, see [`slices_memory_usage`], must be checked before creating it.
+    pub fn new(message_id: u64, num_slices: u32) -> Self {
+        Self {
+            message_id,
+            num_slices: num_slices as usize,
+            num_received_slices: 0,
+            received: vec![false; num_slices as usize],
+            sliced_data: vec![0; slices_memory_usage(num_slices)],
+            len: 0,
+        }
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.num_slices * SLICE_SIZE
+    }
+
+    /// Returns the message once every slice was received. Slices received twice are ignored.
+    pub fn process_slice(&mut self, slice: &SliceMessage) -> Result<Option<Bytes>, ChannelError> {
+        let slice_index = slice.slice_index as usize;
+        let is_last_slice = slice_index + 1 == self.num_slices;
+        let valid_len = match is_last_slice {
+            true => !slice.payload.is_empty() && slice.payload.len() <= SLICE_SIZE,
+            false => slice.payload.len() == SLICE_SIZE,
+        };
+        if slice.message_id != self.message_id
+            || slice.num_slices as usize != self.num_slices
+            || slice_index >= self.num_slices
+            || !valid_len
+        {
+            tracing::warn!(
+                "Invalid slice {} of message {}, expected {} slices",
+                slice.slice_index,
+                slice.message_id,
+                self.num_slices
+            );
+            return Err(ChannelError::InvalidSliceMessage);
+        }
+
+        if !self.received[slice_index] {
+            self.received[slice_index] = true;
+            self.num_received_slices += 1;
+
+            let start = slice_index * SLICE_SIZE;
+            self.sliced_data[start..start + slice.payload.len()].copy_from_slice(&slice.payload);
+            if is_last_slice {
+                self.len = start + slice.payload.len();
+            }
+        }
+
+        if self.num_received_slices == self.num_slices {
+            let mut message = std::mem::take(&mut self.sliced_data);
+            message.truncate(self.len);
+            return Ok(Some(message.into()));
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reassembles_slices_in_any_order() {
+        let message: Bytes = (0..SLICE_SIZE * 2 + 10).map(|i| i as u8).collect();
+        let slices: Vec<SliceMessage> = (0..num_slices(message.len()))
+            .map(|index| slice_message(7, &message, index))
+            .collect();
+        assert_eq!(slices.len(), 3);
+
+        let mut constructor = SliceConstructor::new(7, 3);
+        assert_eq!(constructor.process_slice(&slices[2]), Ok(None));
+        assert_eq!(constructor.process_slice(&slices[0]), Ok(None));
+        assert_eq!(constructor.process_slice(&slices[0]), Ok(None));
+        assert_eq!(constructor.process_slice(&slices[1]), Ok(Some(message)));
+
+        let mut constructor = SliceConstructor::new(7, 3);
+        let short_slice = SliceMessage {
+            payload: slices[0].payload.slice(..10),
+            ..slices[0].clone()
+        };
+        assert_eq!(
+            constructor.process_slice(&short_slice),
+            Err(ChannelError::InvalidSliceMessage)
+        );
+    }
+}
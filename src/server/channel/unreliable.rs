@@ -1,13 +1,28 @@
-use std::collections::VecDeque;
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
 
 use bytes::Bytes;
 
-use crate::{constants::MAX_MESSAGES_LENGTH, server::packet::Packet};
+use crate::{
+    constants::{MAX_MESSAGES_LENGTH, SLICE_DISCARD_AFTER},
+    server::{
+        error::ChannelError,
+        packet::{Packet, SliceMessage},
+    },
+};
+
+use super::slice::{num_slices, slice_message, slices_memory_usage, SliceConstructor};
 
 #[derive(Debug)]
 pub struct SendChannelUnreliable {
     channel_id: u8,
+    /// Numbers every message, so the receiver can drop stale ones, when set.
+    sequenced: bool,
     unreliable_messages: VecDeque<Bytes>,
+    /// Id of the next sequenced or sliced message.
+    next_message_id: u64,
     max_memory_usage_bytes: usize,
     memory_usage_bytes: usize,
 }
@@ -15,16 +30,23 @@ pub struct SendChannelUnreliable {
 #[derive(Debug)]
 pub struct ReceiveChannelUnreliable {
     channel_id: u8,
+    /// Drops messages older than the newest one delivered, when set.
+    sequenced: bool,
+    newest_message_id: Option<u16>,
     messages: VecDeque<Bytes>,
+    /// Sliced messages being received, with when their last slice arrived.
+    slices: HashMap<u64, (SliceConstructor, Duration)>,
     max_memory_usage_bytes: usize,
     memory_usage_bytes: usize,
 }
 
 impl SendChannelUnreliable {
-    pub fn new(channel_id: u8, max_memory_usage_bytes: usize) -> Self {
+    pub fn new(channel_id: u8, max_memory_usage_bytes: usize, sequenced: bool) -> Self {
         Self {
             channel_id,
+            sequenced,
             unreliable_messages: VecDeque::new(),
+            next_message_id: 0,
             max_memory_usage_bytes,
             memory_usage_bytes: 0,
         }
@@ -40,7 +62,7 @@ impl SendChannelUnreliable {
 
     pub fn get_packets_to_send(&mut self, available_bytes: &mut u64) -> Vec<Packet> {
         let mut packets: Vec<Packet> = vec![];
-        let mut small_messages: Vec<Bytes> = vec![];
+        let mut small_messages: Vec<(u64, Bytes)> = vec![];
         let mut small_messages_bytes = 0;
 
         while let Some(message) = self.unreliable_messages.pop_front() {
@@ -52,30 +74,65 @@ impl SendChannelUnreliable {
 
             *available_bytes -= message.len() as u64;
 
-            let serialized_size = message.len() + octets::varint_len(message.len() as u64);
+            let message_id = self.next_message_id;
+            if message.len() > MAX_MESSAGES_LENGTH {
+                for slice_index in 0..num_slices(message.len()) {
+                    packets.push(Packet::UnreliableSlice {
+                        channel_id: self.channel_id,
+                        acked_seq_id: 0,
+                        acked_mask: 0,
+                        slice: slice_message(message_id, &message, slice_index),
+                    });
+                }
+                self.next_message_id += 1;
+                continue;
+            }
+
+            // Messages are prefixed with their u16 length, and their u16 id when sequenced
+            let serialized_size = match self.sequenced {
+                true => message.len() + 4,
+                false => message.len() + 2,
+            };
             if small_messages_bytes + serialized_size > MAX_MESSAGES_LENGTH {
-                packets.push(Packet::SmallUnreliable {
-                    channel_id: self.channel_id,
-                    messages: std::mem::take(&mut small_messages),
-                });
+                packets.push(self.small_messages_packet(std::mem::take(&mut small_messages)));
                 small_messages_bytes = 0;
             }
 
+            if self.sequenced {
+                self.next_message_id += 1;
+            }
             small_messages_bytes += serialized_size;
-            small_messages.push(message);
+            small_messages.push((message_id, message));
         }
 
         // Generate final packet for remaining small messages
         if !small_messages.is_empty() {
-            packets.push(Packet::SmallUnreliable {
-                channel_id: self.channel_id,
-                messages: std::mem::take(&mut small_messages),
-            });
+            packets.push(self.small_messages_packet(small_messages));
         }
 
         packets
     }
 
+    fn small_messages_packet(&self, messages: Vec<(u64, Bytes)>) -> Packet {
+        match self.sequenced {
+            true => Packet::SmallUnreliableSequenced {
+                channel_id: self.channel_id,
+                acked_seq_id: 0,
+                acked_mask: 0,
+                messages: messages
+                    .into_iter()
+                    .map(|(message_id, message)| (message_id as u16, message))
+                    .collect(),
+            },
+            false => Packet::SmallUnreliable {
+                channel_id: self.channel_id,
+                acked_seq_id: 0,
+                acked_mask: 0,
+                messages: messages.into_iter().map(|(_, message)| message).collect(),
+            },
+        }
+    }
+
     pub fn send_message(&mut self, message: Bytes) {
         if self.memory_usage_bytes + message.len() > self.max_memory_usage_bytes {
             tracing::warn!(
@@ -85,22 +142,19 @@ impl SendChannelUnreliable {
             return;
         }
 
-        let message_size = message.len();
-        if message_size > MAX_MESSAGES_LENGTH {
-            tracing::error!(
-                "Sending a message that is longer than {MAX_MESSAGES_LENGTH} is prohibited. Attempted message size: {message_size} ");
-        }
-
         self.memory_usage_bytes += message.len();
         self.unreliable_messages.push_back(message);
     }
 }
 
 impl ReceiveChannelUnreliable {
-    pub fn new(channel_id: u8, max_memory_usage_bytes: usize) -> Self {
+    pub fn new(channel_id: u8, max_memory_usage_bytes: usize, sequenced: bool) -> Self {
         Self {
             channel_id,
+            sequenced,
+            newest_message_id: None,
             messages: VecDeque::new(),
+            slices: HashMap::new(),
             memory_usage_bytes: 0,
             max_memory_usage_bytes,
         }
@@ -119,6 +173,74 @@ impl ReceiveChannelUnreliable {
         self.messages.push_back(message);
     }
 
+    /// Sequenced channels drop the messages older than the newest one delivered. Ids are
+    /// compared on 16 bits, wrapping around.
+    pub fn process_sequenced_message(&mut self, message_id: u16, message: Bytes) {
+        if self.sequenced {
+            if self.is_stale(message_id) {
+                return;
+            }
+            self.newest_message_id = Some(message_id);
+        }
+
+        self.process_message(message);
+    }
+
+    fn is_stale(&self, message_id: u16) -> bool {
+        self.sequenced
+            && self
+                .newest_message_id
+                .is_some_and(|newest| message_id.wrapping_sub(newest) as i16 <= 0)
+    }
+
+    pub fn process_slice(
+        &mut self,
+        slice: SliceMessage,
+        current_time: Duration,
+    ) -> Result<(), ChannelError> {
+        let message_id = slice.message_id;
+        if self.is_stale(message_id as u16) {
+            return Ok(());
+        }
+
+        if !self.slices.contains_key(&message_id) {
+            let memory_usage = slices_memory_usage(slice.num_slices);
+            if self.memory_usage_bytes.saturating_add(memory_usage) > self.max_memory_usage_bytes {
+                tracing::warn!(
+                    "dropped unreliable slice received because channel {} is memory limited",
+                    self.channel_id
+                );
+                return Ok(());
+            }
+
+            self.memory_usage_bytes += memory_usage;
+            let constructor = SliceConstructor::new(message_id, slice.num_slices);
+            self.slices.insert(message_id, (constructor, current_time));
+        }
+
+        let (constructor, last_received) = self.slices.get_mut(&message_id).unwrap();
+        *last_received = current_time;
+        if let Some(message) = constructor.process_slice(&slice)? {
+            let (constructor, _) = self.slices.remove(&message_id).unwrap();
+            self.memory_usage_bytes -= constructor.memory_usage();
+            self.process_sequenced_message(message_id as u16, message);
+        }
+
+        Ok(())
+    }
+
+    /// Drops the sliced messages that stopped receiving slices, their missing slices were lost.
+    pub fn discard_incomplete_old_slices(&mut self, current_time: Duration) {
+        let memory_usage_bytes = &mut self.memory_usage_bytes;
+        self.slices.retain(|_, (constructor, last_received)| {
+            let discard = current_time.saturating_sub(*last_received) >= SLICE_DISCARD_AFTER;
+            if discard {
+                *memory_usage_bytes -= constructor.memory_usage();
+            }
+            !discard
+        });
+    }
+
     pub fn receive_message(&mut self) -> Option<Bytes> {
         if let Some(message) = self.messages.pop_front() {
             self.memory_usage_bytes -= message.len();
@@ -1,8 +1,11 @@
 use bytes::Bytes;
+use serde::Serialize;
 
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::time::{Duration, Instant};
 
+use crate::constants::CONNECTION_AVAILABLE_BYTES_PER_TICK;
+
 use super::channel::reliable::{ReceiveChannelReliable, SendChannelReliable};
 use super::channel::unreliable::{ReceiveChannelUnreliable, SendChannelUnreliable};
 use super::channel::{ChannelConfig, DefaultChannel, SendType};
@@ -16,14 +19,14 @@ pub struct ConnectionConfig {
     /// Default: 60_000, at 60hz this is becomes 28.8 Mbps
     pub available_bytes_per_tick: u64,
     /// The channels that the server sends to the client.
-    /// The order of the channels in this Vec determines which channel has priority when generating packets.
-    /// Each tick, the first channel can consume up to `available_bytes_per_tick`,
-    /// used bytes are removed from it and passed to the next channel
+    /// Reliable channels have priority over unreliable ones when generating packets, then the order
+    /// of the channels in this Vec does. Each tick, the first channel can consume up to
+    /// `available_bytes_per_tick`, used bytes are removed from it and passed to the next channel
     pub server_channels_config: Vec<ChannelConfig>,
     /// The channels that the client sends to the server.
-    /// The order of the channels in this Vec determines which channel has priority when generating packets.
-    /// Each tick, the first channel can consume up to `available_bytes_per_tick`,
-    /// used bytes are removed from it and passed to the next channel
+    /// Reliable channels have priority over unreliable ones when generating packets, then the order
+    /// of the channels in this Vec does. Each tick, the first channel can consume up to
+    /// `available_bytes_per_tick`, used bytes are removed from it and passed to the next channel
     pub client_channels_config: Vec<ChannelConfig>,
 }
 
@@ -41,6 +44,11 @@ enum PacketSentInfo {
         channel_id: u8,
         message_ids: Vec<u64>,
     },
+    ReliableSliceMessage {
+        channel_id: u8,
+        message_id: u64,
+        slice_index: usize,
+    },
 }
 
 #[derive(Debug)]
@@ -51,6 +59,7 @@ enum ChannelOrder {
 
 #[allow(dead_code)]
 /// Describes the stats of a connection.
+#[derive(Debug, Clone, Copy, Serialize)]
 pub struct NetworkInfo {
     /// Round-trip Time
     pub rtt: f64,
@@ -74,10 +83,12 @@ pub struct UnityClient {
     new_ack_to_send: bool,
     ack_process_start_instant: Instant,
     channel_send_order: Vec<ChannelOrder>,
-    send_unreliable_channel: SendChannelUnreliable,
-    receive_unreliable_channel: ReceiveChannelUnreliable,
-    send_reliable_channel: SendChannelReliable,
-    receive_reliable_channel: ReceiveChannelReliable,
+    send_unreliable_channels: HashMap<u8, SendChannelUnreliable>,
+    receive_unreliable_channels: HashMap<u8, ReceiveChannelUnreliable>,
+    send_reliable_channels: HashMap<u8, SendChannelReliable>,
+    receive_reliable_channels: HashMap<u8, ReceiveChannelReliable>,
+    /// Numbers the packets of every reliable channel, they are acked together.
+    next_packet_sequence_id: u16,
     stats: ConnectionStats,
     available_bytes_per_tick: u64,
     connection_status: ClientConnectionStatus,
@@ -88,8 +99,7 @@ pub struct UnityClient {
 impl Default for ConnectionConfig {
     fn default() -> Self {
         Self {
-            // At 60hz this is becomes 28.8 Mbps
-            available_bytes_per_tick: 60_000,
+            available_bytes_per_tick: CONNECTION_AVAILABLE_BYTES_PER_TICK,
             server_channels_config: DefaultChannel::config(),
             client_channels_config: DefaultChannel::config(),
         }
@@ -100,10 +110,8 @@ impl UnityClient {
     pub fn new(config: ConnectionConfig) -> Self {
         Self::from_channels(
             config.available_bytes_per_tick,
-            config.server_channels_config[0].clone(),
-            config.server_channels_config[1].clone(),
-            config.client_channels_config[0].clone(),
-            config.client_channels_config[1].clone(),
+            &config.client_channels_config,
+            &config.server_channels_config,
         )
     }
 
@@ -112,59 +120,86 @@ impl UnityClient {
     pub(crate) fn new_from_server(config: ConnectionConfig) -> Self {
         Self::from_channels(
             config.available_bytes_per_tick,
-            config.server_channels_config[0].clone(),
-            config.server_channels_config[1].clone(),
-            config.client_channels_config[0].clone(),
-            config.client_channels_config[1].clone(),
+            &config.server_channels_config,
+            &config.client_channels_config,
         )
     }
 
+    /// Panics when a channel id is repeated in a list.
     fn from_channels(
         available_bytes_per_tick: u64,
-        send_unreliable_channel_config: ChannelConfig,
-        send_reliable_channel_config: ChannelConfig,
-        receive_unreliable_channel_config: ChannelConfig,
-        receive_reliable_channel_config: ChannelConfig,
+        send_channels_config: &[ChannelConfig],
+        receive_channels_config: &[ChannelConfig],
     ) -> Self {
-        let send_unreliable_channel = SendChannelUnreliable::new(
-            send_unreliable_channel_config.channel_id,
-            send_unreliable_channel_config.max_memory_usage_bytes,
-        );
-
-        let send_reliable_resend_time;
-        match send_reliable_channel_config.send_type {
-            SendType::ReliableOrdered { resend_time } => {
-                send_reliable_resend_time = resend_time;
+        let check_ids = |channels: &[ChannelConfig]| {
+            let mut ids = HashSet::new();
+            for channel in channels {
+                assert!(
+                    ids.insert(channel.channel_id),
+                    "Found channel with duplicate id {}",
+                    channel.channel_id
+                );
             }
-            _ => {
-                unreachable!("Shouldn't come here for send_reliable_channel_config.send_type")
+        };
+        check_ids(send_channels_config);
+        check_ids(receive_channels_config);
+
+        let mut channel_send_order = Vec::with_capacity(send_channels_config.len());
+        let mut send_unreliable_channels = HashMap::new();
+        let mut send_reliable_channels = HashMap::new();
+        for channel_config in send_channels_config {
+            let channel_id = channel_config.channel_id;
+            match channel_config.send_type {
+                SendType::Unreliable | SendType::UnreliableSequenced => {
+                    let channel = SendChannelUnreliable::new(
+                        channel_id,
+                        channel_config.max_memory_usage_bytes,
+                        matches!(channel_config.send_type, SendType::UnreliableSequenced),
+                    );
+                    send_unreliable_channels.insert(channel_id, channel);
+                    channel_send_order.push(ChannelOrder::Unreliable(channel_id));
+                }
+                SendType::ReliableOrdered { resend_time }
+                | SendType::ReliableUnordered { resend_time } => {
+                    let channel = SendChannelReliable::new(
+                        channel_id,
+                        resend_time,
+                        channel_config.max_memory_usage_bytes,
+                    );
+                    send_reliable_channels.insert(channel_id, channel);
+                    channel_send_order.push(ChannelOrder::Reliable(channel_id));
+                }
             }
         }
+        // Unreliable traffic must not starve reliable channels, the sort keeps the config order
+        channel_send_order.sort_by_key(|order| matches!(order, ChannelOrder::Unreliable(_)));
 
-        let send_reliable_channel = SendChannelReliable::new(
-            send_reliable_channel_config.channel_id,
-            send_reliable_resend_time,
-            send_reliable_channel_config.max_memory_usage_bytes,
-        );
-
-        let mut channel_send_order: Vec<ChannelOrder> = Vec::with_capacity(2);
-
-        channel_send_order.push(ChannelOrder::Reliable(
-            send_reliable_channel_config.channel_id,
-        ));
-        channel_send_order.push(ChannelOrder::Unreliable(
-            send_unreliable_channel_config.channel_id,
-        ));
-
-        let receive_unreliable_channel = ReceiveChannelUnreliable::new(
-            receive_unreliable_channel_config.channel_id,
-            receive_unreliable_channel_config.max_memory_usage_bytes,
-        );
-
-        let receive_reliable_channel = ReceiveChannelReliable::new(
-            receive_reliable_channel_config.max_memory_usage_bytes,
-            Some(true),
-        );
+        let mut receive_unreliable_channels = HashMap::new();
+        let mut receive_reliable_channels = HashMap::new();
+        for channel_config in receive_channels_config {
+            let channel_id = channel_config.channel_id;
+            let max_memory_usage_bytes = channel_config.max_memory_usage_bytes;
+            match channel_config.send_type {
+                SendType::Unreliable => {
+                    let channel =
+                        ReceiveChannelUnreliable::new(channel_id, max_memory_usage_bytes, false);
+                    receive_unreliable_channels.insert(channel_id, channel);
+                }
+                SendType::UnreliableSequenced => {
+                    let channel =
+                        ReceiveChannelUnreliable::new(channel_id, max_memory_usage_bytes, true);
+                    receive_unreliable_channels.insert(channel_id, channel);
+                }
+                SendType::ReliableOrdered { .. } => {
+                    let channel = ReceiveChannelReliable::new(max_memory_usage_bytes, Some(true));
+                    receive_reliable_channels.insert(channel_id, channel);
+                }
+                SendType::ReliableUnordered { .. } => {
+                    let channel = ReceiveChannelReliable::new(max_memory_usage_bytes, Some(false));
+                    receive_reliable_channels.insert(channel_id, channel);
+                }
+            }
+        }
 
         Self {
             current_time: Duration::ZERO,
@@ -173,10 +208,11 @@ impl UnityClient {
             new_ack_to_send: false,
             ack_process_start_instant: Instant::now(),
             channel_send_order,
-            send_unreliable_channel,
-            receive_unreliable_channel,
-            send_reliable_channel,
-            receive_reliable_channel,
+            send_unreliable_channels,
+            receive_unreliable_channels,
+            send_reliable_channels,
+            receive_reliable_channels,
+            next_packet_sequence_id: 0,
             stats: ConnectionStats::new(),
             rtt: 0.0,
             available_bytes_per_tick,
@@ -296,20 +332,24 @@ impl UnityClient {
     /// Returns the available memory in bytes for the given channel.
     pub fn channel_available_memory<I: Into<u8>>(&self, channel_id: I) -> usize {
         let channel_id = channel_id.into();
-        match channel_id {
-            0 => self.send_unreliable_channel.available_memory(),
-            1 => self.send_reliable_channel.available_memory(),
-            _ => panic!("Called 'channel_available_memory' with invalid channel {channel_id}"),
+        if let Some(channel) = self.send_unreliable_channels.get(&channel_id) {
+            channel.available_memory()
+        } else if let Some(channel) = self.send_reliable_channels.get(&channel_id) {
+            channel.available_memory()
+        } else {
+            panic!("Called 'channel_available_memory' with invalid channel {channel_id}");
         }
     }
 
     /// Checks if the channel can send a message with the given size in bytes.
     pub fn can_send_message<I: Into<u8>>(&self, channel_id: I, size_bytes: usize) -> bool {
         let channel_id = channel_id.into();
-        match channel_id {
-            0 => self.send_unreliable_channel.can_send_message(size_bytes),
-            1 => self.send_reliable_channel.can_send_message(size_bytes),
-            _ => panic!("Called 'can_send_message' with invalid channel {channel_id}"),
+        if let Some(channel) = self.send_unreliable_channels.get(&channel_id) {
+            channel.can_send_message(size_bytes)
+        } else if let Some(channel) = self.send_reliable_channels.get(&channel_id) {
+            channel.can_send_message(size_bytes)
+        } else {
+            panic!("Called 'can_send_message' with invalid channel {channel_id}");
         }
     }
 
@@ -320,21 +360,17 @@ impl UnityClient {
         }
 
         let channel_id = channel_id.into();
-        match channel_id {
-            0 => {
-                self.send_unreliable_channel.send_message(message.into());
-            }
-            1 => {
-                if let Err(error) = self.send_reliable_channel.send_message(message.into()) {
-                    self.disconnect_with_reason(DisconnectReason::SendChannelError {
-                        channel_id,
-                        error,
-                    });
-                }
-            }
-            _ => {
-                panic!("Called 'send_message' with invalid channel {channel_id}");
+        if let Some(channel) = self.send_unreliable_channels.get_mut(&channel_id) {
+            channel.send_message(message.into());
+        } else if let Some(channel) = self.send_reliable_channels.get_mut(&channel_id) {
+            if let Err(error) = channel.send_message(message.into()) {
+                self.disconnect_with_reason(DisconnectReason::SendChannelError {
+                    channel_id,
+                    error,
+                });
             }
+        } else {
+            panic!("Called 'send_message' with invalid channel {channel_id}");
         }
     }
 
@@ -345,12 +381,12 @@ impl UnityClient {
         }
 
         let channel_id = channel_id.into();
-
-        let channel_id = channel_id.into();
-        match channel_id {
-            0 => self.receive_unreliable_channel.receive_message(),
-            1 => self.receive_reliable_channel.receive_message(),
-            _ => panic!("Called 'receive_message' with invalid channel {channel_id}"),
+        if let Some(channel) = self.receive_unreliable_channels.get_mut(&channel_id) {
+            channel.receive_message()
+        } else if let Some(channel) = self.receive_reliable_channels.get_mut(&channel_id) {
+            channel.receive_message()
+        } else {
+            panic!("Called 'receive_message' with invalid channel {channel_id}");
         }
     }
 
@@ -376,6 +412,10 @@ impl UnityClient {
         for sequence in lost_packets.iter() {
             self.sent_packets.remove(sequence);
         }
+
+        for channel in self.receive_unreliable_channels.values_mut() {
+            channel.discard_incomplete_old_slices(self.current_time);
+        }
     }
 
     /// Process a packet received from the server.
@@ -396,6 +436,9 @@ impl UnityClient {
             Ok(packet) => packet,
         };
 
+        let (acked_seq_id, acked_mask) = packet.acks();
+        self.process_acks(acked_seq_id, acked_mask);
+
         match packet {
             Packet::SmallReliable {
                 channel_id,
@@ -404,11 +447,14 @@ impl UnityClient {
                 ..
             } => {
                 self.add_pending_ack(sequence_id);
+                let Some(channel) = self.receive_reliable_channels.get_mut(&channel_id) else {
+                    self.disconnect_with_reason(DisconnectReason::ReceivedInvalidChannelId(
+                        channel_id,
+                    ));
+                    return;
+                };
                 for (message_id, message) in messages {
-                    if let Err(error) = self
-                        .receive_reliable_channel
-                        .process_message(message, message_id)
-                    {
+                    if let Err(error) = channel.process_message(message, message_id) {
                         self.disconnect_with_reason(DisconnectReason::ReceiveChannelError {
                             channel_id,
                             error,
@@ -417,46 +463,117 @@ impl UnityClient {
                     }
                 }
             }
-            Packet::SmallUnreliable { messages, .. } => {
+            Packet::SmallUnreliable {
+                channel_id,
+                messages,
+                ..
+            } => {
+                let Some(channel) = self.receive_unreliable_channels.get_mut(&channel_id) else {
+                    self.disconnect_with_reason(DisconnectReason::ReceivedInvalidChannelId(
+                        channel_id,
+                    ));
+                    return;
+                };
                 for message in messages {
-                    self.receive_unreliable_channel.process_message(message);
+                    channel.process_message(message);
                 }
             }
-
-            Packet::Ack {
-                acked_seq_id,
-                acked_mask,
+            Packet::SmallUnreliableSequenced {
+                channel_id,
+                messages,
                 ..
             } => {
-                // Create list with just new acks
-                // This prevents DoS from huge ack ranges
-                let new_acks = Self::get_acked_packet_ids(acked_seq_id, acked_mask);
-
-                for packet_sequence in new_acks {
-                    if let Some(sent_packet) = self.sent_packets.remove(&packet_sequence) {
-                        self.stats
-                            .acked_packet(sent_packet.sent_at, self.current_time);
-
-                        // Update rtt
-                        let rtt = (self.current_time - sent_packet.sent_at).as_secs_f64();
-                        if self.rtt < f64::EPSILON {
-                            self.rtt = rtt;
-                        } else {
-                            self.rtt = self.rtt * 0.875 + rtt * 0.125;
-                        }
+                let Some(channel) = self.receive_unreliable_channels.get_mut(&channel_id) else {
+                    self.disconnect_with_reason(DisconnectReason::ReceivedInvalidChannelId(
+                        channel_id,
+                    ));
+                    return;
+                };
+                for (message_id, message) in messages {
+                    channel.process_sequenced_message(message_id, message);
+                }
+            }
+            Packet::ReliableSlice {
+                channel_id,
+                sequence_id,
+                slice,
+                ..
+            } => {
+                self.add_pending_ack(sequence_id);
+                let Some(channel) = self.receive_reliable_channels.get_mut(&channel_id) else {
+                    self.disconnect_with_reason(DisconnectReason::ReceivedInvalidChannelId(
+                        channel_id,
+                    ));
+                    return;
+                };
+                if let Err(error) = channel.process_slice(slice) {
+                    self.disconnect_with_reason(DisconnectReason::ReceiveChannelError {
+                        channel_id,
+                        error,
+                    });
+                }
+            }
+            Packet::UnreliableSlice {
+                channel_id, slice, ..
+            } => {
+                let Some(channel) = self.receive_unreliable_channels.get_mut(&channel_id) else {
+                    self.disconnect_with_reason(DisconnectReason::ReceivedInvalidChannelId(
+                        channel_id,
+                    ));
+                    return;
+                };
+                if let Err(error) = channel.process_slice(slice, self.current_time) {
+                    self.disconnect_with_reason(DisconnectReason::ReceiveChannelError {
+                        channel_id,
+                        error,
+                    });
+                }
+            }
 
-                        match sent_packet.info {
-                            PacketSentInfo::ReliableMessages {
-                                channel_id: _,
-                                message_ids,
-                            } => {
-                                for message_id in message_ids {
-                                    self.send_reliable_channel.process_message_ack(message_id);
-                                }
+            Packet::Ack { .. } => {}
+        }
+    }
+
+    /// Processes the acks carried by a received packet, piggy-backed or in a [`Packet::Ack`].
+    fn process_acks(&mut self, acked_seq_id: u16, acked_mask: u32) {
+        // Create list with just new acks
+        // This prevents DoS from huge ack ranges
+        let new_acks = Self::get_acked_packet_ids(acked_seq_id, acked_mask);
+
+        for packet_sequence in new_acks {
+            if let Some(sent_packet) = self.sent_packets.remove(&packet_sequence) {
+                self.stats
+                    .acked_packet(sent_packet.sent_at, self.current_time);
+
+                // Update rtt
+                let rtt = (self.current_time - sent_packet.sent_at).as_secs_f64();
+                if self.rtt < f64::EPSILON {
+                    self.rtt = rtt;
+                } else {
+                    self.rtt = self.rtt * 0.875 + rtt * 0.125;
+                }
+
+                match sent_packet.info {
+                    PacketSentInfo::ReliableMessages {
+                        channel_id,
+                        message_ids,
+                    } => {
+                        if let Some(channel) = self.send_reliable_channels.get_mut(&channel_id) {
+                            for message_id in message_ids {
+                                channel.process_message_ack(message_id);
                             }
-                            PacketSentInfo::None => {}
                         }
                     }
+                    PacketSentInfo::ReliableSliceMessage {
+                        channel_id,
+                        message_id,
+                        slice_index,
+                    } => {
+                        if let Some(channel) = self.send_reliable_channels.get_mut(&channel_id) {
+                            channel.process_slice_message_ack(message_id, slice_index);
+                        }
+                    }
+                    PacketSentInfo::None => {}
                 }
             }
         }
@@ -475,27 +592,32 @@ impl UnityClient {
         let mut available_bytes = self.available_bytes_per_tick;
         for order in self.channel_send_order.iter() {
             match order {
-                ChannelOrder::Reliable(_channel_id) => {
-                    packets.append(
-                        &mut self
-                            .send_reliable_channel
-                            .get_packets_to_send(&mut available_bytes, self.current_time),
-                    );
+                ChannelOrder::Reliable(channel_id) => {
+                    let channel = self.send_reliable_channels.get_mut(channel_id).unwrap();
+                    packets.append(&mut channel.get_packets_to_send(
+                        &mut available_bytes,
+                        &mut self.next_packet_sequence_id,
+                        self.current_time,
+                    ));
                 }
-                ChannelOrder::Unreliable(_channel_id) => {
-                    packets.append(
-                        &mut self
-                            .send_unreliable_channel
-                            .get_packets_to_send(&mut available_bytes),
-                    );
+                ChannelOrder::Unreliable(channel_id) => {
+                    let channel = self.send_unreliable_channels.get_mut(channel_id).unwrap();
+                    packets.append(&mut channel.get_packets_to_send(&mut available_bytes));
                 }
             }
         }
 
-        if self.new_ack_to_send {
-            if let Some((ack_seq_id, ack_mask)) = self.create_acked_bytes() {
+        // Acks are piggy-backed on every packet, they only get a packet of their own when there
+        // are new ones and nothing else to send
+        if let Some((ack_seq_id, ack_mask)) = self.create_acked_bytes() {
+            for packet in packets.iter_mut() {
+                packet.set_acks(ack_seq_id, ack_mask);
+            }
+
+            if self.new_ack_to_send && packets.is_empty() {
+                // Acks cover the packets of every reliable channel, they belong to none
                 let ack_packet = Packet::Ack {
-                    channel_id: 1,
+                    channel_id: 0,
                     packet_type: 1,
                     packet_process_time: self.ack_process_start_instant.elapsed().as_millis()
                         as u16,
@@ -507,6 +629,7 @@ impl UnityClient {
                 packets.push(ack_packet);
             }
         }
+        self.new_ack_to_send = false;
 
         let sent_at = self.current_time;
         for packet in packets.iter() {
@@ -528,6 +651,24 @@ impl UnityClient {
                         },
                     );
                 }
+                Packet::ReliableSlice {
+                    sequence_id,
+                    channel_id,
+                    slice,
+                    ..
+                } => {
+                    self.sent_packets.insert(
+                        *sequence_id,
+                        PacketSent {
+                            sent_at,
+                            info: PacketSentInfo::ReliableSliceMessage {
+                                channel_id: *channel_id,
+                                message_id: slice.message_id,
+                                slice_index: slice.slice_index as usize,
+                            },
+                        },
+                    );
+                }
                 _ => {}
             }
         }
@@ -555,7 +696,12 @@ impl UnityClient {
     }
 
     fn add_pending_ack(&mut self, sequence_id: u16) {
-        if self.pending_acks[0] >= sequence_id || self.pending_acks.contains(&sequence_id) {
+        if self
+            .pending_acks
+            .front()
+            .is_some_and(|&oldest| oldest >= sequence_id)
+            || self.pending_acks.contains(&sequence_id)
+        {
             return;
         }
         self.new_ack_to_send = true;
@@ -579,7 +725,7 @@ impl UnityClient {
                 if self.pending_acks.contains(&seq_id) {
                     ack_mask |= 1 << i; // Write 1 to ack_mask if sequence ID exists
                 }
-                seq_id = seq_id - 1; // Move to the next sequence ID
+                seq_id = seq_id.wrapping_sub(1); // Move to the next sequence ID
             }
             return Some((*last_pending_ack, ack_mask));
         }
@@ -604,3 +750,298 @@ impl UnityClient {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::{CHANNEL_RESEND_TIME, SLICE_SIZE, TRANSPORT_MAX_PACKET_BYTES};
+
+    use super::*;
+
+    /// Sends the packets of `from` to `to`, dropping the ones `drop` returns true for.
+    fn deliver(from: &mut UnityClient, to: &mut UnityClient, mut drop: impl FnMut(usize) -> bool) {
+        for (index, packet) in from.get_packets_to_send().into_iter().enumerate() {
+            if !drop(index) {
+                to.process_packet(&packet);
+            }
+        }
+    }
+
+    #[test]
+    fn sends_sliced_messages() {
+        let mut server = UnityClient::new_from_server(ConnectionConfig::default());
+        let mut client = UnityClient::new(ConnectionConfig::default());
+
+        let reliable: Bytes = (0..SLICE_SIZE * 5 + 7).map(|i| i as u8).collect();
+        let unreliable: Bytes = (0..SLICE_SIZE * 2 + 1).map(|i| (i * 3) as u8).collect();
+        server.send_message(DefaultChannel::ReliableOrdered, reliable.clone());
+        server.send_message(DefaultChannel::Unreliable, unreliable.clone());
+        server.send_message(DefaultChannel::ReliableOrdered, vec![1, 2, 3]);
+
+        // Every other reliable slice is lost the first time, and resent once the others are acked
+        deliver(&mut server, &mut client, |index| {
+            index % 2 == 1 && index < 6
+        });
+        assert_eq!(
+            client.receive_message(DefaultChannel::Unreliable),
+            Some(unreliable)
+        );
+        assert_eq!(
+            client.receive_message(DefaultChannel::ReliableOrdered),
+            None
+        );
+
+        for _ in 0..3 {
+            deliver(&mut client, &mut server, |_| false);
+            server.update(CHANNEL_RESEND_TIME);
+            client.update(CHANNEL_RESEND_TIME);
+            deliver(&mut server, &mut client, |_| false);
+        }
+
+        assert_eq!(
+            client.receive_message(DefaultChannel::ReliableOrdered),
+            Some(reliable)
+        );
+        assert_eq!(
+            client.receive_message(DefaultChannel::ReliableOrdered),
+            Some(Bytes::from(vec![1, 2, 3]))
+        );
+        assert!(!client.is_disconnected());
+
+        // Every slice was acked, the server stops resending them
+        deliver(&mut client, &mut server, |_| false);
+        server.update(CHANNEL_RESEND_TIME);
+        assert!(server.get_packets_to_send().is_empty());
+        assert_eq!(
+            server.channel_available_memory(DefaultChannel::ReliableOrdered),
+            DefaultChannel::config()[1].max_memory_usage_bytes
+        );
+    }
+
+    #[test]
+    fn lost_unordered_messages_do_not_block_later_ones() {
+        let mut server = UnityClient::new_from_server(ConnectionConfig::default());
+        let mut client = UnityClient::new(ConnectionConfig::default());
+
+        server.send_message(DefaultChannel::ReliableUnordered, vec![1]);
+        deliver(&mut server, &mut client, |_| true);
+        server.send_message(DefaultChannel::ReliableUnordered, vec![2]);
+        deliver(&mut server, &mut client, |_| false);
+        assert_eq!(
+            client.receive_message(DefaultChannel::ReliableUnordered),
+            Some(Bytes::from(vec![2]))
+        );
+        assert_eq!(
+            client.receive_message(DefaultChannel::ReliableUnordered),
+            None
+        );
+
+        // Both messages are resent, only the lost one is received again
+        server.update(CHANNEL_RESEND_TIME);
+        deliver(&mut server, &mut client, |_| false);
+        assert_eq!(
+            client.receive_message(DefaultChannel::ReliableUnordered),
+            Some(Bytes::from(vec![1]))
+        );
+        assert_eq!(
+            client.receive_message(DefaultChannel::ReliableUnordered),
+            None
+        );
+        assert!(!client.is_disconnected());
+    }
+
+    #[test]
+    fn sends_reliable_channels_first() {
+        let config = ConnectionConfig {
+            available_bytes_per_tick: 100,
+            ..Default::default()
+        };
+        let mut server = UnityClient::new_from_server(config.clone());
+        let mut client = UnityClient::new(config);
+
+        // The unreliable channel comes first in the config, and alone uses all the bytes of a tick
+        for _ in 0..4 {
+            server.send_message(DefaultChannel::Unreliable, vec![0; 50]);
+        }
+        server.send_message(DefaultChannel::ReliableOrdered, vec![1; 50]);
+        deliver(&mut server, &mut client, |_| false);
+
+        assert_eq!(
+            client.receive_message(DefaultChannel::ReliableOrdered),
+            Some(Bytes::from(vec![1; 50]))
+        );
+        assert_eq!(
+            client.receive_message(DefaultChannel::Unreliable),
+            Some(Bytes::from(vec![0; 50]))
+        );
+        assert_eq!(client.receive_message(DefaultChannel::Unreliable), None);
+    }
+
+    #[test]
+    fn drops_stale_sequenced_messages() {
+        let mut server = UnityClient::new_from_server(ConnectionConfig::default());
+        let mut client = UnityClient::new(ConnectionConfig::default());
+
+        let sliced: Bytes = (0..SLICE_SIZE + 1).map(|i| i as u8).collect();
+        server.send_message(DefaultChannel::Movement, vec![1]);
+        let old = server.get_packets_to_send();
+        server.send_message(DefaultChannel::Movement, sliced.clone());
+        let late_slices = server.get_packets_to_send();
+        server.send_message(DefaultChannel::Movement, vec![2]);
+        server.send_message(DefaultChannel::Movement, vec![3]);
+        server.send_message(DefaultChannel::Rotation, vec![4]);
+        let new = server.get_packets_to_send();
+
+        // The newest messages arrive first, the older ones are dropped when they arrive late
+        for packet in new.iter().chain(&old).chain(&late_slices) {
+            client.process_packet(packet);
+        }
+        assert_eq!(
+            client.receive_message(DefaultChannel::Movement),
+            Some(Bytes::from(vec![2]))
+        );
+        assert_eq!(
+            client.receive_message(DefaultChannel::Movement),
+            Some(Bytes::from(vec![3]))
+        );
+        assert_eq!(client.receive_message(DefaultChannel::Movement), None);
+        // Channels are sequenced on their own
+        assert_eq!(
+            client.receive_message(DefaultChannel::Rotation),
+            Some(Bytes::from(vec![4]))
+        );
+
+        server.send_message(DefaultChannel::Movement, sliced.clone());
+        deliver(&mut server, &mut client, |_| false);
+        assert_eq!(
+            client.receive_message(DefaultChannel::Movement),
+            Some(sliced)
+        );
+        assert!(!client.is_disconnected());
+    }
+
+    #[test]
+    fn sequenced_ids_wrap_around() {
+        let mut client = UnityClient::new(ConnectionConfig::default());
+        let mut buffer = [0u8; TRANSPORT_MAX_PACKET_BYTES];
+        for (message_id, message) in [(u16::MAX, 1), (0, 2), (u16::MAX - 1, 3), (1, 4)] {
+            let packet = Packet::SmallUnreliableSequenced {
+                channel_id: DefaultChannel::Movement.into(),
+                acked_seq_id: 0,
+                acked_mask: 0,
+                messages: vec![(message_id, Bytes::from(vec![message]))],
+            };
+            let len = packet.to_bytes(&mut buffer).unwrap();
+            client.process_packet(&buffer[..len]);
+        }
+
+        let received: Vec<Bytes> =
+            std::iter::from_fn(|| client.receive_message(DefaultChannel::Movement)).collect();
+        assert_eq!(received, [vec![1], vec![2], vec![4]]);
+    }
+
+    #[test]
+    fn piggy_backs_acks_on_outgoing_packets() {
+        let mut server = UnityClient::new_from_server(ConnectionConfig::default());
+        let mut client = UnityClient::new(ConnectionConfig::default());
+        let full_memory = DefaultChannel::config()[1].max_memory_usage_bytes;
+
+        server.send_message(DefaultChannel::ReliableOrdered, vec![1]);
+        deliver(&mut server, &mut client, |_| false);
+        assert_eq!(
+            client.receive_message(DefaultChannel::ReliableOrdered),
+            Some(Bytes::from(vec![1]))
+        );
+
+        // The ack travels on the unreliable message, there is no ack packet
+        client.send_message(DefaultChannel::Unreliable, vec![2]);
+        let packets = client.get_packets_to_send();
+        assert_eq!(packets.len(), 1);
+        assert!(matches!(
+            Packet::from_bytes(&packets[0]),
+            Ok(Packet::SmallUnreliable { acked_mask: 1, .. })
+        ));
+        server.process_packet(&packets[0]);
+        assert_eq!(
+            server.channel_available_memory(DefaultChannel::ReliableOrdered),
+            full_memory
+        );
+
+        // Without anything else to send, new acks get a packet of their own, only once
+        server.send_message(DefaultChannel::ReliableUnordered, vec![3]);
+        deliver(&mut server, &mut client, |_| false);
+        let packets = client.get_packets_to_send();
+        assert_eq!(packets.len(), 1);
+        assert!(matches!(
+            Packet::from_bytes(&packets[0]),
+            Ok(Packet::Ack { acked_mask: 3, .. })
+        ));
+        assert!(client.get_packets_to_send().is_empty());
+        server.process_packet(&packets[0]);
+        assert_eq!(
+            server.channel_available_memory(DefaultChannel::ReliableUnordered),
+            full_memory
+        );
+        assert!(server.get_packets_to_send().is_empty());
+    }
+
+    #[test]
+    fn drives_the_configured_channels() {
+        let channel = |channel_id, send_type| ChannelConfig {
+            channel_id,
+            max_memory_usage_bytes: 1024,
+            send_type,
+        };
+        let reliable = SendType::ReliableOrdered {
+            resend_time: CHANNEL_RESEND_TIME,
+        };
+        let config = ConnectionConfig {
+            available_bytes_per_tick: CONNECTION_AVAILABLE_BYTES_PER_TICK,
+            server_channels_config: vec![
+                channel(7, reliable.clone()),
+                channel(u8::MAX, SendType::Unreliable),
+                channel(3, SendType::Unreliable),
+            ],
+            client_channels_config: vec![channel(5, reliable)],
+        };
+        let mut server = UnityClient::new_from_server(config.clone());
+        let mut client = UnityClient::new(config);
+
+        server.send_message(3, vec![3]);
+        server.send_message(u8::MAX, vec![255]);
+        server.send_message(7, vec![7]);
+        // Packets follow the order of the channels in the config
+        let packets = server.get_packets_to_send();
+        let channels: Vec<u8> = packets
+            .iter()
+            .map(|packet| match Packet::from_bytes(packet).unwrap() {
+                Packet::SmallReliable { channel_id, .. }
+                | Packet::SmallUnreliable { channel_id, .. } => channel_id,
+                packet => panic!("unexpected packet {packet:?}"),
+            })
+            .collect();
+        assert_eq!(channels, [7, u8::MAX, 3]);
+        for packet in packets {
+            client.process_packet(&packet);
+        }
+        assert_eq!(client.receive_message(3), Some(Bytes::from(vec![3])));
+        assert_eq!(
+            client.receive_message(u8::MAX),
+            Some(Bytes::from(vec![255]))
+        );
+        assert_eq!(client.receive_message(7), Some(Bytes::from(vec![7])));
+        assert_eq!(server.channel_available_memory(3), 1024);
+
+        client.send_message(5, vec![5]);
+        deliver(&mut client, &mut server, |_| false);
+        assert_eq!(server.receive_message(5), Some(Bytes::from(vec![5])));
+
+        // The server does not receive on channel 7, a packet for it is refused
+        server.send_message(7, vec![7]);
+        let packet = server.get_packets_to_send().remove(0);
+        server.process_packet(&packet);
+        assert!(matches!(
+            server.disconnect_reason(),
+            Some(DisconnectReason::ReceivedInvalidChannelId(7))
+        ));
+    }
+}
@@ -11,6 +11,14 @@ pub enum DisconnectReason {
     DisconnectedByClient,
     /// Connection was terminated by the server
     DisconnectedByServer,
+    /// The player logged in again with another connection
+    DuplicateLogin,
+    /// The player moved to another session
+    SessionChanged,
+    /// An operator kicked the player
+    Kicked,
+    /// An operator banned the player
+    Banned,
     /// Failed to serialize packet
     PacketSerialization(SerializationError),
     /// Failed to deserialize packet
@@ -30,7 +38,6 @@ pub enum ChannelError {
     /// Reliable channel reached maximum allowed memory
     ReliableChannelMaxMemoryReached,
     /// Received an invalid slice message in the channel.
-    #[allow(dead_code)]
     InvalidSliceMessage,
 }
 
@@ -55,6 +62,10 @@ impl fmt::Display for DisconnectReason {
             Transport => write!(fmt, "connection terminated by the transport layer"),
             DisconnectedByClient => write!(fmt, "connection terminated by the client"),
             DisconnectedByServer => write!(fmt, "connection terminated by the server"),
+            DuplicateLogin => write!(fmt, "player logged in from another connection"),
+            SessionChanged => write!(fmt, "player moved to another session"),
+            Kicked => write!(fmt, "player kicked by an operator"),
+            Banned => write!(fmt, "player banned by an operator"),
             PacketSerialization(err) => write!(fmt, "failed to serialize packet: {err}"),
             PacketDeserialization(err) => write!(fmt, "failed to deserialize packet: {err}"),
             ReceivedInvalidChannelId(id) => {
@@ -74,6 +74,15 @@ impl MessageIn {
             player_id: self.player_id.clone(),
         })
     }
+
+    pub fn to_session_id(&self) -> Result<u32, SerializationError> {
+        if self.data.len() < 4 {
+            return Err(SerializationError::BufferTooShort);
+        }
+        let mut reader = Cursor::new(&self.data);
+
+        Ok(reader.read_u32::<LittleEndian>()?)
+    }
 }
 
 #[derive(Debug)]
@@ -83,8 +92,9 @@ pub enum MessageInType {
     Rotation = 3,
     Jump = 4,
     Invalid = 99,
-    // SessionCreate = 100,
-    // SessionJoin = 101,
+    SessionCreate = 100,
+    SessionJoin = 101,
+    SessionList = 102,
 }
 
 impl TryFrom<u8> for MessageInType {
@@ -96,7 +106,9 @@ impl TryFrom<u8> for MessageInType {
             2 => Ok(MessageInType::Move),
             3 => Ok(MessageInType::Rotation),
             4 => Ok(MessageInType::Jump),
-            // 100 => Ok(MessageInType::SessionCreate),
+            100 => Ok(MessageInType::SessionCreate),
+            101 => Ok(MessageInType::SessionJoin),
+            102 => Ok(MessageInType::SessionList),
             _ => Ok(MessageInType::Invalid),
         }
     }
@@ -2,6 +2,8 @@ use bevy::math::{Quat, Vec3, Vec4};
 use bincode;
 use serde::{Deserialize, Serialize};
 
+use super::transport::transport::CreateSessionError;
+
 #[derive(Debug)]
 pub struct MessageOut {
     // allow dead code because we have some unused message types
@@ -119,6 +121,83 @@ impl MessageOut {
             data: serialized,
         })
     }
+
+    /// Tells the client the session it is in.
+    pub fn session_joined_message(session_id: u32) -> MessageOut {
+        let mut serialized = bincode::serialize(&SessionMessageOut { session_id }).unwrap();
+        serialized.insert(0, 100); // Session Joined Message Type 100
+
+        MessageOut {
+            event_type: MessageOutType::SessionJoined,
+            data: serialized,
+        }
+    }
+
+    pub fn session_join_failed_message(session_id: u32) -> MessageOut {
+        let mut serialized = bincode::serialize(&SessionMessageOut { session_id }).unwrap();
+        serialized.insert(0, 101); // Session Join Failed Message Type 101
+
+        MessageOut {
+            event_type: MessageOutType::SessionJoinFailed,
+            data: serialized,
+        }
+    }
+
+    /// Tells the client why its session was not created.
+    pub fn session_create_failed_message(reason: CreateSessionError) -> MessageOut {
+        let mut serialized = bincode::serialize(&SessionCreateFailedMessageOut {
+            reason: reason as u8,
+        })
+        .unwrap();
+        serialized.insert(0, 105); // Session Create Failed Message Type 105
+
+        MessageOut {
+            event_type: MessageOutType::SessionCreateFailed,
+            data: serialized,
+        }
+    }
+
+    pub fn session_list_message(sessions: Vec<(u32, usize)>) -> MessageOut {
+        let session_list = SessionListMessageOut {
+            sessions: sessions
+                .into_iter()
+                .map(|(session_id, players)| SessionDetails {
+                    session_id,
+                    players: players as u32,
+                })
+                .collect(),
+        };
+
+        let mut serialized = bincode::serialize(&session_list).unwrap();
+        serialized.insert(0, 102); // Session List Message Type 102
+
+        MessageOut {
+            event_type: MessageOutType::SessionList,
+            data: serialized,
+        }
+    }
+
+    /// Warns the client that the server shuts down in the given seconds.
+    pub fn server_closing_message(seconds_left: u32) -> MessageOut {
+        let mut serialized = bincode::serialize(&ServerClosingMessageOut { seconds_left }).unwrap();
+        serialized.insert(0, 104); // Server Closing Message Type 104
+
+        MessageOut {
+            event_type: MessageOutType::ServerClosing,
+            data: serialized,
+        }
+    }
+
+    /// A text from the operators, shown to every player.
+    pub fn announcement_message(text: String) -> MessageOut {
+        let mut serialized = bincode::serialize(&AnnouncementMessageOut { text }).unwrap();
+        serialized.insert(0, 103); // Announcement Message Type 103
+
+        MessageOut {
+            event_type: MessageOutType::Announcement,
+            data: serialized,
+        }
+    }
 }
 
 fn normalize_player_id(player_id: &str) -> [u8; 16] {
@@ -135,6 +214,12 @@ pub enum MessageOutType {
     Position = 1,
     Rotation = 2,
     Disconnect = 10,
+    SessionJoined = 100,
+    SessionJoinFailed = 101,
+    SessionList = 102,
+    Announcement = 103,
+    ServerClosing = 104,
+    SessionCreateFailed = 105,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -199,3 +284,34 @@ struct SpawnDetails {
     position: Vec3,
     rotation: Vec4,
 }
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SessionMessageOut {
+    session_id: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SessionListMessageOut {
+    sessions: Vec<SessionDetails>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SessionDetails {
+    session_id: u32,
+    players: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct AnnouncementMessageOut {
+    text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SessionCreateFailedMessageOut {
+    reason: u8,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct ServerClosingMessageOut {
+    seconds_left: u32,
+}
@@ -6,6 +6,27 @@ use std::{
 };
 pub type Payload = Vec<u8>;
 
+/// Packets start with a byte of these flags, then a byte with the channel id, so any u8 can be
+/// used as a channel id.
+/// Set in packets of reliable channels, followed by the reliable header and the packet type.
+const RELIABLE_FLAG: u8 = 1 << 0;
+/// Set in unreliable packets carrying a slice, reliable packets use their packet type instead.
+const SLICE_FLAG: u8 = 1 << 1;
+/// Set in unreliable packets whose messages are numbered, slices always are.
+const SEQUENCED_FLAG: u8 = 1 << 2;
+/// Set in unreliable packets carrying acks, followed by the acked sequence id and mask after the
+/// channel id. Reliable packets always carry them in their header.
+const ACKS_FLAG: u8 = 1 << 3;
+
+/// A slice of a message too long to fit in one packet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SliceMessage {
+    pub message_id: u64,
+    pub slice_index: u32,
+    pub num_slices: u32,
+    pub payload: Bytes,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Packet {
     // Small messages in a reliable channel are aggregated and sent in this packet
@@ -21,8 +42,34 @@ pub enum Packet {
     // Small messages in a unreliable channel are aggregated and sent in this packet
     SmallUnreliable {
         channel_id: u8,
+        acked_seq_id: u16,
+        acked_mask: u32,
         messages: Vec<Bytes>,
     },
+    // Small messages in a sequenced unreliable channel, with their wrapping sequence number
+    SmallUnreliableSequenced {
+        channel_id: u8,
+        acked_seq_id: u16,
+        acked_mask: u32,
+        messages: Vec<(u16, Bytes)>,
+    },
+    // A slice of a big message in a reliable channel, acked and resent on its own
+    ReliableSlice {
+        channel_id: u8,
+        packet_type: u16,
+        packet_process_time: u16,
+        sequence_id: u16,
+        acked_seq_id: u16,
+        acked_mask: u32,
+        slice: SliceMessage,
+    },
+    // A slice of a big message in a unreliable channel
+    UnreliableSlice {
+        channel_id: u8,
+        acked_seq_id: u16,
+        acked_mask: u32,
+        slice: SliceMessage,
+    },
     // Contains the packets that were acked
     // Acks are saved in multiples ranges, all values in the ranges are considered acked.
     Ack {
@@ -41,10 +88,88 @@ impl Packet {
         match self {
             Packet::SmallReliable { sequence_id, .. } => *sequence_id,
             Packet::SmallUnreliable { .. } => 0, // Return 0 when there's no sequence_id
+            Packet::SmallUnreliableSequenced { .. } => 0,
+            Packet::ReliableSlice { sequence_id, .. } => *sequence_id,
+            Packet::UnreliableSlice { .. } => 0,
             Packet::Ack { sequence_id, .. } => *sequence_id,
         }
     }
 
+    /// The acks carried by the packet, a zero mask acks nothing.
+    pub fn acks(&self) -> (u16, u32) {
+        match self {
+            Packet::SmallReliable {
+                acked_seq_id,
+                acked_mask,
+                ..
+            }
+            | Packet::SmallUnreliable {
+                acked_seq_id,
+                acked_mask,
+                ..
+            }
+            | Packet::SmallUnreliableSequenced {
+                acked_seq_id,
+                acked_mask,
+                ..
+            }
+            | Packet::ReliableSlice {
+                acked_seq_id,
+                acked_mask,
+                ..
+            }
+            | Packet::UnreliableSlice {
+                acked_seq_id,
+                acked_mask,
+                ..
+            }
+            | Packet::Ack {
+                acked_seq_id,
+                acked_mask,
+                ..
+            } => (*acked_seq_id, *acked_mask),
+        }
+    }
+
+    /// Piggy-backs the acks of the connection on the packet.
+    pub fn set_acks(&mut self, seq_id: u16, mask: u32) {
+        match self {
+            Packet::SmallReliable {
+                acked_seq_id,
+                acked_mask,
+                ..
+            }
+            | Packet::SmallUnreliable {
+                acked_seq_id,
+                acked_mask,
+                ..
+            }
+            | Packet::SmallUnreliableSequenced {
+                acked_seq_id,
+                acked_mask,
+                ..
+            }
+            | Packet::ReliableSlice {
+                acked_seq_id,
+                acked_mask,
+                ..
+            }
+            | Packet::UnreliableSlice {
+                acked_seq_id,
+                acked_mask,
+                ..
+            }
+            | Packet::Ack {
+                acked_seq_id,
+                acked_mask,
+                ..
+            } => {
+                *acked_seq_id = seq_id;
+                *acked_mask = mask;
+            }
+        }
+    }
+
     pub fn to_bytes(&self, b: &mut [u8]) -> Result<usize, SerializationError> {
         let mut writer = Cursor::new(b);
         let before = writer.remaining();
@@ -58,6 +183,7 @@ impl Packet {
                 acked_mask,
                 messages,
             } => {
+                writer.write_u8(RELIABLE_FLAG)?;
                 writer.write_u8(*channel_id)?;
                 writer.write_u16::<LittleEndian>(*packet_type)?;
                 writer.write_u16::<LittleEndian>(*packet_process_time)?;
@@ -73,15 +199,72 @@ impl Packet {
             }
             Packet::SmallUnreliable {
                 channel_id,
+                acked_seq_id,
+                acked_mask,
                 messages,
             } => {
-                writer.write_u8(*channel_id)?;
+                write_unreliable_header(&mut writer, 0, *channel_id, *acked_seq_id, *acked_mask)?;
                 writer.write_u16::<LittleEndian>(messages.len() as u16)?;
                 for message in messages {
                     writer.write_u16::<LittleEndian>(message.len() as u16)?;
                     writer.write_all(message)?;
                 }
             }
+            Packet::SmallUnreliableSequenced {
+                channel_id,
+                acked_seq_id,
+                acked_mask,
+                messages,
+            } => {
+                let flags = SEQUENCED_FLAG;
+                write_unreliable_header(
+                    &mut writer,
+                    flags,
+                    *channel_id,
+                    *acked_seq_id,
+                    *acked_mask,
+                )?;
+                writer.write_u16::<LittleEndian>(messages.len() as u16)?;
+                for (message_id, message) in messages {
+                    writer.write_u16::<LittleEndian>(*message_id)?;
+                    writer.write_u16::<LittleEndian>(message.len() as u16)?;
+                    writer.write_all(message)?;
+                }
+            }
+            Packet::ReliableSlice {
+                channel_id,
+                packet_type,
+                packet_process_time,
+                sequence_id,
+                acked_seq_id,
+                acked_mask,
+                slice,
+            } => {
+                writer.write_u8(RELIABLE_FLAG)?;
+                writer.write_u8(*channel_id)?;
+                writer.write_u16::<LittleEndian>(*packet_type)?;
+                writer.write_u16::<LittleEndian>(*packet_process_time)?;
+                writer.write_u16::<LittleEndian>(*sequence_id)?;
+                writer.write_u16::<LittleEndian>(*acked_seq_id)?;
+                writer.write_u32::<LittleEndian>(*acked_mask)?;
+                slice.write(&mut writer)?;
+            }
+            Packet::UnreliableSlice {
+                channel_id,
+                acked_seq_id,
+                acked_mask,
+                slice,
+            } => {
+                let flags = SLICE_FLAG;
+                write_unreliable_header(
+                    &mut writer,
+                    flags,
+                    *channel_id,
+                    *acked_seq_id,
+                    *acked_mask,
+                )?;
+                slice.write(&mut writer)?;
+            }
             Packet::Ack {
                 channel_id,
                 packet_type,
@@ -91,6 +274,7 @@ impl Packet {
                 acked_mask,
                 end_posfix,
             } => {
+                writer.write_u8(RELIABLE_FLAG)?;
                 writer.write_u8(*channel_id)?;
                 writer.write_u16::<LittleEndian>(*packet_type)?;
                 writer.write_u16::<LittleEndian>(*packet_process_time)?;
@@ -106,9 +290,17 @@ impl Packet {
 
     pub fn from_bytes(b: &[u8]) -> Result<Packet, SerializationError> {
         let mut reader = Cursor::new(b);
+        let flags = reader.read_u8()?;
         let channel_id = reader.read_u8()?;
+        let (acked_seq_id, acked_mask) = match flags & (RELIABLE_FLAG | ACKS_FLAG) {
+            ACKS_FLAG => (
+                reader.read_u16::<LittleEndian>()?,
+                reader.read_u32::<LittleEndian>()?,
+            ),
+            _ => (0, 0),
+        };
         let mut messages: Vec<Bytes> = Vec::with_capacity(64);
-        match channel_id {
+        match flags & !ACKS_FLAG {
             0 => {
                 // SmallUnreliable
                 let messages_len = reader.read_u16::<LittleEndian>()?;
@@ -120,11 +312,37 @@ impl Packet {
                 }
                 Ok(Packet::SmallUnreliable {
                     channel_id,
+                    acked_seq_id,
+                    acked_mask,
                     messages,
                 })
             }
 
-            1 => {
+            SEQUENCED_FLAG => {
+                let messages_len = reader.read_u16::<LittleEndian>()?;
+                let mut messages: Vec<(u16, Bytes)> = Vec::with_capacity(64);
+                for _ in 0..messages_len {
+                    let message_id = reader.read_u16::<LittleEndian>()?;
+                    let message_len = reader.read_u16::<LittleEndian>()?;
+                    let mut data = vec![0u8; message_len as usize];
+                    reader.read_exact(&mut data)?;
+                    messages.push((message_id, data.into()));
+                }
+                Ok(Packet::SmallUnreliableSequenced {
+                    channel_id,
+                    acked_seq_id,
+                    acked_mask,
+                    messages,
+                })
+            }
+            SLICE_FLAG => Ok(Packet::UnreliableSlice {
+                channel_id,
+                acked_seq_id,
+                acked_mask,
+                slice: SliceMessage::read(&mut reader)?,
+            }),
+            // Reliable ordered and unordered channels share the packet format
+            RELIABLE_FLAG => {
                 let packet_type = reader.read_u16::<LittleEndian>()?;
                 let packet_process_time = reader.read_u16::<LittleEndian>()?;
                 let sequence_id = reader.read_u16::<LittleEndian>()?;
@@ -153,6 +371,15 @@ impl Packet {
                             messages,
                         })
                     }
+                    2 => Ok(Packet::ReliableSlice {
+                        channel_id,
+                        packet_type,
+                        packet_process_time,
+                        sequence_id,
+                        acked_seq_id,
+                        acked_mask,
+                        slice: SliceMessage::read(&mut reader)?,
+                    }),
                     1 => {
                         // SmallReliable Ack
                         let end_posfix = reader.read_u8()?;
@@ -169,20 +396,69 @@ impl Packet {
                     _ => Err(SerializationError::InvalidPacketType),
                 }
             }
-            _ => Err(SerializationError::InvalidChannelId),
+            _ => Err(SerializationError::InvalidPacketType),
         }
     }
 }
 
+/// Unreliable packets only carry acks when there are some, see [`ACKS_FLAG`].
+fn write_unreliable_header(
+    writer: &mut Cursor<&mut [u8]>,
+    flags: u8,
+    channel_id: u8,
+    acked_seq_id: u16,
+    acked_mask: u32,
+) -> Result<(), SerializationError> {
+    if acked_mask == 0 {
+        writer.write_u8(flags)?;
+        writer.write_u8(channel_id)?;
+    } else {
+        writer.write_u8(flags | ACKS_FLAG)?;
+        writer.write_u8(channel_id)?;
+        writer.write_u16::<LittleEndian>(acked_seq_id)?;
+        writer.write_u32::<LittleEndian>(acked_mask)?;
+    }
+    Ok(())
+}
+
+impl SliceMessage {
+    fn write(&self, writer: &mut Cursor<&mut [u8]>) -> Result<(), SerializationError> {
+        writer.write_u64::<LittleEndian>(self.message_id)?;
+        writer.write_u32::<LittleEndian>(self.slice_index)?;
+        writer.write_u32::<LittleEndian>(self.num_slices)?;
+        writer.write_u16::<LittleEndian>(self.payload.len() as u16)?;
+        writer.write_all(&self.payload)?;
+        Ok(())
+    }
+
+    fn read(reader: &mut Cursor<&[u8]>) -> Result<Self, SerializationError> {
+        let message_id = reader.read_u64::<LittleEndian>()?;
+        let slice_index = reader.read_u32::<LittleEndian>()?;
+        let num_slices = reader.read_u32::<LittleEndian>()?;
+        if slice_index >= num_slices {
+            return Err(SerializationError::InvalidNumSlices);
+        }
+
+        let payload_len = reader.read_u16::<LittleEndian>()?;
+        let mut payload = vec![0u8; payload_len as usize];
+        reader.read_exact(&mut payload)?;
+
+        Ok(Self {
+            message_id,
+            slice_index,
+            num_slices,
+            payload: payload.into(),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SerializationError {
     BufferTooShort,
-    #[allow(dead_code)]
     InvalidNumSlices,
     #[allow(dead_code)]
     InvalidAckRange,
     InvalidPacketType,
-    InvalidChannelId,
     CursorReadError,
 }
 
@@ -197,7 +473,6 @@ impl fmt::Display for SerializationError {
             InvalidNumSlices => write!(fmt, "invalid number of slices"),
             InvalidAckRange => write!(fmt, "invalid ack range"),
             InvalidPacketType => write!(fmt, "invalid packet type"),
-            InvalidChannelId => write!(fmt, "invalid channel id"),
             CursorReadError => write!(fmt, "cursor read error"),
         }
     }
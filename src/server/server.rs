@@ -3,10 +3,13 @@ use std::time::Duration;
 
 use bevy::prelude::Resource;
 use bytes::Bytes;
-use crossbeam::channel::{Receiver, Sender};
+use crossbeam::channel::{Receiver, Sender, TryRecvError};
+use serde::Serialize;
 
+use super::channel::DefaultChannel;
 use super::connection::{ConnectionConfig, NetworkInfo, UnityClient};
 use super::error::{ClientNotFound, DisconnectReason};
+use super::message_out::MessageOut;
 use super::packet::Payload;
 use super::transport::transport::{FromDenariaServerMessage, ToDenariaServerMessage};
 
@@ -23,18 +26,30 @@ pub enum ServerEvent {
     },
 }
 
+/// A player connected to a session, as listed to the operators.
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerInfo {
+    pub client_id: u64,
+    pub player_id: String,
+    pub session_id: u32,
+    pub network_info: NetworkInfo,
+}
+
 #[derive(Debug, Resource)]
 pub struct DenariaServer {
     connections: HashMap<ClientId, UnityClient>,
     player_connection_map: HashMap<String, ClientId>,
     connection_config: ConnectionConfig,
     events: VecDeque<ServerEvent>,
+    session_id: u32,
+    shutting_down: bool,
     from_transport_server_rx: Receiver<ToDenariaServerMessage>,
     to_transport_server_tx: Sender<FromDenariaServerMessage>,
 }
 
 impl DenariaServer {
     pub fn new(
+        session_id: u32,
         connection_config: ConnectionConfig,
         from_transport_server_rx: Receiver<ToDenariaServerMessage>,
         to_transport_server_tx: Sender<FromDenariaServerMessage>,
@@ -44,11 +59,18 @@ impl DenariaServer {
             player_connection_map: HashMap::new(),
             connection_config,
             events: VecDeque::new(),
+            session_id,
+            shutting_down: false,
             from_transport_server_rx,
             to_transport_server_tx,
         }
     }
 
+    /// Returns whether the transport asked the session to stop.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down
+    }
+
     /// Adds a new connection to the server. If a connection already exits it does nothing.
     /// <p style="background:rgba(77,220,255,0.16);padding:0.5em;">
     /// <strong>Note:</strong> This should only be called by the transport layer.
@@ -61,9 +83,32 @@ impl DenariaServer {
         let mut connection = UnityClient::new_from_server(self.connection_config.clone());
         // Consider newly added connections as connected
         connection.set_connected(player_id.clone());
+        self.insert_connection(client_id, player_id, connection);
+    }
+
+    /// Adds the connection of a client that moved from another session, keeping the state of
+    /// its channels. If a connection already exits it does nothing.
+    pub fn add_moved_connection(
+        &mut self,
+        client_id: ClientId,
+        player_id: String,
+        connection: UnityClient,
+    ) {
+        if self.connections.contains_key(&client_id) {
+            return;
+        }
+
+        self.insert_connection(client_id, player_id, connection);
+    }
+
+    fn insert_connection(
+        &mut self,
+        client_id: ClientId,
+        player_id: String,
+        connection: UnityClient,
+    ) {
         self.connections.insert(client_id, connection);
-        self.player_connection_map
-            .insert(player_id.clone(), client_id);
+        self.player_connection_map.insert(player_id, client_id);
         self.events
             .push_back(ServerEvent::ClientConnected { client_id })
     }
@@ -142,21 +187,33 @@ impl DenariaServer {
 
     /// Removes a connection from the server, emits an disconnect server event.
     /// It does nothing if the client does not exits.
+    /// `reason` is used when the connection itself was not disconnected.
     /// <p style="background:rgba(77,220,255,0.16);padding:0.5em;">
     /// <strong>Note:</strong> This should only be called by the transport layer.
     /// </p>
-    pub fn remove_connection(&mut self, client_id: ClientId) {
-        if let Some(connection) = self.connections.remove(&client_id) {
-            let player_id = connection.player_id().clone();
-            let reason = connection
-                .disconnect_reason()
-                .unwrap_or(DisconnectReason::Transport);
-            self.events.push_back(ServerEvent::ClientDisconnected {
-                client_id,
-                player_id,
-                reason,
-            });
+    pub fn remove_connection(&mut self, client_id: ClientId, reason: DisconnectReason) {
+        self.take_connection(client_id, reason);
+    }
+
+    /// Same as [`DenariaServer::remove_connection`], returning the removed connection.
+    fn take_connection(
+        &mut self,
+        client_id: ClientId,
+        reason: DisconnectReason,
+    ) -> Option<UnityClient> {
+        let connection = self.connections.remove(&client_id)?;
+        let player_id = connection.player_id().clone();
+        // The player may already be connected again with another client
+        if self.player_connection_map.get(&player_id) == Some(&client_id) {
+            self.player_connection_map.remove(&player_id);
         }
+        let reason = connection.disconnect_reason().unwrap_or(reason);
+        self.events.push_back(ServerEvent::ClientDisconnected {
+            client_id,
+            player_id,
+            reason,
+        });
+        Some(connection)
     }
 
     /// Disconnects a client, it does nothing if the client does not exist.
@@ -337,7 +394,16 @@ impl DenariaServer {
     }
 
     pub fn process_server_transport_messages(&mut self) {
-        while let Ok(message) = self.from_transport_server_rx.try_recv() {
+        loop {
+            let message = match self.from_transport_server_rx.try_recv() {
+                Ok(message) => message,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    // The transport is gone, nothing can reach the session anymore
+                    self.shutting_down = true;
+                    break;
+                }
+            };
             match message {
                 ToDenariaServerMessage::ClientConnected {
                     client_id,
@@ -347,8 +413,33 @@ impl DenariaServer {
                 } => {
                     self.add_connection(ClientId::from_raw(client_id), player_id);
                 }
-                ToDenariaServerMessage::ClientDisconnected { client_id } => {
-                    self.remove_connection(ClientId::from_raw(client_id));
+                ToDenariaServerMessage::ClientDisconnected { client_id, reason } => {
+                    self.remove_connection(ClientId::from_raw(client_id), reason);
+                }
+                ToDenariaServerMessage::MoveClient {
+                    client_id,
+                    session_id,
+                } => {
+                    let connection = self.take_connection(
+                        ClientId::from_raw(client_id),
+                        DisconnectReason::SessionChanged,
+                    );
+                    self.send_to_server_transport(FromDenariaServerMessage::ClientMovedOut {
+                        client_id,
+                        session_id,
+                        connection: connection.map(Box::new),
+                    });
+                }
+                ToDenariaServerMessage::ClientMoved {
+                    client_id,
+                    player_id,
+                    connection,
+                } => {
+                    self.add_moved_connection(
+                        ClientId::from_raw(client_id),
+                        player_id,
+                        *connection,
+                    );
                 }
                 ToDenariaServerMessage::Payload { client_id, payload } => {
                     if let Err(e) =
@@ -357,10 +448,120 @@ impl DenariaServer {
                         tracing::error!("Failed to process packet from client: {:?}", e);
                     }
                 }
+                ToDenariaServerMessage::SessionJoined {
+                    client_id,
+                    session_id,
+                } => {
+                    let message = MessageOut::session_joined_message(session_id);
+                    self.send_message(
+                        ClientId::from_raw(client_id),
+                        DefaultChannel::ReliableOrdered,
+                        message.data,
+                    );
+                }
+                ToDenariaServerMessage::SessionJoinFailed {
+                    client_id,
+                    session_id,
+                } => {
+                    let message = MessageOut::session_join_failed_message(session_id);
+                    self.send_message(
+                        ClientId::from_raw(client_id),
+                        DefaultChannel::ReliableOrdered,
+                        message.data,
+                    );
+                }
+                ToDenariaServerMessage::SessionCreateFailed { client_id, reason } => {
+                    let message = MessageOut::session_create_failed_message(reason);
+                    self.send_message(
+                        ClientId::from_raw(client_id),
+                        DefaultChannel::ReliableOrdered,
+                        message.data,
+                    );
+                }
+                ToDenariaServerMessage::SessionList {
+                    client_id,
+                    sessions,
+                } => {
+                    let message = MessageOut::session_list_message(
+                        sessions
+                            .into_iter()
+                            .map(|session| (session.id, session.players))
+                            .collect(),
+                    );
+                    self.send_message(
+                        ClientId::from_raw(client_id),
+                        DefaultChannel::ReliableOrdered,
+                        message.data,
+                    );
+                }
+                ToDenariaServerMessage::Announcement { text } => {
+                    let message = MessageOut::announcement_message(text);
+                    self.broadcast_message(DefaultChannel::ReliableOrdered, message.data);
+                }
+                ToDenariaServerMessage::ServerClosing { seconds_left } => {
+                    let message = MessageOut::server_closing_message(seconds_left);
+                    self.broadcast_message(DefaultChannel::ReliableOrdered, message.data);
+                }
+                ToDenariaServerMessage::PlayersInfo { reply } => {
+                    // Fails when the operator stopped waiting
+                    let _ = reply.send(self.players_info());
+                }
+                ToDenariaServerMessage::Shutdown => {
+                    tracing::info!("Session {} is shutting down", self.session_id);
+                    self.shutting_down = true;
+                }
             }
         }
     }
 
+    /// Returns the connected players with the stats of their connection.
+    pub fn players_info(&self) -> Vec<PlayerInfo> {
+        self.connections
+            .iter()
+            .map(|(client_id, connection)| PlayerInfo {
+                client_id: client_id.raw(),
+                player_id: connection.player_id().clone(),
+                session_id: self.session_id,
+                network_info: connection.network_info(),
+            })
+            .collect()
+    }
+
+    /// Tells the transport the session is set up and can run its clients.
+    pub fn session_started(&self) {
+        self.send_to_server_transport(FromDenariaServerMessage::SessionStarted {
+            session_id: self.session_id,
+        });
+    }
+
+    /// Asks the transport to create a session and move the client to it.
+    pub fn create_session(&self, client_id: ClientId) {
+        self.send_to_server_transport(FromDenariaServerMessage::CreateSession {
+            client_id: client_id.raw(),
+        });
+    }
+
+    /// Asks the transport to move the client to the session.
+    pub fn join_session(&self, client_id: ClientId, session_id: u32) {
+        self.send_to_server_transport(FromDenariaServerMessage::JoinSession {
+            client_id: client_id.raw(),
+            session_id,
+        });
+    }
+
+    /// Asks the transport to send the open sessions to the client.
+    pub fn list_sessions(&self, client_id: ClientId) {
+        self.send_to_server_transport(FromDenariaServerMessage::ListSessions {
+            client_id: client_id.raw(),
+        });
+    }
+
+    fn send_to_server_transport(&self, message: FromDenariaServerMessage) {
+        if let Err(e) = self.to_transport_server_tx.send(message) {
+            tracing::error!("Failed to send message to server transport: {:?}", e);
+        }
+    }
+
     pub fn send_packets_to_server_transport(&mut self, client_id: ClientId, packets: Vec<Vec<u8>>) {
         if let Err(e) = self
             .to_transport_server_tx
@@ -0,0 +1,520 @@
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crossbeam::channel::{unbounded, Receiver, RecvTimeoutError, Sender};
+use serde_json::{json, Value};
+
+use crate::{
+    constants::SHUTDOWN_DRAIN_PERIOD,
+    server::{error::DisconnectReason, server::PlayerInfo},
+};
+
+use super::transport::ServerTransport;
+
+/// How long a connection waits for the transport to run its command.
+const ADMIN_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the sessions have to list their players.
+const ADMIN_PLAYERS_TIMEOUT: Duration = Duration::from_secs(1);
+/// Connections idle for this long are closed.
+const ADMIN_READ_TIMEOUT: Duration = Duration::from_secs(60);
+/// Connections handled at once, the next ones are refused until one closes.
+const ADMIN_MAX_CONNECTIONS: usize = 4;
+
+/// A command sent by an operator, one per line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminCommand {
+    /// `sessions`, also answers the counters of the rate limiter
+    Sessions,
+    /// `players`
+    Players,
+    /// `kick <player_id> [reason]`
+    Kick { player_id: String, reason: String },
+    /// `ban <player_id> [reason]`, bans are only kept in memory and are lost on restart
+    Ban { player_id: String, reason: String },
+    /// `unban <player_id>`
+    Unban { player_id: String },
+    /// `announce <text>`
+    Announce { text: String },
+    /// `shutdown [drain seconds]`
+    Shutdown { drain_period: Option<Duration> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdminCommandError {
+    Unknown(String),
+    MissingArgument(&'static str),
+    InvalidArgument(&'static str),
+}
+
+impl fmt::Display for AdminCommandError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use AdminCommandError::*;
+
+        match self {
+            Unknown(command) => write!(fmt, "unknown command {command}"),
+            MissingArgument(argument) => write!(fmt, "missing {argument}"),
+            InvalidArgument(argument) => write!(fmt, "invalid {argument}"),
+        }
+    }
+}
+
+impl std::error::Error for AdminCommandError {}
+
+impl AdminCommand {
+    pub fn parse(line: &str) -> Result<Self, AdminCommandError> {
+        let line = line.trim();
+        let (command, arguments) = line.split_once(' ').unwrap_or((line, ""));
+        let arguments = arguments.trim();
+        let (first, rest) = arguments.split_once(' ').unwrap_or((arguments, ""));
+        let player_id = || {
+            if first.is_empty() {
+                return Err(AdminCommandError::MissingArgument("player id"));
+            }
+            Ok(first.to_string())
+        };
+        let reason = || match rest.trim() {
+            "" => "no reason given".to_string(),
+            reason => reason.to_string(),
+        };
+
+        match command {
+            "sessions" => Ok(Self::Sessions),
+            "players" => Ok(Self::Players),
+            "kick" => Ok(Self::Kick {
+                player_id: player_id()?,
+                reason: reason(),
+            }),
+            "ban" => Ok(Self::Ban {
+                player_id: player_id()?,
+                reason: reason(),
+            }),
+            "unban" => Ok(Self::Unban {
+                player_id: player_id()?,
+            }),
+            "announce" if arguments.is_empty() => Err(AdminCommandError::MissingArgument("text")),
+            "announce" => Ok(Self::Announce {
+                text: arguments.to_string(),
+            }),
+            "shutdown" if arguments.is_empty() => Ok(Self::Shutdown { drain_period: None }),
+            "shutdown" => match arguments.parse() {
+                Ok(secs) => Ok(Self::Shutdown {
+                    drain_period: Some(Duration::from_secs(secs)),
+                }),
+                Err(_) => Err(AdminCommandError::InvalidArgument("drain seconds")),
+            },
+            _ => Err(AdminCommandError::Unknown(command.to_string())),
+        }
+    }
+}
+
+enum AdminReply {
+    Done(Value),
+    Failed(String),
+    /// The sessions answer on the channel, see [`ServerTransport::players_info`].
+    Players(Receiver<Vec<PlayerInfo>>),
+}
+
+struct AdminRequest {
+    command: AdminCommand,
+    reply: Sender<AdminReply>,
+}
+
+/// Line based control endpoint for the operators, on a local TCP port.
+///
+/// A connection starts with `auth <token>`, then sends one [`AdminCommand`] per line and gets
+/// one JSON line back for each. Connections run on their own threads, the commands are run on
+/// the transport by [`AdminServer::handle_requests`].
+#[derive(Debug)]
+pub struct AdminServer {
+    addr: SocketAddr,
+    requests: Receiver<AdminRequest>,
+}
+
+impl fmt::Debug for AdminRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminRequest")
+            .field("command", &self.command)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AdminServer {
+    /// Listens on the address, which must be a loopback one.
+    pub fn bind(addr: SocketAddr, token: String) -> io::Result<Self> {
+        if !addr.ip().is_loopback() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("admin address {addr} must be a loopback address"),
+            ));
+        }
+        if token.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "admin token must not be empty",
+            ));
+        }
+
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let (requests_tx, requests) = unbounded();
+        let connections = Arc::new(AtomicUsize::new(0));
+        std::thread::Builder::new()
+            .name("admin".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            tracing::error!("Failed to accept admin connection: {e}");
+                            continue;
+                        }
+                    };
+                    if connections.load(Ordering::Relaxed) >= ADMIN_MAX_CONNECTIONS {
+                        tracing::warn!("Refused admin connection, too many are open");
+                        let reply = AdminReply::Failed("too many connections".to_string());
+                        let _ = write_reply(&mut stream, reply);
+                        continue;
+                    }
+                    if let Err(e) = stream.set_read_timeout(Some(ADMIN_READ_TIMEOUT)) {
+                        tracing::error!("Failed to set admin connection timeout: {e}");
+                        continue;
+                    }
+
+                    connections.fetch_add(1, Ordering::Relaxed);
+                    let token = token.clone();
+                    let requests_tx = requests_tx.clone();
+                    let thread_connections = connections.clone();
+                    let spawned = std::thread::Builder::new()
+                        .name("admin-connection".to_string())
+                        .spawn(move || {
+                            if let Err(e) = handle_connection(stream, &token, requests_tx) {
+                                tracing::warn!("Admin connection failed: {e}");
+                            }
+                            thread_connections.fetch_sub(1, Ordering::Relaxed);
+                        });
+                    if let Err(e) = spawned {
+                        tracing::error!("Failed to spawn admin connection thread: {e}");
+                        connections.fetch_sub(1, Ordering::Relaxed);
+                    }
+                }
+            })?;
+
+        Ok(Self { addr, requests })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Runs the commands received since the last call.
+    pub fn handle_requests(&self, transport: &mut ServerTransport) {
+        while let Ok(request) = self.requests.try_recv() {
+            tracing::info!("Admin command: {:?}", request.command);
+            let reply = match request.command {
+                AdminCommand::Sessions => AdminReply::Done(json!({
+                    "sessions": transport
+                        .sessions()
+                        .into_iter()
+                        .map(|session| json!({
+                            "id": session.id,
+                            "state": format!("{:?}", session.state),
+                            "players": session.players,
+                        }))
+                        .collect::<Vec<_>>(),
+                    "rate_limit": transport.rate_limit_stats(),
+                })),
+                AdminCommand::Players => AdminReply::Players(transport.players_info()),
+                AdminCommand::Kick { player_id, reason } => {
+                    if transport.kick_player(&player_id, DisconnectReason::Kicked, &reason) {
+                        tracing::info!("Kicked player {player_id}: {reason}");
+                        AdminReply::Done(Value::Null)
+                    } else {
+                        AdminReply::Failed(format!("player {player_id} is not connected"))
+                    }
+                }
+                AdminCommand::Ban { player_id, reason } => {
+                    tracing::info!("Banned player {player_id}: {reason}");
+                    transport.ban_player(&player_id, reason);
+                    AdminReply::Done(Value::Null)
+                }
+                AdminCommand::Unban { player_id } => {
+                    if transport.unban_player(&player_id) {
+                        AdminReply::Done(Value::Null)
+                    } else {
+                        AdminReply::Failed(format!("player {player_id} is not banned"))
+                    }
+                }
+                AdminCommand::Announce { text } => {
+                    transport.announce(&text);
+                    AdminReply::Done(Value::Null)
+                }
+                AdminCommand::Shutdown { drain_period } => {
+                    transport.begin_shutdown(drain_period.unwrap_or(SHUTDOWN_DRAIN_PERIOD));
+                    AdminReply::Done(Value::Null)
+                }
+            };
+            // The connection may be gone already, the command ran anyway
+            let _ = request.reply.send(reply);
+        }
+    }
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    token: &str,
+    requests_tx: Sender<AdminRequest>,
+) -> io::Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut writer = stream.try_clone()?;
+    let mut lines = BufReader::new(stream).lines();
+
+    let authenticated = match lines.next() {
+        Some(line) => line?
+            .trim()
+            .strip_prefix("auth ")
+            .is_some_and(|given| token_matches(given.trim(), token)),
+        None => return Ok(()),
+    };
+    if !authenticated {
+        tracing::warn!("Admin connection from {peer} failed to authenticate");
+        return write_reply(&mut writer, AdminReply::Failed("unauthorized".to_string()));
+    }
+    write_reply(&mut writer, AdminReply::Done(Value::Null))?;
+
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let command = match AdminCommand::parse(&line) {
+            Ok(command) => command,
+            Err(e) => {
+                write_reply(&mut writer, AdminReply::Failed(e.to_string()))?;
+                continue;
+            }
+        };
+
+        let (reply_tx, reply_rx) = unbounded();
+        let request = AdminRequest {
+            command,
+            reply: reply_tx,
+        };
+        if requests_tx.send(request).is_err() {
+            return write_reply(
+                &mut writer,
+                AdminReply::Failed("server stopped".to_string()),
+            );
+        }
+        let reply = reply_rx
+            .recv_timeout(ADMIN_REPLY_TIMEOUT)
+            .unwrap_or_else(|_| AdminReply::Failed("server did not answer".to_string()));
+        write_reply(&mut writer, reply)?;
+    }
+
+    Ok(())
+}
+
+fn write_reply(writer: &mut impl Write, reply: AdminReply) -> io::Result<()> {
+    let reply = match reply {
+        AdminReply::Done(result) => json!({ "ok": true, "result": result }),
+        AdminReply::Failed(error) => json!({ "ok": false, "error": error }),
+        AdminReply::Players(sessions) => {
+            json!({ "ok": true, "result": collect_players(sessions) })
+        }
+    };
+    writeln!(writer, "{reply}")
+}
+
+/// Waits for every session to list its players, sessions answering too late are left out.
+fn collect_players(sessions: Receiver<Vec<PlayerInfo>>) -> Vec<PlayerInfo> {
+    let deadline = Instant::now() + ADMIN_PLAYERS_TIMEOUT;
+    let mut players = vec![];
+    loop {
+        match sessions.recv_deadline(deadline) {
+            Ok(session_players) => players.extend(session_players),
+            Err(RecvTimeoutError::Timeout) => {
+                tracing::warn!("Sessions did not list their players in time");
+                break;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    players.sort_by_key(|player| player.client_id);
+    players
+}
+
+/// Compares the whole tokens, so the time taken does not tell how much of it matched.
+fn token_matches(given: &str, token: &str) -> bool {
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        constants::TICK_DELTA,
+        server::transport::{
+            simulated_client::{test_server_config, SimulatedClient},
+            socket::InMemoryNetwork,
+        },
+    };
+
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            AdminCommand::parse("kick player1 spamming the chat"),
+            Ok(AdminCommand::Kick {
+                player_id: "player1".to_string(),
+                reason: "spamming the chat".to_string()
+            })
+        );
+        assert_eq!(
+            AdminCommand::parse("announce Restarting in 5 minutes"),
+            Ok(AdminCommand::Announce {
+                text: "Restarting in 5 minutes".to_string()
+            })
+        );
+        assert_eq!(
+            AdminCommand::parse("ban"),
+            Err(AdminCommandError::MissingArgument("player id"))
+        );
+        assert_eq!(
+            AdminCommand::parse("reboot"),
+            Err(AdminCommandError::Unknown("reboot".to_string()))
+        );
+    }
+
+    #[test]
+    fn refuses_connections_over_the_limit() {
+        let admin =
+            AdminServer::bind("127.0.0.1:0".parse().unwrap(), "secret".to_string()).unwrap();
+        let addr = admin.local_addr();
+
+        let mut open: Vec<BufReader<TcpStream>> = (0..ADMIN_MAX_CONNECTIONS)
+            .map(|_| {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                writeln!(stream, "auth secret").unwrap();
+                BufReader::new(stream)
+            })
+            .collect();
+        // Each connection is being handled once it answered the authentication
+        for reader in &mut open {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            assert_eq!(
+                serde_json::from_str::<Value>(&line).unwrap(),
+                json!({ "ok": true, "result": null })
+            );
+        }
+
+        let refused = BufReader::new(TcpStream::connect(addr).unwrap())
+            .lines()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&refused).unwrap(),
+            json!({ "ok": false, "error": "too many connections" })
+        );
+    }
+
+    #[test]
+    fn kicks_and_bans_players() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut transport = ServerTransport::new(
+            test_server_config(server_addr, 8),
+            network.bind(server_addr),
+        )
+        .unwrap();
+        let client_addr: SocketAddr = "10.0.0.1:10000".parse().unwrap();
+        let mut client = SimulatedClient::new(&network, client_addr, server_addr, 1, "player");
+        for _ in 0..500 {
+            client.update();
+            transport.update(TICK_DELTA).unwrap();
+            if client.is_connected() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(client.is_connected());
+
+        let admin =
+            AdminServer::bind("127.0.0.1:0".parse().unwrap(), "secret".to_string()).unwrap();
+        let addr = admin.local_addr();
+        let operator = std::thread::spawn(move || {
+            let send = |commands: &[&str]| -> Vec<Value> {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                for command in commands {
+                    writeln!(stream, "{command}").unwrap();
+                }
+                stream.shutdown(std::net::Shutdown::Write).unwrap();
+                BufReader::new(stream)
+                    .lines()
+                    .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+                    .collect()
+            };
+
+            let denied = send(&["auth wrong"]);
+            let replies = send(&[
+                "auth secret",
+                "sessions",
+                "kick player spawn camping",
+                "kick player",
+                "ban player",
+            ]);
+            (denied, replies)
+        });
+        while !operator.is_finished() {
+            admin.handle_requests(&mut transport);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let (denied, replies) = operator.join().unwrap();
+
+        assert_eq!(denied, [json!({ "ok": false, "error": "unauthorized" })]);
+        let ok: Vec<bool> = replies
+            .iter()
+            .map(|reply| reply["ok"].as_bool().unwrap())
+            .collect();
+        assert_eq!(ok, [true, true, true, false, true]);
+        assert_eq!(replies[1]["result"]["sessions"], json!([]));
+        assert_eq!(
+            replies[1]["result"]["rate_limit"],
+            json!(transport.rate_limit_stats())
+        );
+        assert_eq!(replies[1]["result"]["rate_limit"]["blocks"], 0);
+        assert_eq!(transport.connected_clients(), 0);
+        client.update();
+        assert_eq!(client.disconnect_reason(), Some("spawn camping"));
+        assert!(!transport.is_shutting_down());
+
+        // Banned players are denied until they are unbanned
+        let connect = |transport: &mut ServerTransport, client_id| {
+            let mut client =
+                SimulatedClient::new(&network, client_addr, server_addr, client_id, "player");
+            for _ in 0..100 {
+                client.update();
+                transport.update(TICK_DELTA).unwrap();
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            transport.connected_clients()
+        };
+        assert_eq!(connect(&mut transport, 2), 0);
+        assert!(transport.unban_player("player"));
+        assert_eq!(connect(&mut transport, 3), 1);
+    }
+}
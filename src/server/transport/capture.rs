@@ -0,0 +1,369 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+
+use super::{
+    server::{
+        auth_worker::AuthResult,
+        serialize::{read_bytes, read_u16, read_u64, read_u8},
+    },
+    socket::TransportSocket,
+};
+
+/// Starts every capture file, followed by the format version.
+const CAPTURE_MAGIC: [u8; 5] = *b"DNCAP";
+const CAPTURE_VERSION: u8 = 1;
+
+/// What is needed to recreate the server exactly as it was when the capture started.
+/// The seed derives the keys of the server, so captures must be kept as secret as the keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureHeader {
+    pub current_time: Duration,
+    pub max_clients: usize,
+    pub public_addresses: Vec<SocketAddr>,
+    pub rng_seed: [u8; 32],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CaptureEvent {
+    /// [`ServerTransport::update`] was called with the duration.
+    ///
+    /// [`ServerTransport::update`]: super::transport::ServerTransport::update
+    Tick { duration: Duration },
+    Datagram {
+        direction: Direction,
+        addr: SocketAddr,
+        packet: Vec<u8>,
+    },
+    /// The auth worker finished authenticating a client. Recorded because the worker runs on its
+    /// own thread, so the update it completes in can not be reproduced otherwise.
+    Authenticated(AuthResult),
+}
+
+/// An event with the time elapsed since the capture started.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureRecord {
+    pub elapsed: Duration,
+    pub event: CaptureEvent,
+}
+
+/// Writes the events of a [`ServerTransport`] to a capture. Clones write to the same capture.
+///
+/// Failing to write is logged once and stops the capture, the server keeps running.
+///
+/// [`ServerTransport`]: super::transport::ServerTransport
+#[derive(Clone)]
+pub struct PacketCapture {
+    writer: Arc<Mutex<CaptureWriter>>,
+}
+
+struct CaptureWriter {
+    writer: Option<Box<dyn Write + Send>>,
+    start: Instant,
+}
+
+impl PacketCapture {
+    pub fn new(
+        mut writer: impl Write + Send + 'static,
+        header: &CaptureHeader,
+    ) -> io::Result<Self> {
+        writer.write_all(&CAPTURE_MAGIC)?;
+        writer.write_all(&[CAPTURE_VERSION])?;
+        writer.write_all(&(header.current_time.as_micros() as u64).to_le_bytes())?;
+        writer.write_all(&(header.max_clients as u16).to_le_bytes())?;
+        writer.write_all(&[header.public_addresses.len() as u8])?;
+        for addr in &header.public_addresses {
+            write_addr(&mut writer, *addr)?;
+        }
+        writer.write_all(&header.rng_seed)?;
+
+        Ok(Self {
+            writer: Arc::new(Mutex::new(CaptureWriter {
+                writer: Some(Box::new(writer)),
+                start: Instant::now(),
+            })),
+        })
+    }
+
+    pub fn record(&self, event: &CaptureEvent) {
+        let mut capture = self.writer.lock().unwrap();
+        let elapsed = capture.start.elapsed();
+        let Some(writer) = capture.writer.as_mut() else {
+            return;
+        };
+
+        if let Err(e) = write_record(writer, elapsed, event) {
+            tracing::error!("Failed to write packet capture, stopping it: {}", e);
+            capture.writer = None;
+        }
+    }
+
+    pub fn flush(&self) {
+        let mut capture = self.writer.lock().unwrap();
+        if let Some(writer) = capture.writer.as_mut() {
+            if let Err(e) = writer.flush() {
+                tracing::error!("Failed to flush packet capture, stopping it: {}", e);
+                capture.writer = None;
+            }
+        }
+    }
+}
+
+/// Creates a new capture file that only its owner can read, it never overwrites an existing
+/// one since captures hold the seed of the server keys.
+pub fn create_capture_file(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path)
+}
+
+impl std::fmt::Debug for PacketCapture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PacketCapture").finish_non_exhaustive()
+    }
+}
+
+/// Reads back the records written by a [`PacketCapture`].
+#[derive(Debug)]
+pub struct CaptureReader<R> {
+    reader: R,
+    header: CaptureHeader,
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let magic: [u8; 5] = read_bytes(&mut reader)?;
+        if magic != CAPTURE_MAGIC {
+            return Err(invalid_data("not a packet capture"));
+        }
+        let version = read_u8(&mut reader)?;
+        if version != CAPTURE_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported packet capture version {version}"
+            )));
+        }
+
+        let current_time = Duration::from_micros(read_u64(&mut reader)?);
+        let max_clients = read_u16(&mut reader)? as usize;
+        let public_addresses = (0..read_u8(&mut reader)?)
+            .map(|_| read_addr(&mut reader))
+            .collect::<io::Result<Vec<_>>>()?;
+        let rng_seed = read_bytes(&mut reader)?;
+
+        Ok(Self {
+            reader,
+            header: CaptureHeader {
+                current_time,
+                max_clients,
+                public_addresses,
+                rng_seed,
+            },
+        })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+
+    /// Returns the next record, `None` at the end of the capture. A record cut short, when the
+    /// server stopped while writing it, also ends the capture.
+    pub fn next_record(&mut self) -> io::Result<Option<CaptureRecord>> {
+        match read_record(&mut self.reader) {
+            Ok(record) => Ok(Some(record)),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Wraps a socket and records every datagram it sends and receives.
+#[derive(Debug)]
+pub struct CaptureSocket<S> {
+    socket: S,
+    capture: PacketCapture,
+}
+
+impl<S: TransportSocket> CaptureSocket<S> {
+    pub fn new(socket: S, capture: PacketCapture) -> Self {
+        Self { socket, capture }
+    }
+}
+
+impl<S: TransportSocket> TransportSocket for CaptureSocket<S> {
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let (len, addr) = self.socket.recv_from(buffer)?;
+        self.capture.record(&CaptureEvent::Datagram {
+            direction: Direction::Inbound,
+            addr,
+            packet: buffer[..len].to_vec(),
+        });
+
+        Ok((len, addr))
+    }
+
+    fn send_to(&mut self, packet: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.capture.record(&CaptureEvent::Datagram {
+            direction: Direction::Outbound,
+            addr,
+            packet: packet.to_vec(),
+        });
+
+        self.socket.send_to(packet, addr)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+// Record layout: elapsed micros (u64), event kind (u8), then the event fields.
+const TICK: u8 = 0;
+const INBOUND: u8 = 1;
+const OUTBOUND: u8 = 2;
+const AUTHENTICATED: u8 = 3;
+
+fn write_record(
+    writer: &mut impl Write,
+    elapsed: Duration,
+    event: &CaptureEvent,
+) -> io::Result<()> {
+    writer.write_all(&(elapsed.as_micros() as u64).to_le_bytes())?;
+    match event {
+        CaptureEvent::Tick { duration } => {
+            writer.write_all(&[TICK])?;
+            writer.write_all(&(duration.as_micros() as u64).to_le_bytes())?;
+        }
+        CaptureEvent::Datagram {
+            direction,
+            addr,
+            packet,
+        } => {
+            let kind = match direction {
+                Direction::Inbound => INBOUND,
+                Direction::Outbound => OUTBOUND,
+            };
+            writer.write_all(&[kind])?;
+            write_addr(writer, *addr)?;
+            writer.write_all(&(packet.len() as u16).to_le_bytes())?;
+            writer.write_all(packet)?;
+        }
+        CaptureEvent::Authenticated(result) => {
+            writer.write_all(&[AUTHENTICATED])?;
+            write_addr(writer, result.addr)?;
+            writer.write_all(&result.client_id.to_le_bytes())?;
+            writer.write_all(&[result.authenticated as u8])?;
+            writer.write_all(&(result.player_id.len() as u16).to_le_bytes())?;
+            writer.write_all(result.player_id.as_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_record(reader: &mut impl Read) -> io::Result<CaptureRecord> {
+    let elapsed = Duration::from_micros(read_u64(reader)?);
+    let event = match read_u8(reader)? {
+        TICK => CaptureEvent::Tick {
+            duration: Duration::from_micros(read_u64(reader)?),
+        },
+        kind @ (INBOUND | OUTBOUND) => {
+            let addr = read_addr(reader)?;
+            let mut packet = vec![0u8; read_u16(reader)? as usize];
+            reader.read_exact(&mut packet)?;
+            CaptureEvent::Datagram {
+                direction: if kind == INBOUND {
+                    Direction::Inbound
+                } else {
+                    Direction::Outbound
+                },
+                addr,
+                packet,
+            }
+        }
+        AUTHENTICATED => {
+            let addr = read_addr(reader)?;
+            let client_id = read_u64(reader)?;
+            let authenticated = read_u8(reader)? != 0;
+            let mut player_id = vec![0u8; read_u16(reader)? as usize];
+            reader.read_exact(&mut player_id)?;
+            CaptureEvent::Authenticated(AuthResult {
+                addr,
+                client_id,
+                player_id: String::from_utf8(player_id)
+                    .map_err(|_| invalid_data("invalid player id"))?,
+                authenticated,
+            })
+        }
+        kind => return Err(invalid_data(format!("unknown capture record kind {kind}"))),
+    };
+
+    Ok(CaptureRecord { elapsed, event })
+}
+
+fn write_addr(writer: &mut impl Write, addr: SocketAddr) -> io::Result<()> {
+    match addr.ip() {
+        IpAddr::V4(ip) => {
+            writer.write_all(&[4])?;
+            writer.write_all(&ip.octets())?;
+        }
+        IpAddr::V6(ip) => {
+            writer.write_all(&[6])?;
+            writer.write_all(&ip.octets())?;
+        }
+    }
+    writer.write_all(&addr.port().to_le_bytes())
+}
+
+fn read_addr(reader: &mut impl Read) -> io::Result<SocketAddr> {
+    let ip = match read_u8(reader)? {
+        4 => IpAddr::V4(Ipv4Addr::from(read_bytes::<4>(reader)?)),
+        6 => IpAddr::V6(Ipv6Addr::from(read_bytes::<16>(reader)?)),
+        family => return Err(invalid_data(format!("unknown address family {family}"))),
+    };
+
+    Ok(SocketAddr::new(ip, read_u16(reader)?))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture_files_are_private_and_never_overwritten() {
+        let path = std::env::temp_dir().join(format!("capture-{}.bin", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let file = create_capture_file(&path).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = file.metadata().unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        drop(file);
+
+        let error = create_capture_file(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap},
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::socket::TransportSocket;
+
+/// Packets are delayed by at least this much when reordered, even without latency or jitter.
+const MIN_REORDER_DELAY: Duration = Duration::from_millis(50);
+
+/// How a simulated network degrades the packets going through it, in each direction.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NetworkConditions {
+    /// One-way delay added to every packet.
+    pub latency: Duration,
+    /// Random delay between zero and this, added on top of the latency.
+    pub jitter: Duration,
+    /// Percentage of packets dropped.
+    pub loss: f64,
+    /// Percentage of packets delivered twice.
+    pub duplicate: f64,
+    /// Percentage of packets held back so the packets sent after them arrive first.
+    pub reorder: f64,
+}
+
+/// Conditions to simulate, for the listed client addresses or for every address when empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConditionerConfig {
+    pub conditions: NetworkConditions,
+    pub addresses: Vec<SocketAddr>,
+}
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct DelayedPacket {
+    deliver_at: Instant,
+    // Keeps packets with the same delivery time in sending order
+    order: u64,
+    addr: SocketAddr,
+    packet: Vec<u8>,
+}
+
+/// Wraps a socket and applies [`NetworkConditions`] to the packets it sends and receives,
+/// either to every address or only to the ones configured with [`ConditionedSocket::set_conditions`].
+#[derive(Debug)]
+pub struct ConditionedSocket<S> {
+    socket: S,
+    global: Option<NetworkConditions>,
+    per_addr: HashMap<SocketAddr, NetworkConditions>,
+    incoming: BinaryHeap<Reverse<DelayedPacket>>,
+    outgoing: BinaryHeap<Reverse<DelayedPacket>>,
+    order: u64,
+    rng: StdRng,
+    buffer: Vec<u8>,
+}
+
+impl<S: TransportSocket> ConditionedSocket<S> {
+    /// Applies the conditions to every address, or to none until [`ConditionedSocket::set_conditions`] is used.
+    pub fn new(socket: S, global: Option<NetworkConditions>) -> Self {
+        Self::with_rng(socket, global, StdRng::from_entropy())
+    }
+
+    /// Same as [`ConditionedSocket::new`], with reproducible losses and delays.
+    #[allow(dead_code)]
+    pub fn with_seed(socket: S, global: Option<NetworkConditions>, seed: u64) -> Self {
+        Self::with_rng(socket, global, StdRng::seed_from_u64(seed))
+    }
+
+    pub fn from_config(socket: S, config: ConditionerConfig) -> Self {
+        if config.addresses.is_empty() {
+            return Self::new(socket, Some(config.conditions));
+        }
+
+        let mut conditioned = Self::new(socket, None);
+        for addr in config.addresses {
+            conditioned.set_conditions(addr, config.conditions.clone());
+        }
+        conditioned
+    }
+
+    fn with_rng(socket: S, global: Option<NetworkConditions>, rng: StdRng) -> Self {
+        Self {
+            socket,
+            global,
+            per_addr: HashMap::new(),
+            incoming: BinaryHeap::new(),
+            outgoing: BinaryHeap::new(),
+            order: 0,
+            rng,
+            buffer: vec![0; u16::MAX as usize],
+        }
+    }
+
+    /// Overrides the global conditions for the address.
+    pub fn set_conditions(&mut self, addr: SocketAddr, conditions: NetworkConditions) {
+        self.per_addr.insert(addr, conditions);
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_conditions(&mut self, addr: SocketAddr) {
+        self.per_addr.remove(&addr);
+    }
+
+    fn conditions(&self, addr: SocketAddr) -> Option<&NetworkConditions> {
+        self.per_addr.get(&addr).or(self.global.as_ref())
+    }
+
+    /// Returns when each copy of the packet should be delivered, none if it is lost.
+    fn schedule(&mut self, addr: SocketAddr, now: Instant) -> Vec<Instant> {
+        let Some(conditions) = self.conditions(addr).cloned() else {
+            return vec![now];
+        };
+
+        if self.roll(conditions.loss) {
+            return vec![];
+        }
+
+        let copies = if self.roll(conditions.duplicate) {
+            2
+        } else {
+            1
+        };
+        (0..copies)
+            .map(|_| {
+                let mut delay = conditions.latency + self.random_delay(conditions.jitter);
+                if self.roll(conditions.reorder) {
+                    let max_delay = (conditions.latency + conditions.jitter).max(MIN_REORDER_DELAY);
+                    delay += self.random_delay(max_delay);
+                }
+                now + delay
+            })
+            .collect()
+    }
+
+    fn roll(&mut self, percentage: f64) -> bool {
+        percentage > 0. && self.rng.gen_range(0. ..100.) < percentage
+    }
+
+    fn random_delay(&mut self, max: Duration) -> Duration {
+        if max.is_zero() {
+            return Duration::ZERO;
+        }
+        self.rng.gen_range(Duration::ZERO..=max)
+    }
+
+    fn push(&mut self, incoming: bool, deliver_at: Instant, addr: SocketAddr, packet: &[u8]) {
+        self.order += 1;
+        let packet = Reverse(DelayedPacket {
+            deliver_at,
+            order: self.order,
+            addr,
+            packet: packet.to_vec(),
+        });
+        if incoming {
+            self.incoming.push(packet);
+        } else {
+            self.outgoing.push(packet);
+        }
+    }
+
+    /// Sends the delayed packets that are due, a failed send only loses its own packet.
+    fn flush_outgoing(&mut self, now: Instant) {
+        while self
+            .outgoing
+            .peek()
+            .is_some_and(|Reverse(p)| p.deliver_at <= now)
+        {
+            let Reverse(delayed) = self.outgoing.pop().unwrap();
+            if let Err(e) = self.socket.send_to(&delayed.packet, delayed.addr) {
+                tracing::error!("Failed to send delayed packet to {}: {}", delayed.addr, e);
+            }
+        }
+    }
+}
+
+impl<S: TransportSocket> TransportSocket for ConditionedSocket<S> {
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let now = Instant::now();
+        // Delayed packets are sent while the transport polls for new ones
+        self.flush_outgoing(now);
+
+        loop {
+            match self.socket.recv_from(&mut self.buffer) {
+                Ok((len, addr)) => {
+                    let packet = self.buffer[..len].to_vec();
+                    for deliver_at in self.schedule(addr, now) {
+                        self.push(true, deliver_at, addr, &packet);
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        match self.incoming.peek() {
+            Some(Reverse(delayed)) if delayed.deliver_at <= now => {
+                let Reverse(delayed) = self.incoming.pop().unwrap();
+                let len = delayed.packet.len().min(buffer.len());
+                buffer[..len].copy_from_slice(&delayed.packet[..len]);
+                Ok((len, delayed.addr))
+            }
+            _ => Err(io::ErrorKind::WouldBlock.into()),
+        }
+    }
+
+    fn send_to(&mut self, packet: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let now = Instant::now();
+        for deliver_at in self.schedule(addr, now) {
+            self.push(false, deliver_at, addr, packet);
+        }
+        self.flush_outgoing(now);
+
+        Ok(packet.len())
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::transport::socket::InMemoryNetwork;
+
+    use super::*;
+
+    fn received(socket: &mut impl TransportSocket) -> Vec<Vec<u8>> {
+        let mut buffer = [0u8; 16];
+        let mut packets = vec![];
+        while let Ok((len, _)) = socket.recv_from(&mut buffer) {
+            packets.push(buffer[..len].to_vec());
+        }
+        packets
+    }
+
+    #[test]
+    fn conditions_apply_per_address() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let lossy_addr: SocketAddr = "127.0.0.1:6000".parse().unwrap();
+        let slow_addr: SocketAddr = "127.0.0.1:6001".parse().unwrap();
+
+        let mut server = ConditionedSocket::with_seed(
+            network.bind(server_addr),
+            Some(NetworkConditions {
+                duplicate: 100.,
+                ..Default::default()
+            }),
+            0,
+        );
+        server.set_conditions(
+            lossy_addr,
+            NetworkConditions {
+                loss: 100.,
+                ..Default::default()
+            },
+        );
+        server.set_conditions(
+            slow_addr,
+            NetworkConditions {
+                latency: Duration::from_millis(20),
+                ..Default::default()
+            },
+        );
+
+        let mut lossy = network.bind(lossy_addr);
+        let mut slow = network.bind(slow_addr);
+        let mut other = network.bind("127.0.0.1:6002".parse().unwrap());
+
+        server.send_to(&[1], lossy_addr).unwrap();
+        server.send_to(&[2], slow_addr).unwrap();
+        server.send_to(&[3], other.local_addr().unwrap()).unwrap();
+
+        assert!(received(&mut lossy).is_empty());
+        assert!(received(&mut slow).is_empty());
+        assert_eq!(received(&mut other), vec![vec![3], vec![3]]);
+
+        std::thread::sleep(Duration::from_millis(25));
+        // Delayed packets are flushed when the server polls its socket
+        assert!(received(&mut server).is_empty());
+        assert_eq!(received(&mut slow), vec![vec![2]]);
+    }
+}
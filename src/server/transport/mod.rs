@@ -1,3 +1,11 @@
+pub(crate) mod admin;
+pub(crate) mod capture;
+pub(crate) mod conditioner;
 pub(crate) mod error;
+pub(crate) mod replay;
 pub(crate) mod server;
+pub(crate) mod signal;
+#[cfg(test)]
+pub(crate) mod simulated_client;
+pub(crate) mod socket;
 pub(crate) mod transport;
@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    config::Config,
+    constants::{MAIN_SESSION_ID, TRANSPORT_KEY_BYTES, TRANSPORT_MAX_PACKET_BYTES},
+};
+
+use super::{
+    capture::{CaptureEvent, CaptureReader, Direction},
+    server::{auth::AlwaysAcceptAuth, auth_worker::AuthResult, server::ServerConfig},
+    socket::{InMemoryNetwork, InMemorySocket, TransportSocket},
+    transport::ServerTransport,
+};
+
+/// The replayed server sits on its own in-memory network, so any address works.
+const REPLAY_SERVER_ADDR: SocketAddr =
+    SocketAddr::new(std::net::IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 5000);
+
+/// What happened while replaying a capture. Sent packets are compared with the captured ones,
+/// a mismatch means the replay diverged from the captured run.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub ticks: usize,
+    pub received: usize,
+    pub sent: usize,
+    pub mismatched: usize,
+    pub connected_clients: usize,
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} ticks, {} packets received, {} sent, {} sent packets not matching the capture, {} clients connected at the end",
+            self.ticks, self.received, self.sent, self.mismatched, self.connected_clients
+        )
+    }
+}
+
+/// Feeds a capture back into a fresh [`ServerTransport`], update by update.
+///
+/// The server is seeded like the captured one and authentications complete in the same updates,
+/// so the transport sends the same packets. With `session`, the payloads are also passed to a
+/// game session and updates are paced like the captured ones. The session runs on its own
+/// clock, so the packets it sends may not match the capture.
+///
+/// The settings that are not in the capture come from `config`, which should be the
+/// configuration the captured server ran with.
+pub fn replay(
+    reader: impl io::Read,
+    config: &Config,
+    connect_token_key: Option<[u8; TRANSPORT_KEY_BYTES]>,
+    session: bool,
+) -> io::Result<ReplayReport> {
+    let mut capture = CaptureReader::new(reader)?;
+    let header = capture.header().clone();
+
+    let network = InMemoryNetwork::new();
+    let server_config = ServerConfig {
+        max_clients: header.max_clients,
+        public_addresses: header.public_addresses,
+        rng_seed: Some(header.rng_seed),
+        // Captured authentication results are used instead of the provider
+        ..config.server_config(
+            header.current_time,
+            connect_token_key,
+            Arc::new(AlwaysAcceptAuth),
+        )
+    };
+    let mut transport = ServerTransport::new(server_config, network.bind(REPLAY_SERVER_ADDR))?;
+    config.configure_sessions(&mut transport);
+    if session {
+        transport.create_session(MAIN_SESSION_ID);
+    }
+
+    let mut replay = Replay {
+        network,
+        transport,
+        session,
+        clients: HashMap::new(),
+        tick: None,
+        auth_results: Vec::new(),
+        expected: Vec::new(),
+        report: ReplayReport::default(),
+    };
+
+    while let Some(record) = capture.next_record()? {
+        match record.event {
+            CaptureEvent::Tick { duration } => {
+                replay.run_tick()?;
+                replay.tick = Some(duration);
+            }
+            CaptureEvent::Datagram {
+                direction: Direction::Inbound,
+                addr,
+                packet,
+            } => {
+                replay.report.received += 1;
+                replay.client(addr).send_to(&packet, REPLAY_SERVER_ADDR)?;
+            }
+            CaptureEvent::Datagram {
+                direction: Direction::Outbound,
+                addr,
+                packet,
+            } => replay.expected.push((addr, packet)),
+            CaptureEvent::Authenticated(result) => replay.auth_results.push(result),
+        }
+    }
+    replay.run_tick()?;
+
+    replay.report.connected_clients = replay.transport.connected_clients();
+    Ok(replay.report)
+}
+
+struct Replay {
+    network: InMemoryNetwork,
+    transport: ServerTransport,
+    session: bool,
+    /// A socket for every captured client address, to send its packets from.
+    clients: HashMap<SocketAddr, InMemorySocket>,
+    /// The update being replayed, with the events captured since it started.
+    tick: Option<Duration>,
+    auth_results: Vec<AuthResult>,
+    expected: Vec<(SocketAddr, Vec<u8>)>,
+    report: ReplayReport,
+}
+
+impl Replay {
+    fn client(&mut self, addr: SocketAddr) -> &mut InMemorySocket {
+        self.clients
+            .entry(addr)
+            .or_insert_with(|| self.network.bind(addr))
+    }
+
+    fn run_tick(&mut self) -> io::Result<()> {
+        // Packets sent before the first update
+        let Some(duration) = self.tick.take() else {
+            self.check_sent();
+            return Ok(());
+        };
+
+        self.report.ticks += 1;
+        self.transport
+            .replay_update(duration, std::mem::take(&mut self.auth_results))
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        self.transport.send_packets();
+        if self.session {
+            std::thread::sleep(duration);
+        }
+
+        self.check_sent();
+        Ok(())
+    }
+
+    fn check_sent(&mut self) {
+        let mut expected = std::mem::take(&mut self.expected);
+        let mut buffer = [0u8; TRANSPORT_MAX_PACKET_BYTES];
+        for (&addr, socket) in self.clients.iter_mut() {
+            while let Ok((len, _)) = socket.recv_from(&mut buffer) {
+                self.report.sent += 1;
+                let packet = &buffer[..len];
+                match expected.iter().position(|(a, p)| *a == addr && p == packet) {
+                    Some(index) => {
+                        expected.remove(index);
+                    }
+                    None => self.report.mismatched += 1,
+                }
+            }
+        }
+        self.report.mismatched += expected.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::{
+        constants::TICK_DELTA,
+        server::transport::simulated_client::{test_server_config, SimulatedClient},
+    };
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn replays_captured_connections() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let buffer = SharedBuffer::default();
+        let mut transport = ServerTransport::with_capture(
+            test_server_config(server_addr, 8),
+            network.bind(server_addr),
+            buffer.clone(),
+        )
+        .unwrap();
+
+        let mut clients: Vec<SimulatedClient> = (0..5)
+            .map(|i| {
+                let addr = SocketAddr::new("10.0.0.1".parse().unwrap(), 10_000 + i);
+                SimulatedClient::new(
+                    &network,
+                    addr,
+                    server_addr,
+                    i as u64 + 1,
+                    &format!("player{i}"),
+                )
+            })
+            .collect();
+        for _ in 0..500 {
+            clients.iter_mut().for_each(|client| client.update());
+            transport.update(TICK_DELTA).unwrap();
+            if transport.connected_clients() == clients.len() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(transport.connected_clients(), clients.len());
+
+        let capture = buffer.0.lock().unwrap().clone();
+        let report = replay(capture.as_slice(), &Config::default(), None, false).unwrap();
+
+        assert_eq!(report.connected_clients, clients.len());
+        assert!(report.received > 0 && report.sent > 0);
+        assert_eq!(report.mismatched, 0);
+    }
+}
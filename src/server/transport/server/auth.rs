@@ -0,0 +1,297 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    future::Future,
+    io,
+    pin::Pin,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub type AuthFuture = Pin<Box<dyn Future<Output = bool> + Send>>;
+
+/// Checks the session ticket a client sends in its application level connection request.
+/// Implementations log why a player was rejected, the client is only told that authentication failed.
+pub trait AuthProvider: Send + Sync + fmt::Debug {
+    fn authenticate(&self, player_id: String, session_ticket: String) -> AuthFuture;
+}
+
+/// Validates the ticket against the PlayFab server API, the ticket must belong to the player.
+pub struct PlayFabAuth {
+    api_url: String,
+    api_key: String,
+    client: reqwest::Client,
+}
+
+/// Accepts only the listed players, the session ticket is ignored.
+#[derive(Debug)]
+pub struct AllowListAuth {
+    players: HashSet<String>,
+}
+
+/// Accepts tickets signed with a key shared with the backend.
+///
+/// A ticket is `<expire_timestamp>:<hex HMAC-SHA256 of "<player_id>:<expire_timestamp>">`,
+/// with the expire timestamp in seconds since the unix epoch.
+pub struct HmacTicketAuth {
+    key: Vec<u8>,
+}
+
+/// Accepts every player, only meant for local development.
+#[derive(Debug)]
+pub struct AlwaysAcceptAuth;
+
+impl PlayFabAuth {
+    pub fn new(api_url: String, api_key: String) -> Self {
+        Self {
+            api_url,
+            api_key,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+impl AuthProvider for PlayFabAuth {
+    fn authenticate(&self, player_id: String, session_ticket: String) -> AuthFuture {
+        let request = self
+            .client
+            .post(format!("{}/Server/AuthenticateSessionTicket", self.api_url))
+            .header("X-SecretKey", &self.api_key)
+            .json(&serde_json::json!({
+                "SessionTicket": session_ticket,
+            }));
+
+        Box::pin(async move {
+            let response = match request.send().await {
+                Ok(response) => response,
+                Err(e) => {
+                    tracing::error!("Failed to authenticate player: {}", e);
+                    return false;
+                }
+            };
+
+            if !response.status().is_success() {
+                tracing::error!("Failed to authenticate player: {}", response.status());
+                return false;
+            }
+
+            match response.json::<serde_json::Value>().await {
+                Ok(response) => ticket_belongs_to(&response, &player_id),
+                Err(e) => {
+                    tracing::error!("Failed to authenticate player: {}", e);
+                    false
+                }
+            }
+        })
+    }
+}
+
+/// Checks that an `AuthenticateSessionTicket` response is for an unexpired ticket of the player.
+fn ticket_belongs_to(response: &serde_json::Value, player_id: &str) -> bool {
+    let data = &response["data"];
+    if data["IsSessionTicketExpired"].as_bool() == Some(true) {
+        tracing::debug!("Session ticket of player {} expired", player_id);
+        return false;
+    }
+
+    match data["UserInfo"]["PlayFabId"].as_str() {
+        Some(playfab_id) if playfab_id == player_id => true,
+        playfab_id => {
+            tracing::debug!(
+                "Session ticket of {:?} used by player {}",
+                playfab_id,
+                player_id
+            );
+            false
+        }
+    }
+}
+
+impl fmt::Debug for PlayFabAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PlayFabAuth")
+            .field("api_url", &self.api_url)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AllowListAuth {
+    pub fn new(players: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            players: players.into_iter().collect(),
+        }
+    }
+}
+
+impl AuthProvider for AllowListAuth {
+    fn authenticate(&self, player_id: String, _session_ticket: String) -> AuthFuture {
+        let allowed = self.players.contains(&player_id);
+        if !allowed {
+            tracing::debug!("Player {} is not in the allow list", player_id);
+        }
+
+        Box::pin(async move { allowed })
+    }
+}
+
+impl HmacTicketAuth {
+    pub fn new(key: Vec<u8>) -> Self {
+        Self { key }
+    }
+
+    /// Returns the ticket the backend would issue for the player.
+    #[allow(dead_code)] // Tickets are issued by the backend, the server only verifies them
+    pub fn ticket(&self, player_id: &str, expire_timestamp: u64) -> String {
+        let mac = self
+            .mac(player_id, expire_timestamp)
+            .finalize()
+            .into_bytes();
+        format!("{}:{}", expire_timestamp, hex::encode(mac))
+    }
+
+    fn verify(&self, player_id: &str, session_ticket: &str, current_time: Duration) -> bool {
+        let Some((expire_timestamp, signature)) = session_ticket.split_once(':') else {
+            return false;
+        };
+        let Ok(expire_timestamp) = expire_timestamp.parse::<u64>() else {
+            return false;
+        };
+        let Ok(signature) = hex::decode(signature) else {
+            return false;
+        };
+
+        expire_timestamp > current_time.as_secs()
+            && self
+                .mac(player_id, expire_timestamp)
+                .verify_slice(&signature)
+                .is_ok()
+    }
+
+    fn mac(&self, player_id: &str, expire_timestamp: u64) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.key).expect("HMAC can take a key of any size");
+        mac.update(format!("{}:{}", player_id, expire_timestamp).as_bytes());
+        mac
+    }
+}
+
+impl AuthProvider for HmacTicketAuth {
+    fn authenticate(&self, player_id: String, session_ticket: String) -> AuthFuture {
+        let current_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let valid = self.verify(&player_id, &session_ticket, current_time);
+        if !valid {
+            tracing::debug!("Invalid session ticket for player {}", player_id);
+        }
+
+        Box::pin(async move { valid })
+    }
+}
+
+impl fmt::Debug for HmacTicketAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HmacTicketAuth").finish_non_exhaustive()
+    }
+}
+
+impl AuthProvider for AlwaysAcceptAuth {
+    fn authenticate(&self, _player_id: String, _session_ticket: String) -> AuthFuture {
+        Box::pin(async { true })
+    }
+}
+
+/// Builds the provider selected by `AUTH_PROVIDER`: `playfab` (default), `allow_list`,
+/// `hmac_ticket` or `always_accept`.
+///
+/// - `playfab` reads `PLAYFAB_API_URL` and `PLAYFAB_API_KEY`.
+/// - `allow_list` reads the comma separated player ids in `AUTH_ALLOW_LIST`.
+/// - `hmac_ticket` reads the hex encoded key in `AUTH_TICKET_KEY`.
+pub fn auth_provider_from_env() -> io::Result<Arc<dyn AuthProvider>> {
+    let provider = std::env::var("AUTH_PROVIDER").unwrap_or_else(|_| "playfab".to_string());
+
+    let provider: Arc<dyn AuthProvider> = match provider.as_str() {
+        "playfab" => Arc::new(PlayFabAuth::new(
+            required_env("PLAYFAB_API_URL")?,
+            required_env("PLAYFAB_API_KEY")?,
+        )),
+        "allow_list" => Arc::new(AllowListAuth::new(
+            required_env("AUTH_ALLOW_LIST")?
+                .split(',')
+                .map(|player_id| player_id.trim().to_string())
+                .filter(|player_id| !player_id.is_empty()),
+        )),
+        "hmac_ticket" => {
+            let key = hex::decode(required_env("AUTH_TICKET_KEY")?.trim()).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("AUTH_TICKET_KEY must be hex encoded: {e}"),
+                )
+            })?;
+            Arc::new(HmacTicketAuth::new(key))
+        }
+        "always_accept" => {
+            tracing::warn!("Authentication is disabled, every player will be accepted");
+            Arc::new(AlwaysAcceptAuth)
+        }
+        other => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown AUTH_PROVIDER {other}"),
+            ))
+        }
+    };
+
+    Ok(provider)
+}
+
+fn required_env(name: &str) -> io::Result<String> {
+    std::env::var(name).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{name} must be set for the selected AUTH_PROVIDER"),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_ticket() {
+        let auth = HmacTicketAuth::new(b"secret".to_vec());
+        let now = Duration::from_secs(1_000);
+        let ticket = auth.ticket("player1", 1_060);
+
+        assert!(auth.verify("player1", &ticket, now));
+        assert!(!auth.verify("player2", &ticket, now));
+        assert!(!auth.verify("player1", &ticket, Duration::from_secs(1_060)));
+        assert!(!auth.verify("player1", "1060:00", now));
+        assert!(!HmacTicketAuth::new(b"other".to_vec()).verify("player1", &ticket, now));
+    }
+
+    #[test]
+    fn playfab_ticket_must_belong_to_the_player() {
+        let response = |playfab_id: &str, expired: bool| {
+            serde_json::json!({
+                "code": 200,
+                "status": "OK",
+                "data": {
+                    "UserInfo": { "PlayFabId": playfab_id },
+                    "IsSessionTicketExpired": expired,
+                },
+            })
+        };
+
+        assert!(ticket_belongs_to(&response("A1B2", false), "A1B2"));
+        assert!(!ticket_belongs_to(&response("A1B2", false), "C3D4"));
+        assert!(!ticket_belongs_to(&response("A1B2", true), "A1B2"));
+        assert!(!ticket_belongs_to(&serde_json::json!({}), "A1B2"));
+    }
+}
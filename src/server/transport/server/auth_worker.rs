@@ -0,0 +1,146 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+use tokio::sync::{mpsc, Semaphore};
+
+use serde::Deserialize;
+
+use crate::{
+    config::duration_secs,
+    constants::{TRANSPORT_AUTH_MAX_CONCURRENT, TRANSPORT_AUTH_QUEUE_SIZE, TRANSPORT_AUTH_TIMEOUT},
+};
+
+use super::auth::AuthProvider;
+
+/// A player waiting for its session ticket to be checked.
+#[derive(Debug)]
+pub struct AuthRequest {
+    pub addr: SocketAddr,
+    pub client_id: u64,
+    pub player_id: String,
+    pub session_ticket: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthResult {
+    pub addr: SocketAddr,
+    pub client_id: u64,
+    pub player_id: String,
+    pub authenticated: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthWorkerConfig {
+    /// Requests waiting to be started, submitting more fails until the queue drains.
+    pub queue_size: usize,
+    /// Requests that can wait on the auth provider at the same time.
+    pub max_concurrent: usize,
+    /// Requests taking longer than this fail.
+    #[serde(rename = "timeout_secs", deserialize_with = "duration_secs")]
+    pub timeout: Duration,
+}
+
+impl Default for AuthWorkerConfig {
+    fn default() -> Self {
+        Self {
+            queue_size: TRANSPORT_AUTH_QUEUE_SIZE,
+            max_concurrent: TRANSPORT_AUTH_MAX_CONCURRENT,
+            timeout: TRANSPORT_AUTH_TIMEOUT,
+        }
+    }
+}
+
+/// Runs the authentication of every pending client on one long-lived thread, so the
+/// transport never blocks on the auth provider. Results are polled with [`AuthWorker::try_recv`].
+#[derive(Debug)]
+pub struct AuthWorker {
+    requests_tx: mpsc::Sender<AuthRequest>,
+    results_rx: Receiver<AuthResult>,
+}
+
+impl AuthWorker {
+    pub fn new(provider: Arc<dyn AuthProvider>, config: AuthWorkerConfig) -> Self {
+        let (requests_tx, requests_rx) = mpsc::channel(config.queue_size);
+        let (results_tx, results_rx) = unbounded();
+
+        std::thread::Builder::new()
+            .name("auth-worker".to_string())
+            .spawn(move || run(provider, config, requests_rx, results_tx))
+            .expect("failed to spawn the auth worker thread");
+
+        Self {
+            requests_tx,
+            results_rx,
+        }
+    }
+
+    /// Queues the request, returns it back if the queue is full.
+    pub fn submit(&self, request: AuthRequest) -> Result<(), AuthRequest> {
+        self.requests_tx.try_send(request).map_err(|e| match e {
+            mpsc::error::TrySendError::Full(request) => request,
+            mpsc::error::TrySendError::Closed(request) => {
+                tracing::error!("Auth worker stopped, can not authenticate players");
+                request
+            }
+        })
+    }
+
+    /// Returns the next finished authentication, if any.
+    pub fn try_recv(&self) -> Option<AuthResult> {
+        match self.results_rx.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => {
+                tracing::error!("Auth worker stopped, can not authenticate players");
+                None
+            }
+        }
+    }
+}
+
+fn run(
+    provider: Arc<dyn AuthProvider>,
+    config: AuthWorkerConfig,
+    mut requests_rx: mpsc::Receiver<AuthRequest>,
+    results_tx: Sender<AuthResult>,
+) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build the auth worker runtime");
+
+    let semaphore = Arc::new(Semaphore::new(config.max_concurrent));
+
+    runtime.block_on(async move {
+        // Ends once the worker is dropped and the queue is drained
+        while let Some(request) = requests_rx.recv().await {
+            let Ok(permit) = semaphore.clone().acquire_owned().await else {
+                break;
+            };
+
+            let authentication =
+                provider.authenticate(request.player_id.clone(), request.session_ticket.clone());
+            let results_tx = results_tx.clone();
+            let timeout = config.timeout;
+
+            tokio::spawn(async move {
+                let authenticated = match tokio::time::timeout(timeout, authentication).await {
+                    Ok(authenticated) => authenticated,
+                    Err(_) => {
+                        tracing::warn!("Authentication of player {} timed out", request.player_id);
+                        false
+                    }
+                };
+                drop(permit);
+
+                let _ = results_tx.send(AuthResult {
+                    addr: request.addr,
+                    client_id: request.client_id,
+                    player_id: request.player_id,
+                    authenticated,
+                });
+            });
+        }
+    });
+}
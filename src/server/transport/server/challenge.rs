@@ -0,0 +1,106 @@
+use std::{net::SocketAddr, time::Duration};
+
+use hmac::{Hmac, Mac};
+use rand::{CryptoRng, RngCore};
+use sha2::Sha256;
+
+use crate::constants::{TRANSPORT_COOKIE_BUCKET, TRANSPORT_COOKIE_BYTES};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Generates and verifies the cookies used in the connection challenge.
+///
+/// A cookie is an HMAC over the source address, the client identifier and a time bucket,
+/// so the server can check a [`ConnectionResponse`] without keeping any state for clients
+/// that have not answered the challenge yet.
+///
+/// [`ConnectionResponse`]: super::packet::Packet::ConnectionResponse
+pub struct ChallengeCookies {
+    key: [u8; 32],
+}
+
+impl ChallengeCookies {
+    /// Creates a cookie generator with a random key.
+    pub fn new(rng: &mut (impl RngCore + CryptoRng)) -> Self {
+        let mut key = [0u8; 32];
+        rng.fill_bytes(&mut key);
+
+        Self { key }
+    }
+
+    /// Returns the cookie for the client at the current time.
+    pub fn generate(
+        &self,
+        addr: SocketAddr,
+        client_identifier: u64,
+        current_time: Duration,
+    ) -> [u8; TRANSPORT_COOKIE_BYTES] {
+        self.mac(addr, client_identifier, time_bucket(current_time))
+            .finalize()
+            .into_bytes()
+            .into()
+    }
+
+    /// Returns whether the cookie was generated for this client in the current or previous time bucket.
+    pub fn verify(
+        &self,
+        addr: SocketAddr,
+        client_identifier: u64,
+        current_time: Duration,
+        cookie: &[u8; TRANSPORT_COOKIE_BYTES],
+    ) -> bool {
+        let bucket = time_bucket(current_time);
+
+        [bucket, bucket.saturating_sub(1)]
+            .into_iter()
+            .any(|bucket| {
+                self.mac(addr, client_identifier, bucket)
+                    .verify_slice(cookie)
+                    .is_ok()
+            })
+    }
+
+    fn mac(&self, addr: SocketAddr, client_identifier: u64, bucket: u64) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.key).expect("HMAC can take a key of any size");
+        match addr {
+            SocketAddr::V4(addr) => mac.update(&addr.ip().octets()),
+            SocketAddr::V6(addr) => mac.update(&addr.ip().octets()),
+        }
+        mac.update(&addr.port().to_le_bytes());
+        mac.update(&client_identifier.to_le_bytes());
+        mac.update(&bucket.to_le_bytes());
+        mac
+    }
+}
+
+impl std::fmt::Debug for ChallengeCookies {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChallengeCookies").finish_non_exhaustive()
+    }
+}
+
+fn time_bucket(current_time: Duration) -> u64 {
+    current_time.as_secs() / TRANSPORT_COOKIE_BUCKET.as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cookie_is_bound_to_client_and_time() {
+        let cookies = ChallengeCookies::new(&mut rand::thread_rng());
+        let addr: SocketAddr = "127.0.0.1:4000".parse().unwrap();
+        let now = Duration::from_secs(1_000);
+
+        let cookie = cookies.generate(addr, 7, now);
+        assert!(cookies.verify(addr, 7, now, &cookie));
+        assert!(cookies.verify(addr, 7, now + TRANSPORT_COOKIE_BUCKET, &cookie));
+
+        assert!(!cookies.verify("127.0.0.1:4001".parse().unwrap(), 7, now, &cookie));
+        assert!(!cookies.verify(addr, 8, now, &cookie));
+        assert!(!cookies.verify(addr, 7, now + TRANSPORT_COOKIE_BUCKET * 2, &cookie));
+        assert!(!ChallengeCookies::new(&mut rand::thread_rng()).verify(addr, 7, now, &cookie));
+    }
+}
@@ -0,0 +1,239 @@
+use chacha20poly1305::{aead::AeadInPlace, ChaCha20Poly1305, KeyInit, Nonce, Tag};
+use hkdf::Hkdf;
+use rand::{CryptoRng, RngCore};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use crate::constants::{TRANSPORT_KEY_BYTES, TRANSPORT_MAC_BYTES};
+
+use super::error::TransportServerError;
+
+pub type Key = [u8; TRANSPORT_KEY_BYTES];
+
+const REPLAY_PROTECTION_BUFFER_SIZE: usize = 256;
+
+/// One side of the X25519 key exchange done during the handshake.
+pub struct KeyExchange {
+    secret: EphemeralSecret,
+    public_key: PublicKey,
+}
+
+/// Symmetric keys of a connection, one for each direction.
+#[derive(Clone)]
+pub struct ConnectionKeys {
+    pub send_key: Key,
+    pub receive_key: Key,
+}
+
+/// Keeps track of the sequences received from a connection so replayed packets can be discarded.
+#[derive(Debug, Clone)]
+pub struct ReplayProtection {
+    most_recent_sequence: u64,
+    received_packet: [u64; REPLAY_PROTECTION_BUFFER_SIZE],
+}
+
+impl KeyExchange {
+    pub fn new(rng: &mut (impl RngCore + CryptoRng)) -> Self {
+        let secret = EphemeralSecret::random_from_rng(rng);
+        let public_key = PublicKey::from(&secret);
+
+        Self { secret, public_key }
+    }
+
+    pub fn public_key(&self) -> Key {
+        self.public_key.to_bytes()
+    }
+
+    /// Finishes the exchange with the public key sent by the client and derives the server keys.
+    /// Returns `None` if the client key is a low order point.
+    pub fn server_keys(self, client_public_key: &Key) -> Option<ConnectionKeys> {
+        let server_public_key = self.public_key();
+        let (client_to_server, server_to_client) =
+            self.derive(client_public_key, client_public_key, &server_public_key)?;
+
+        Some(ConnectionKeys {
+            send_key: server_to_client,
+            receive_key: client_to_server,
+        })
+    }
+
+    /// Client side of [`KeyExchange::server_keys`].
+    #[cfg(test)]
+    pub fn client_keys(self, server_public_key: &Key) -> Option<ConnectionKeys> {
+        let client_public_key = self.public_key();
+        let (client_to_server, server_to_client) =
+            self.derive(server_public_key, &client_public_key, server_public_key)?;
+
+        Some(ConnectionKeys {
+            send_key: client_to_server,
+            receive_key: server_to_client,
+        })
+    }
+
+    fn derive(
+        self,
+        peer_public_key: &Key,
+        client_public_key: &Key,
+        server_public_key: &Key,
+    ) -> Option<(Key, Key)> {
+        let shared_secret = self
+            .secret
+            .diffie_hellman(&PublicKey::from(*peer_public_key));
+        if !shared_secret.was_contributory() {
+            return None;
+        }
+
+        let mut salt = [0u8; TRANSPORT_KEY_BYTES * 2];
+        salt[..TRANSPORT_KEY_BYTES].copy_from_slice(client_public_key);
+        salt[TRANSPORT_KEY_BYTES..].copy_from_slice(server_public_key);
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared_secret.as_bytes());
+
+        let mut client_to_server = [0u8; TRANSPORT_KEY_BYTES];
+        let mut server_to_client = [0u8; TRANSPORT_KEY_BYTES];
+        hkdf.expand(b"denaria client to server", &mut client_to_server)
+            .ok()?;
+        hkdf.expand(b"denaria server to client", &mut server_to_client)
+            .ok()?;
+
+        Some((client_to_server, server_to_client))
+    }
+}
+
+impl std::fmt::Debug for KeyExchange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyExchange")
+            .field("public_key", &self.public_key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl std::fmt::Debug for ConnectionKeys {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionKeys").finish_non_exhaustive()
+    }
+}
+
+/// Encrypts the buffer in place and returns the authentication tag.
+/// The sequence is used as nonce, so it must never repeat for the same key.
+pub fn seal(
+    key: &Key,
+    sequence: u64,
+    associated_data: &[u8],
+    buffer: &mut [u8],
+) -> Result<[u8; TRANSPORT_MAC_BYTES], TransportServerError> {
+    let cipher = ChaCha20Poly1305::new(key.into());
+    let tag = cipher
+        .encrypt_in_place_detached(&nonce(sequence), associated_data, buffer)
+        .map_err(|_| TransportServerError::CryptoError)?;
+
+    Ok(tag.into())
+}
+
+/// Decrypts the buffer in place, fails if the packet was not sealed with the key or was tampered with.
+pub fn open(
+    key: &Key,
+    sequence: u64,
+    associated_data: &[u8],
+    buffer: &mut [u8],
+    tag: &[u8; TRANSPORT_MAC_BYTES],
+) -> Result<(), TransportServerError> {
+    let cipher = ChaCha20Poly1305::new(key.into());
+    cipher
+        .decrypt_in_place_detached(
+            &nonce(sequence),
+            associated_data,
+            buffer,
+            Tag::from_slice(tag),
+        )
+        .map_err(|_| TransportServerError::CryptoError)
+}
+
+fn nonce(sequence: u64) -> Nonce {
+    let mut nonce = [0u8; 12];
+    nonce[4..].copy_from_slice(&sequence.to_le_bytes());
+    nonce.into()
+}
+
+impl Default for ReplayProtection {
+    fn default() -> Self {
+        Self {
+            most_recent_sequence: 0,
+            received_packet: [u64::MAX; REPLAY_PROTECTION_BUFFER_SIZE],
+        }
+    }
+}
+
+impl ReplayProtection {
+    pub fn most_recent_sequence(&self) -> u64 {
+        self.most_recent_sequence
+    }
+
+    pub fn already_received(&self, sequence: u64) -> bool {
+        if sequence.saturating_add(REPLAY_PROTECTION_BUFFER_SIZE as u64)
+            <= self.most_recent_sequence
+        {
+            return true;
+        }
+
+        let index = sequence as usize % REPLAY_PROTECTION_BUFFER_SIZE;
+        if self.received_packet[index] == u64::MAX {
+            return false;
+        }
+
+        self.received_packet[index] >= sequence
+    }
+
+    pub fn advance_sequence(&mut self, sequence: u64) {
+        if sequence > self.most_recent_sequence {
+            self.most_recent_sequence = sequence;
+        }
+
+        let index = sequence as usize % REPLAY_PROTECTION_BUFFER_SIZE;
+        self.received_packet[index] = sequence;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exchanged_keys_match() {
+        let server = KeyExchange::new(&mut rand::thread_rng());
+        let client = KeyExchange::new(&mut rand::thread_rng());
+        let server_public_key = server.public_key();
+
+        let server_keys = server.server_keys(&client.public_key()).unwrap();
+        let client_keys = client.client_keys(&server_public_key).unwrap();
+
+        assert_eq!(server_keys.send_key, client_keys.receive_key);
+        assert_eq!(server_keys.receive_key, client_keys.send_key);
+        assert_ne!(server_keys.send_key, server_keys.receive_key);
+    }
+
+    #[test]
+    fn tampered_packets_are_rejected() {
+        let key = [7u8; TRANSPORT_KEY_BYTES];
+        let mut buffer = *b"hello";
+
+        let tag = seal(&key, 1, b"header", &mut buffer).unwrap();
+        assert!(open(&key, 1, b"other", &mut buffer.clone(), &tag).is_err());
+        assert!(open(&key, 2, b"header", &mut buffer.clone(), &tag).is_err());
+
+        open(&key, 1, b"header", &mut buffer, &tag).unwrap();
+        assert_eq!(&buffer, b"hello");
+    }
+
+    #[test]
+    fn replay_protection() {
+        let mut replay_protection = ReplayProtection::default();
+
+        for sequence in 0..REPLAY_PROTECTION_BUFFER_SIZE as u64 * 2 {
+            assert!(!replay_protection.already_received(sequence));
+            replay_protection.advance_sequence(sequence);
+            assert!(replay_protection.already_received(sequence));
+        }
+
+        assert!(replay_protection.already_received(0));
+    }
+}
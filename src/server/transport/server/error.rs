@@ -18,6 +18,10 @@ pub enum TransportServerError {
     PayloadAboveLimit,
     /// The processed packet is duplicated
     DuplicatedSequence,
+    /// The packet could not be encrypted, or failed verification when decrypting.
+    CryptoError,
+    /// The connect token signature or contents are invalid.
+    InvalidConnectToken,
     /// No more host are available in the connect token..
     NoMoreServers,
     /// The connect token has expired.
@@ -49,7 +53,9 @@ impl fmt::Display for TransportServerError {
                 TRANSPORT_MAX_PAYLOAD_BYTES
             ),
             Expired => write!(fmt, "connection expired"),
+            InvalidConnectToken => write!(fmt, "invalid connect token"),
             DuplicatedSequence => write!(fmt, "sequence already received"),
+            CryptoError => write!(fmt, "failed to encrypt or decrypt packet"),
             Disconnected(reason) => write!(fmt, "disconnected: {}", reason),
             NoMoreServers => write!(fmt, "client has no more servers to connect"),
             NotInHostList => write!(fmt, "token does not contain the server address"),
@@ -1,4 +1,10 @@
+pub(crate) mod auth;
+pub(crate) mod auth_worker;
+pub(crate) mod challenge;
+pub(crate) mod crypto;
 pub(crate) mod error;
 pub(crate) mod packet;
+pub(crate) mod rate_limit;
 pub(crate) mod serialize;
 pub(crate) mod server;
+pub(crate) mod token;
@@ -1,16 +1,45 @@
-use std::io::{self, Cursor, Write};
+use std::{
+    fmt,
+    io::{self, Cursor, Write},
+};
 
-use super::{error::TransportServerError, serialize::*};
+use crate::constants::{TRANSPORT_COOKIE_BYTES, TRANSPORT_KEY_BYTES, TRANSPORT_MAC_BYTES};
+
+use super::{
+    crypto::{self, Key, ReplayProtection},
+    error::TransportServerError,
+    serialize::*,
+};
+
+/// Encrypted packets start with the packet type, the client identifier and the packet sequence.
+const SEALED_HEADER_BYTES: usize = 1 + 8 + 8;
 
 #[derive(Debug)]
 #[repr(u8)]
 pub enum PacketType {
     ConnectionRequest = 85,
+    ConnectionChallenge = 86,
+    ConnectionResponse = 87,
+    KeyExchange = 88,
+    ConnectionDenied = 89,
     Data = 1,
     Disconnect = 2,
     KeepAlive = 3,
 }
 
+/// Why the server refused a connection, sent to the client in a [`Packet::ConnectionDenied`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DenialReason {
+    ServerFull = 1,
+    AuthFailed = 2,
+    Banned = 3,
+    VersionMismatch = 4,
+    TokenExpired = 5,
+    AlreadyConnected = 6,
+    ShuttingDown = 7,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 #[allow(clippy::large_enum_variant)] // TODO: Consider boxing types
 pub enum Packet<'a> {
@@ -18,6 +47,27 @@ pub enum Packet<'a> {
         connection_prefix: [u8; 3],
         connection_side_id: u8,
         client_identifier: u64,
+        /// Signed connect token, empty when the client authenticates with its first payload.
+        connect_token: &'a [u8],
+    },
+    ConnectionChallenge {
+        client_identifier: u64,
+        cookie: [u8; TRANSPORT_COOKIE_BYTES],
+    },
+    ConnectionResponse {
+        client_identifier: u64,
+        cookie: [u8; TRANSPORT_COOKIE_BYTES],
+        public_key: [u8; TRANSPORT_KEY_BYTES],
+        connect_token: &'a [u8],
+    },
+    KeyExchange {
+        client_identifier: u64,
+        public_key: [u8; TRANSPORT_KEY_BYTES],
+    },
+    /// Sent in plain text, clients should only honour it while they are connecting.
+    ConnectionDenied {
+        client_identifier: u64,
+        reason: DenialReason,
     },
     KeepAlive {
         client_identifier: u64,
@@ -26,8 +76,10 @@ pub enum Packet<'a> {
         client_identifier: u64,
         payload: &'a [u8],
     },
+    /// The reason is UTF-8 text for the player, empty when the client disconnects.
     Disconnect {
         client_identifier: u64,
+        reason: &'a [u8],
     },
 }
 
@@ -40,6 +92,10 @@ impl PacketType {
             3 => KeepAlive,
             2 => Disconnect,
             85 => ConnectionRequest,
+            86 => ConnectionChallenge,
+            87 => ConnectionResponse,
+            88 => KeyExchange,
+            89 => ConnectionDenied,
             _ => return Err(TransportServerError::InvalidPacketType),
         };
         Ok(packet_type)
@@ -53,15 +109,70 @@ impl PacketType {
             KeepAlive => 3,
             Disconnect => 2,
             ConnectionRequest => 85,
+            ConnectionChallenge => 86,
+            ConnectionResponse => 87,
+            KeyExchange => 88,
+            ConnectionDenied => 89,
         };
         Ok(packet_value)
     }
+
+    /// Packets sent once the keys are exchanged are encrypted with the connection keys.
+    pub fn is_sealed(&self) -> bool {
+        matches!(
+            self,
+            PacketType::Data | PacketType::KeepAlive | PacketType::Disconnect
+        )
+    }
+}
+
+impl DenialReason {
+    fn from_u8(value: u8) -> Result<Self, io::Error> {
+        use DenialReason::*;
+
+        let reason = match value {
+            1 => ServerFull,
+            2 => AuthFailed,
+            3 => Banned,
+            4 => VersionMismatch,
+            5 => TokenExpired,
+            6 => AlreadyConnected,
+            7 => ShuttingDown,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid connection denied reason",
+                ))
+            }
+        };
+        Ok(reason)
+    }
+}
+
+impl fmt::Display for DenialReason {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use DenialReason::*;
+
+        match *self {
+            ServerFull => write!(fmt, "server is full"),
+            AuthFailed => write!(fmt, "authentication failed"),
+            Banned => write!(fmt, "player is banned"),
+            VersionMismatch => write!(fmt, "protocol version mismatch"),
+            TokenExpired => write!(fmt, "connect token expired"),
+            AlreadyConnected => write!(fmt, "client is already connected"),
+            ShuttingDown => write!(fmt, "server is shutting down"),
+        }
+    }
 }
 
 impl<'a> Packet<'a> {
     pub fn packet_type(&self) -> PacketType {
         match self {
             Packet::ConnectionRequest { .. } => PacketType::ConnectionRequest,
+            Packet::ConnectionChallenge { .. } => PacketType::ConnectionChallenge,
+            Packet::ConnectionResponse { .. } => PacketType::ConnectionResponse,
+            Packet::KeyExchange { .. } => PacketType::KeyExchange,
+            Packet::ConnectionDenied { .. } => PacketType::ConnectionDenied,
             Packet::KeepAlive { .. } => PacketType::KeepAlive,
             Packet::Data { .. } => PacketType::Data,
             Packet::Disconnect { .. } => PacketType::Disconnect,
@@ -72,29 +183,85 @@ impl<'a> Packet<'a> {
         self.packet_type() as u8
     }
 
+    pub fn client_identifier(&self) -> u64 {
+        match self {
+            Packet::ConnectionRequest {
+                client_identifier, ..
+            }
+            | Packet::ConnectionChallenge {
+                client_identifier, ..
+            }
+            | Packet::ConnectionResponse {
+                client_identifier, ..
+            }
+            | Packet::KeyExchange {
+                client_identifier, ..
+            }
+            | Packet::ConnectionDenied {
+                client_identifier, ..
+            }
+            | Packet::KeepAlive { client_identifier }
+            | Packet::Data {
+                client_identifier, ..
+            }
+            | Packet::Disconnect {
+                client_identifier, ..
+            } => *client_identifier,
+        }
+    }
+
     fn write(&self, writer: &mut impl io::Write) -> Result<(), io::Error> {
         match self {
             Packet::ConnectionRequest {
                 connection_prefix,
                 connection_side_id,
                 client_identifier,
+                connect_token,
             } => {
                 writer.write_all(connection_prefix)?;
                 writer.write_all(&connection_side_id.to_le_bytes())?;
                 writer.write_all(&client_identifier.to_le_bytes())?;
+                write_connect_token(writer, connect_token)?;
             }
-            Packet::KeepAlive { client_identifier } => {
+            Packet::ConnectionChallenge {
+                client_identifier,
+                cookie,
+            } => {
                 writer.write_all(&client_identifier.to_le_bytes())?;
+                writer.write_all(cookie)?;
             }
-            Packet::Data {
+            Packet::ConnectionResponse {
                 client_identifier,
-                payload,
+                cookie,
+                public_key,
+                connect_token,
             } => {
-                let _ = writer.write_all(&client_identifier.to_le_bytes());
+                writer.write_all(&client_identifier.to_le_bytes())?;
+                writer.write_all(cookie)?;
+                writer.write_all(public_key)?;
+                write_connect_token(writer, connect_token)?;
+            }
+            Packet::KeyExchange {
+                client_identifier,
+                public_key,
+            } => {
+                writer.write_all(&client_identifier.to_le_bytes())?;
+                writer.write_all(public_key)?;
+            }
+            Packet::ConnectionDenied {
+                client_identifier,
+                reason,
+            } => {
+                writer.write_all(&client_identifier.to_le_bytes())?;
+                writer.write_all(&[*reason as u8])?;
+            }
+            // The client identifier of sealed packets is written in the header
+            Packet::KeepAlive { .. } => {}
+            Packet::Data { payload, .. } => {
                 writer.write_all(payload)?;
             }
-            Packet::Disconnect { client_identifier } => {
-                let _ = writer.write_all(&client_identifier.to_le_bytes());
+            Packet::Disconnect { reason, .. } => {
+                writer.write_all(reason)?;
             }
         }
 
@@ -105,50 +272,191 @@ impl<'a> Packet<'a> {
         let cursor = &mut Cursor::new(src);
 
         match packet_type {
-            PacketType::Data => {
-                let client_identifier = read_u64(cursor)?;
-
-                let payload = &src[cursor.position() as usize..];
-                Ok(Packet::Data {
-                    client_identifier,
-                    payload,
-                })
-            }
             PacketType::ConnectionRequest => {
                 let connection_prefix = read_bytes(cursor)?;
                 let connection_side_id = read_u8(cursor)?;
                 let client_identifier = read_u64(cursor)?;
+                let connect_token = read_connect_token(cursor)?;
                 Ok(Packet::ConnectionRequest {
                     connection_prefix,
                     connection_side_id,
                     client_identifier,
+                    connect_token,
                 })
             }
-            PacketType::KeepAlive => {
+            PacketType::ConnectionChallenge => {
                 let client_identifier = read_u64(cursor)?;
-
-                Ok(Packet::KeepAlive { client_identifier })
+                let cookie = read_bytes(cursor)?;
+                Ok(Packet::ConnectionChallenge {
+                    client_identifier,
+                    cookie,
+                })
+            }
+            PacketType::ConnectionResponse => {
+                let client_identifier = read_u64(cursor)?;
+                let cookie = read_bytes(cursor)?;
+                let public_key = read_bytes(cursor)?;
+                let connect_token = read_connect_token(cursor)?;
+                Ok(Packet::ConnectionResponse {
+                    client_identifier,
+                    cookie,
+                    public_key,
+                    connect_token,
+                })
+            }
+            PacketType::KeyExchange => {
+                let client_identifier = read_u64(cursor)?;
+                let public_key = read_bytes(cursor)?;
+                Ok(Packet::KeyExchange {
+                    client_identifier,
+                    public_key,
+                })
             }
-            PacketType::Disconnect => {
+            PacketType::ConnectionDenied => {
                 let client_identifier = read_u64(cursor)?;
-                Ok(Packet::Disconnect { client_identifier })
+                let reason = DenialReason::from_u8(read_u8(cursor)?)?;
+                Ok(Packet::ConnectionDenied {
+                    client_identifier,
+                    reason,
+                })
             }
+            PacketType::Data | PacketType::KeepAlive | PacketType::Disconnect => Err(
+                io::Error::new(io::ErrorKind::InvalidData, "sealed packet read as plain"),
+            ),
         }
     }
 
-    pub fn encode(&self, buffer: &mut [u8]) -> Result<usize, TransportServerError> {
-        let mut writer = io::Cursor::new(buffer);
-        let prefix_byte = self.packet_type().to_u8()?;
+    fn read_sealed(packet_type: PacketType, client_identifier: u64, body: &'a [u8]) -> Self {
+        match packet_type {
+            PacketType::KeepAlive => Packet::KeepAlive { client_identifier },
+            PacketType::Disconnect => Packet::Disconnect {
+                client_identifier,
+                reason: body,
+            },
+            _ => Packet::Data {
+                client_identifier,
+                payload: body,
+            },
+        }
+    }
+
+    /// Encodes the packet into the buffer. Sealed packet types need the packet sequence and
+    /// the connection send key, handshake packets are written in plain text.
+    pub fn encode(
+        &self,
+        buffer: &mut [u8],
+        crypto: Option<(u64, &Key)>,
+    ) -> Result<usize, TransportServerError> {
+        let packet_type = self.packet_type();
+        if !packet_type.is_sealed() {
+            let mut writer = io::Cursor::new(buffer);
+            writer.write_all(&packet_type.to_u8()?.to_le_bytes())?;
+            self.write(&mut writer)?;
+            return Ok(writer.position() as usize);
+        }
+
+        let Some((sequence, key)) = crypto else {
+            return Err(TransportServerError::CryptoError);
+        };
+        if buffer.len() < SEALED_HEADER_BYTES + TRANSPORT_MAC_BYTES {
+            return Err(TransportServerError::PayloadAboveLimit);
+        }
 
-        writer.write_all(&prefix_byte.to_le_bytes())?;
-        self.write(&mut writer)?;
-        Ok(writer.position() as usize)
+        let (header, rest) = buffer.split_at_mut(SEALED_HEADER_BYTES);
+        header[0] = packet_type.to_u8()?;
+        header[1..9].copy_from_slice(&self.client_identifier().to_le_bytes());
+        header[9..].copy_from_slice(&sequence.to_le_bytes());
+
+        let body_capacity = rest.len() - TRANSPORT_MAC_BYTES;
+        let mut writer = io::Cursor::new(&mut rest[..body_capacity]);
+        self.write(&mut writer)
+            .map_err(|_| TransportServerError::PayloadAboveLimit)?;
+        let body_len = writer.position() as usize;
+
+        let (body, tag) = rest.split_at_mut(body_len);
+        let mac = crypto::seal(key, sequence, header, body)?;
+        tag[..TRANSPORT_MAC_BYTES].copy_from_slice(&mac);
+
+        Ok(SEALED_HEADER_BYTES + body_len + TRANSPORT_MAC_BYTES)
     }
 
-    pub fn decode(buffer: &'a mut [u8]) -> Result<Self, TransportServerError> {
-        let packet_type = buffer[0];
-        let packet_type = PacketType::from_u8(packet_type)?;
-        let packet = Packet::read(packet_type, &buffer[1..])?;
-        Ok(packet)
+    /// Reads the client identifier and sequence of an encrypted packet without decrypting it.
+    /// They can only be trusted once the packet is decoded with the client keys.
+    pub fn sealed_header(buffer: &[u8]) -> Option<(u64, u64)> {
+        let packet_type = PacketType::from_u8(*buffer.first()?).ok()?;
+        if !packet_type.is_sealed() || buffer.len() < SEALED_HEADER_BYTES + TRANSPORT_MAC_BYTES {
+            return None;
+        }
+
+        let client_identifier = read_u64(&mut &buffer[1..9]).ok()?;
+        let sequence = read_u64(&mut &buffer[9..SEALED_HEADER_BYTES]).ok()?;
+        Some((client_identifier, sequence))
     }
+
+    /// Decodes a packet from the buffer, decrypting it in place when its type is sealed.
+    /// Sealed packets are rejected if there are no connection keys, if they fail verification
+    /// or if their sequence was already received.
+    pub fn decode(
+        buffer: &'a mut [u8],
+        crypto: Option<(&Key, &mut ReplayProtection)>,
+    ) -> Result<Self, TransportServerError> {
+        if buffer.is_empty() {
+            return Err(TransportServerError::PacketTooSmall);
+        }
+
+        let packet_type = PacketType::from_u8(buffer[0])?;
+        if !packet_type.is_sealed() {
+            return Ok(Packet::read(packet_type, &buffer[1..])?);
+        }
+
+        let Some((key, replay_protection)) = crypto else {
+            return Err(TransportServerError::CryptoError);
+        };
+        if buffer.len() < SEALED_HEADER_BYTES + TRANSPORT_MAC_BYTES {
+            return Err(TransportServerError::PacketTooSmall);
+        }
+
+        let (header, body) = buffer.split_at_mut(SEALED_HEADER_BYTES);
+        let client_identifier = read_u64(&mut &header[1..9])?;
+        let sequence = read_u64(&mut &header[9..])?;
+        if replay_protection.already_received(sequence) {
+            return Err(TransportServerError::DuplicatedSequence);
+        }
+
+        let (body, tag) = body.split_at_mut(body.len() - TRANSPORT_MAC_BYTES);
+        let tag: [u8; TRANSPORT_MAC_BYTES] = read_bytes(&mut &tag[..])?;
+        crypto::open(key, sequence, header, body, &tag)?;
+        replay_protection.advance_sequence(sequence);
+
+        Ok(Packet::read_sealed(packet_type, client_identifier, body))
+    }
+}
+
+fn write_connect_token(writer: &mut impl io::Write, connect_token: &[u8]) -> Result<(), io::Error> {
+    let len = u16::try_from(connect_token.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "connect token too large"))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(connect_token)
+}
+
+/// Reads the length prefixed connect token. Clients without a token may end the packet
+/// before the length or pad it with zeros, both read as an empty token.
+fn read_connect_token<'a>(cursor: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], io::Error> {
+    let src = *cursor.get_ref();
+    let start = cursor.position() as usize;
+    if src.len() < start + 2 {
+        return Ok(&[]);
+    }
+
+    let len = read_u16(cursor)? as usize;
+    let start = start + 2;
+    if src.len() < start + len {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connect token is truncated",
+        ));
+    }
+    cursor.set_position((start + len) as u64);
+
+    Ok(&src[start..start + len])
 }
@@ -0,0 +1,264 @@
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::duration_secs,
+    constants::{
+        TRANSPORT_RATE_LIMIT_BLOCK_AFTER, TRANSPORT_RATE_LIMIT_BLOCK_DURATION,
+        TRANSPORT_RATE_LIMIT_CLIENT_BURST, TRANSPORT_RATE_LIMIT_CLIENT_PACKETS_PER_SECOND,
+        TRANSPORT_RATE_LIMIT_IP_BURST, TRANSPORT_RATE_LIMIT_IP_PACKETS_PER_SECOND,
+        TRANSPORT_RATE_LIMIT_MAX_PENDING_PER_IP,
+    },
+};
+
+/// Packets dropped so far by the [`RateLimiter`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct RateLimitStats {
+    /// Packets over the rate of their source address.
+    pub ip_rate_dropped: u64,
+    /// Packets over the rate of their connected client.
+    pub client_rate_dropped: u64,
+    /// Packets from a blocked address.
+    pub blocked_dropped: u64,
+    /// Connections denied because their address had too many pending connections.
+    pub pending_denied: u64,
+    /// Times an address was blocked.
+    pub blocks: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Packets per second allowed from one IP address, shared by every client behind it.
+    pub ip_packets_per_second: f64,
+    /// Packets one IP address can send at once after being idle.
+    pub ip_burst: f64,
+    pub client_packets_per_second: f64,
+    pub client_burst: f64,
+    /// Pending connections one IP address can have at a time.
+    pub max_pending_per_ip: usize,
+    /// Packets over the IP rate within a second before the address is blocked.
+    pub block_after: u32,
+    #[serde(rename = "block_duration_secs", deserialize_with = "duration_secs")]
+    pub block_duration: Duration,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            ip_packets_per_second: TRANSPORT_RATE_LIMIT_IP_PACKETS_PER_SECOND,
+            ip_burst: TRANSPORT_RATE_LIMIT_IP_BURST,
+            client_packets_per_second: TRANSPORT_RATE_LIMIT_CLIENT_PACKETS_PER_SECOND,
+            client_burst: TRANSPORT_RATE_LIMIT_CLIENT_BURST,
+            max_pending_per_ip: TRANSPORT_RATE_LIMIT_MAX_PENDING_PER_IP,
+            block_after: TRANSPORT_RATE_LIMIT_BLOCK_AFTER,
+            block_duration: TRANSPORT_RATE_LIMIT_BLOCK_DURATION,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Duration,
+}
+
+impl TokenBucket {
+    fn new(burst: f64, current_time: Duration) -> Self {
+        Self {
+            tokens: burst,
+            last_refill: current_time,
+        }
+    }
+
+    fn refill(&mut self, rate: f64, burst: f64, current_time: Duration) {
+        let elapsed = current_time.saturating_sub(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst);
+        self.last_refill = current_time;
+    }
+
+    fn take(&mut self, rate: f64, burst: f64, current_time: Duration) -> bool {
+        self.refill(rate, burst, current_time);
+        if self.tokens < 1. {
+            return false;
+        }
+        self.tokens -= 1.;
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+struct IpState {
+    bucket: TokenBucket,
+    violations: u32,
+    violations_since: Duration,
+    blocked_until: Option<Duration>,
+}
+
+/// Token buckets for every source address and connected client. Packets from unknown addresses
+/// are dropped before being decrypted, so a flooding host costs a map lookup per packet. Packets
+/// of connected clients are only charged to them once they decrypt, since their source address
+/// can be spoofed.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    ips: HashMap<IpAddr, IpState>,
+    clients: HashMap<u64, TokenBucket>,
+    stats: RateLimitStats,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            ips: HashMap::new(),
+            clients: HashMap::new(),
+            stats: RateLimitStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> RateLimitStats {
+        self.stats
+    }
+
+    /// Returns whether a packet from the address can be processed. Addresses that keep going
+    /// over their rate are blocked for a while.
+    pub fn allow_ip(&mut self, ip: IpAddr, current_time: Duration) -> bool {
+        let config = &self.config;
+        let state = self.ips.entry(ip).or_insert_with(|| IpState {
+            bucket: TokenBucket::new(config.ip_burst, current_time),
+            violations: 0,
+            violations_since: current_time,
+            blocked_until: None,
+        });
+
+        match state.blocked_until {
+            Some(blocked_until) if current_time < blocked_until => {
+                self.stats.blocked_dropped += 1;
+                return false;
+            }
+            Some(_) => state.blocked_until = None,
+            None => {}
+        }
+
+        if state
+            .bucket
+            .take(config.ip_packets_per_second, config.ip_burst, current_time)
+        {
+            return true;
+        }
+
+        self.stats.ip_rate_dropped += 1;
+        if current_time.saturating_sub(state.violations_since) > Duration::from_secs(1) {
+            state.violations = 0;
+            state.violations_since = current_time;
+        }
+        state.violations += 1;
+
+        if state.violations >= config.block_after {
+            tracing::warn!(
+                "Blocking {} for {:?}, too many packets",
+                ip,
+                config.block_duration
+            );
+            state.blocked_until = Some(current_time + config.block_duration);
+            state.violations = 0;
+            self.stats.blocks += 1;
+        }
+
+        false
+    }
+
+    /// Returns whether a packet from the connected client can be processed.
+    pub fn allow_client(&mut self, client_id: u64, current_time: Duration) -> bool {
+        let config = &self.config;
+        let allowed = self
+            .clients
+            .entry(client_id)
+            .or_insert_with(|| TokenBucket::new(config.client_burst, current_time))
+            .take(
+                config.client_packets_per_second,
+                config.client_burst,
+                current_time,
+            );
+
+        if !allowed {
+            self.stats.client_rate_dropped += 1;
+        }
+        allowed
+    }
+
+    /// Returns whether an address that already has `pending` connections can open another one.
+    pub fn allow_pending(&mut self, pending: usize) -> bool {
+        if pending < self.config.max_pending_per_ip {
+            return true;
+        }
+
+        self.stats.pending_denied += 1;
+        false
+    }
+
+    pub fn remove_client(&mut self, client_id: u64) {
+        self.clients.remove(&client_id);
+    }
+
+    /// Forgets the addresses that are not blocked and have a full bucket again.
+    pub fn update(&mut self, current_time: Duration) {
+        let config = &self.config;
+        self.ips.retain(|_, state| {
+            if state
+                .blocked_until
+                .is_some_and(|blocked_until| current_time < blocked_until)
+            {
+                return true;
+            }
+            state
+                .bucket
+                .refill(config.ip_packets_per_second, config.ip_burst, current_time);
+            state.bucket.tokens < config.ip_burst
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttles_and_blocks_addresses() {
+        let mut limiter = RateLimiter::new(RateLimitConfig {
+            ip_packets_per_second: 10.,
+            ip_burst: 5.,
+            block_after: 20,
+            block_duration: Duration::from_secs(30),
+            ..Default::default()
+        });
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        let mut now = Duration::from_secs(1_000);
+
+        assert_eq!((0..10).filter(|_| limiter.allow_ip(ip, now)).count(), 5);
+        assert!(limiter.allow_ip(other, now));
+
+        now += Duration::from_millis(100);
+        assert!(limiter.allow_ip(ip, now));
+        assert!(!limiter.allow_ip(ip, now));
+
+        for _ in 0..14 {
+            limiter.allow_ip(ip, now);
+        }
+        now += Duration::from_secs(10);
+        assert!(!limiter.allow_ip(ip, now));
+        now += Duration::from_secs(20);
+        assert!(limiter.allow_ip(ip, now));
+
+        let stats = limiter.stats();
+        assert_eq!(stats.blocks, 1);
+        assert_eq!(stats.blocked_dropped, 1);
+        assert!(stats.ip_rate_dropped >= 20);
+
+        limiter.update(now + Duration::from_secs(1));
+        assert!(limiter.ips.is_empty());
+    }
+}
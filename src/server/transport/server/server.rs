@@ -1,19 +1,29 @@
-use std::{
-    collections::HashMap,
-    net::SocketAddr,
-    sync::{Arc, Mutex},
-    time::Duration,
-};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use rand::{rngs::StdRng, SeedableRng};
+use serde::Deserialize;
 
 use crate::{
     constants::{
-        TRANSPORT_MAX_CLIENTS, TRANSPORT_MAX_PACKET_BYTES, TRANSPORT_MAX_PENDING_CLIENTS,
-        TRANSPORT_SEND_RATE,
+        TRANSPORT_COOKIE_BYTES, TRANSPORT_KEY_BYTES, TRANSPORT_MAX_CLIENTS,
+        TRANSPORT_MAX_PACKET_BYTES, TRANSPORT_MAX_PAYLOAD_BYTES, TRANSPORT_MAX_PENDING_CLIENTS,
+        TRANSPORT_MIN_CONNECTION_REQUEST_BYTES, TRANSPORT_PROTOCOL_PREFIX,
+    },
+    server::{
+        error::DisconnectReason,
+        transport::server::packet::{DenialReason, Packet},
     },
-    server::transport::server::packet::Packet,
 };
 
-use super::error::TransportServerError;
+use super::{
+    auth::AuthProvider,
+    auth_worker::{AuthRequest, AuthResult, AuthWorker, AuthWorkerConfig},
+    challenge::ChallengeCookies,
+    crypto::{ConnectionKeys, KeyExchange, ReplayProtection},
+    error::TransportServerError,
+    rate_limit::{RateLimitConfig, RateLimitStats, RateLimiter},
+    token::ConnectToken,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ConnectionState {
@@ -28,24 +38,60 @@ struct Connection {
     confirmed: bool,
     client_id: u64,
     state: ConnectionState,
-    is_authenticated: Arc<Mutex<(bool, String)>>,
-    // TODO MAYBE user_data: [u8; NETCODE_USER_DATA_BYTES],
+    /// Known once the client is authenticated, or from the start when it sent a connect token.
+    player_id: Option<String>,
+    /// Why the server disconnected the client, sent to the session once the client is removed.
+    disconnect_reason: Option<DisconnectReason>,
     addr: SocketAddr,
+    keys: ConnectionKeys,
+    server_public_key: [u8; TRANSPORT_KEY_BYTES],
+    sequence: u64,
+    replay_protection: ReplayProtection,
     last_packet_received_time: Duration,
     last_packet_send_time: Duration,
-    timeout_seconds: i32,
+    timeout: Duration,
     expire_timestamp: u64,
 }
 
+impl Connection {
+    /// Encodes a sealed packet with the connection send key and advances the packet sequence.
+    fn encode(
+        &mut self,
+        packet: &Packet,
+        buffer: &mut [u8],
+    ) -> Result<usize, TransportServerError> {
+        let len = packet.encode(buffer, Some((self.sequence, &self.keys.send_key)))?;
+        self.sequence += 1;
+        Ok(len)
+    }
+}
+
 /// A server that can generate packets from connect clients, that are encrypted, or process
 /// incoming encrypted packets from clients. The server is agnostic from the transport layer, only
 /// consuming and generating bytes that can be transported in any way desired.
 #[derive(Debug)]
 pub struct TransportServer {
     clients: Box<[Option<Connection>]>,
+    /// Slots of the connected clients, kept in sync by [`TransportServer::insert_client`] and
+    /// [`TransportServer::remove_client`] so packets do not scan every slot.
+    addr_to_slot: HashMap<SocketAddr, usize>,
+    id_to_slot: HashMap<u64, usize>,
+    player_to_slot: HashMap<String, usize>,
     pending_clients: HashMap<SocketAddr, Connection>,
+    cookies: ChallengeCookies,
     max_clients: usize,
     public_addresses: Vec<SocketAddr>,
+    connect_token_key: Option<[u8; TRANSPORT_KEY_BYTES]>,
+    auth_worker: AuthWorker,
+    rate_limiter: RateLimiter,
+    duplicate_login: DuplicateLoginPolicy,
+    keep_alive_interval: Duration,
+    client_timeout: Duration,
+    /// Players denied with [`DenialReason::Banned`], with the reason they were banned for.
+    banned_players: HashMap<String, String>,
+    /// Cleared when the server shuts down, connecting clients are denied.
+    accepting: bool,
+    rng: StdRng,
     current_time: Duration,
     out: [u8; TRANSPORT_MAX_PACKET_BYTES],
 }
@@ -73,16 +119,43 @@ pub enum ServerResult<'a, 's> {
     ClientDisconnected {
         client_id: u64,
         addr: SocketAddr,
+        reason: DisconnectReason,
         payload: Option<&'s mut [u8]>,
     },
 }
 
+/// What happens when a player logs in while already connected with another client.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateLoginPolicy {
+    /// The connected client is disconnected and the new one takes over the player.
+    #[default]
+    KickOld,
+    /// The new client is denied with [`DenialReason::AlreadyConnected`].
+    RejectNew,
+}
+
 pub struct ServerConfig {
     pub current_time: Duration,
     /// Maximum numbers of clients that can be connected at a time
     pub max_clients: usize,
     /// Publicly available addresses to which clients will attempt to connect.
     pub public_addresses: Vec<SocketAddr>,
+    /// Key shared with the backend to verify connect tokens.
+    /// Without it only clients that authenticate with their first payload can connect.
+    pub connect_token_key: Option<[u8; TRANSPORT_KEY_BYTES]>,
+    /// Checks the session tickets of clients that connect without a connect token.
+    pub auth_provider: Arc<dyn AuthProvider>,
+    pub auth_worker: AuthWorkerConfig,
+    pub rate_limit: RateLimitConfig,
+    pub duplicate_login: DuplicateLoginPolicy,
+    /// Connected clients are sent a keep alive when nothing else was sent to them for this long.
+    pub keep_alive_interval: Duration,
+    /// Connected clients are disconnected when nothing was received from them for this long.
+    pub client_timeout: Duration,
+    /// Seeds the cookie key and the key exchanges, so a packet capture can be replayed.
+    /// Random when not set.
+    pub rng_seed: Option<[u8; 32]>,
 }
 
 impl TransportServer {
@@ -94,13 +167,30 @@ impl TransportServer {
         }
 
         let clients = vec![None; config.max_clients].into_boxed_slice();
+        let mut rng = match config.rng_seed {
+            Some(seed) => StdRng::from_seed(seed),
+            None => StdRng::from_entropy(),
+        };
 
         Self {
             clients,
+            addr_to_slot: HashMap::new(),
+            id_to_slot: HashMap::new(),
+            player_to_slot: HashMap::new(),
             pending_clients: HashMap::new(),
+            cookies: ChallengeCookies::new(&mut rng),
             max_clients: config.max_clients,
 
             public_addresses: config.public_addresses,
+            connect_token_key: config.connect_token_key,
+            auth_worker: AuthWorker::new(config.auth_provider, config.auth_worker),
+            rate_limiter: RateLimiter::new(config.rate_limit),
+            duplicate_login: config.duplicate_login,
+            keep_alive_interval: config.keep_alive_interval,
+            client_timeout: config.client_timeout,
+            banned_players: HashMap::new(),
+            accepting: true,
+            rng,
             current_time: config.current_time,
             out: [0u8; TRANSPORT_MAX_PACKET_BYTES],
         }
@@ -114,19 +204,15 @@ impl TransportServer {
         self.current_time
     }
 
-    // /// Returns the user data from the connected client.
-    // pub fn user_data(&self, client_id: u64) -> Option<[u8; NETCODE_USER_DATA_BYTES]> {
-    //     if let Some(client) = find_client_by_id(&self.clients, client_id) {
-    //         return Some(client.user_data);
-    //     }
-
-    //     None
-    // }
+    /// Returns how many packets and connections were dropped by the rate limiter.
+    pub fn rate_limit_stats(&self) -> RateLimitStats {
+        self.rate_limiter.stats()
+    }
 
     /// Returns the duration since the connected client last received a packet.
     /// Usefull to detect users that are timing out.
     pub fn time_since_last_received_packet(&self, client_id: u64) -> Option<Duration> {
-        if let Some(client) = find_client_by_id(&self.clients, client_id) {
+        if let Some(client) = find_client_by_id(&self.clients, &self.id_to_slot, client_id) {
             let time = self.current_time - client.last_packet_received_time;
             return Some(time);
         }
@@ -136,76 +222,379 @@ impl TransportServer {
 
     /// Returns the client address if connected.
     pub fn client_addr(&self, client_id: u64) -> Option<SocketAddr> {
-        if let Some(client) = find_client_by_id(&self.clients, client_id) {
+        if let Some(client) = find_client_by_id(&self.clients, &self.id_to_slot, client_id) {
             return Some(client.addr);
         }
 
         None
     }
 
+    /// Returns the player id of the connected client.
+    pub fn client_player_id(&self, client_id: u64) -> Option<&str> {
+        find_client_by_id(&self.clients, &self.id_to_slot, client_id)?
+            .player_id
+            .as_deref()
+    }
+
+    /// Returns the connected client playing as the player.
+    pub fn client_id_by_player_id(&self, player_id: &str) -> Option<u64> {
+        let slot = self.player_to_slot.get(player_id)?;
+        self.clients[*slot].as_ref().map(|client| client.client_id)
+    }
+
+    /// Denies the player from connecting again, it is not disconnected.
+    pub fn ban_player(&mut self, player_id: String, reason: String) {
+        self.banned_players.insert(player_id, reason);
+    }
+
+    /// Returns whether the player was banned.
+    pub fn unban_player(&mut self, player_id: &str) -> bool {
+        self.banned_players.remove(player_id).is_some()
+    }
+
+    /// Denies every client connecting from now on, the connected ones are kept.
+    pub fn stop_accepting(&mut self) {
+        self.accepting = false;
+    }
+
+    /// Checks that the connect token was signed by the backend for this client and this server,
+    /// and that it has not expired.
+    fn validate_connect_token(
+        &self,
+        client_identifier: u64,
+        connect_token: &[u8],
+    ) -> Result<ConnectToken, TransportServerError> {
+        let Some(key) = &self.connect_token_key else {
+            return Err(TransportServerError::InvalidConnectToken);
+        };
+
+        let token = ConnectToken::verify(connect_token, key)?;
+        if token.client_id != client_identifier {
+            return Err(TransportServerError::InvalidConnectToken);
+        }
+
+        if token.expire_timestamp <= self.current_time.as_secs() {
+            return Err(TransportServerError::Expired);
+        }
+
+        if !token
+            .server_addresses
+            .iter()
+            .any(|addr| self.public_addresses.contains(addr))
+        {
+            return Err(TransportServerError::NotInHostList);
+        }
+
+        Ok(token)
+    }
+
+    /// Answers a connection request with a challenge cookie. No state is kept for the client
+    /// until it echoes a valid cookie back in a [`Packet::ConnectionResponse`].
     fn handle_connection_request<'a>(
         &mut self,
         addr: SocketAddr,
-        connection_prefix: [u8; 3],
         client_identifier: u64,
+        connection_prefix: [u8; 3],
+        connect_token: &[u8],
     ) -> Result<ServerResult<'a, '_>, TransportServerError> {
-        let addr_already_connected = find_client_mut_by_addr(&mut self.clients, addr).is_some();
-        let id_already_connected =
-            find_client_mut_by_id(&mut self.clients, client_identifier).is_some();
+        if connection_prefix != TRANSPORT_PROTOCOL_PREFIX {
+            return self.deny_connection(addr, client_identifier, DenialReason::VersionMismatch);
+        }
+        if !self.accepting {
+            return self.deny_connection(addr, client_identifier, DenialReason::ShuttingDown);
+        }
 
-        if id_already_connected || addr_already_connected {
-            tracing::debug!(
-                "Connection request denied: client {} already connected (address: {}).",
+        if !connect_token.is_empty() {
+            if let Err(e) = self.validate_connect_token(client_identifier, connect_token) {
+                tracing::debug!(
+                    "Invalid connect token from Client {}: {}",
+                    client_identifier,
+                    e
+                );
+                return self.deny_connection(addr, client_identifier, token_denial_reason(&e));
+            }
+        }
+
+        let packet = Packet::ConnectionChallenge {
+            client_identifier,
+            cookie: self
+                .cookies
+                .generate(addr, client_identifier, self.current_time),
+        };
+
+        let len = packet.encode(&mut self.out, None)?;
+
+        tracing::trace!("Connection challenge sent to Client {}", client_identifier);
+
+        Ok(ServerResult::PacketToSend {
+            addr,
+            payload: &mut self.out[..len],
+        })
+    }
+
+    /// Creates the pending client once it echoes a valid cookie, and finishes the key exchange
+    /// with the public key it sent. Clients with a valid connect token, bound to that public key,
+    /// skip the authentication payload and connect with their first data packet.
+    fn handle_connection_response<'a>(
+        &mut self,
+        addr: SocketAddr,
+        client_identifier: u64,
+        cookie: [u8; TRANSPORT_COOKIE_BYTES],
+        public_key: [u8; TRANSPORT_KEY_BYTES],
+        connect_token: &[u8],
+    ) -> Result<ServerResult<'a, '_>, TransportServerError> {
+        if !self
+            .cookies
+            .verify(addr, client_identifier, self.current_time, &cookie)
+        {
+            tracing::trace!(
+                "Connection response denied: invalid cookie from Client {} ({}).",
                 client_identifier,
                 addr
             );
             return Ok(ServerResult::None);
         }
 
-        if !self.pending_clients.contains_key(&addr)
-            && self.pending_clients.len() >= TRANSPORT_MAX_PENDING_CLIENTS
-        {
+        if let Some(pending) = self.pending_clients.get_mut(&addr) {
+            if pending.client_id != client_identifier {
+                return self.deny_connection(
+                    addr,
+                    client_identifier,
+                    DenialReason::AlreadyConnected,
+                );
+            }
+
+            // The key exchange was lost, send the same server key again
+            let packet = Packet::KeyExchange {
+                client_identifier,
+                public_key: pending.server_public_key,
+            };
+            let len = packet.encode(&mut self.out, None)?;
+            pending.last_packet_received_time = self.current_time;
+            pending.last_packet_send_time = self.current_time;
+
+            return Ok(ServerResult::PacketToSend {
+                addr,
+                payload: &mut self.out[..len],
+            });
+        }
+
+        let addr_already_connected = self.addr_to_slot.contains_key(&addr);
+        let id_already_connected = self.id_to_slot.contains_key(&client_identifier);
+
+        if id_already_connected || addr_already_connected {
+            return self.deny_connection(addr, client_identifier, DenialReason::AlreadyConnected);
+        }
+
+        let pending_from_ip = self
+            .pending_clients
+            .keys()
+            .filter(|pending_addr| pending_addr.ip() == addr.ip())
+            .count();
+        if !self.rate_limiter.allow_pending(pending_from_ip) {
+            tracing::debug!("Too many pending connections from {}", addr.ip());
+            return self.deny_connection(addr, client_identifier, DenialReason::ServerFull);
+        }
+
+        if self.pending_clients.len() >= TRANSPORT_MAX_PENDING_CLIENTS {
             tracing::warn!(
-                "Connection request denied: reached max amount allowed of pending clients ({}).",
+                "Reached max amount allowed of pending clients ({}).",
                 TRANSPORT_MAX_PENDING_CLIENTS
             );
-            return Ok(ServerResult::None);
+            return self.deny_connection(addr, client_identifier, DenialReason::ServerFull);
         }
 
-        if self.clients.iter().flatten().count() >= self.max_clients {
-            self.pending_clients.remove(&addr);
-            // TODO: Maybe implement ConnectionDenied message
-            return Ok(ServerResult::None);
+        if self.connected_clients() >= self.max_clients {
+            return self.deny_connection(addr, client_identifier, DenialReason::ServerFull);
         }
 
-        let packet = Packet::ConnectionRequest {
-            connection_prefix,
-            connection_side_id: 2,
+        let connect_token = if connect_token.is_empty() {
+            None
+        } else {
+            let token = self
+                .validate_connect_token(client_identifier, connect_token)
+                .and_then(|token| match token.client_public_key == public_key {
+                    true => Ok(token),
+                    false => Err(TransportServerError::InvalidConnectToken),
+                });
+            match token {
+                Ok(token) => Some(token),
+                Err(e) => {
+                    tracing::debug!(
+                        "Invalid connect token from Client {}: {}",
+                        client_identifier,
+                        e
+                    );
+                    return self.deny_connection(addr, client_identifier, token_denial_reason(&e));
+                }
+            }
+        };
+
+        let key_exchange = KeyExchange::new(&mut self.rng);
+        let server_public_key = key_exchange.public_key();
+        let Some(keys) = key_exchange.server_keys(&public_key) else {
+            tracing::debug!("Invalid public key from Client {}.", client_identifier);
+            return self.deny_connection(addr, client_identifier, DenialReason::AuthFailed);
+        };
+
+        let packet = Packet::KeyExchange {
             client_identifier,
+            public_key: server_public_key,
         };
 
-        let len = packet.encode(&mut self.out)?;
+        let len = packet.encode(&mut self.out, None)?;
 
-        tracing::trace!("Connection request from Client {}", client_identifier);
+        tracing::trace!("Connection response from Client {}", client_identifier);
 
-        let pending = self
-            .pending_clients
-            .entry(addr)
-            .or_insert_with(|| Connection {
+        let mut expire_timestamp = self.current_time.as_secs() + 10;
+        let player_id = connect_token.map(|token| {
+            expire_timestamp = expire_timestamp.min(token.expire_timestamp);
+            token.player_id
+        });
+
+        self.pending_clients.insert(
+            addr,
+            Connection {
                 confirmed: false,
-                is_authenticated: Arc::new(Mutex::new((false, String::new()))),
+                player_id,
+                disconnect_reason: None,
                 client_id: client_identifier,
                 last_packet_received_time: self.current_time,
                 last_packet_send_time: self.current_time,
                 addr,
+                keys,
+                server_public_key,
+                sequence: 0,
+                replay_protection: ReplayProtection::default(),
                 state: ConnectionState::PendingResponse,
-                timeout_seconds: 10,
-                // write code to calculate based on timeout_seconds and current_time
-                expire_timestamp: self.current_time.as_secs() + 10 as u64,
-            });
-        pending.last_packet_received_time = self.current_time;
+                timeout: self.client_timeout,
+                expire_timestamp,
+            },
+        );
+
+        Ok(ServerResult::PacketToSend {
+            addr,
+            payload: &mut self.out[..len],
+        })
+    }
+
+    /// Moves an authenticated pending client into a free slot.
+    fn connect_pending<'a>(
+        &mut self,
+        mut pending: Connection,
+        player_id: String,
+    ) -> Result<ServerResult<'a, '_>, TransportServerError> {
+        let addr = pending.addr;
+        let client_id = pending.client_id;
+
+        if self.id_to_slot.contains_key(&client_id) {
+            return self.deny_connection(addr, client_id, DenialReason::AlreadyConnected);
+        }
+
+        if !self.accepting {
+            return self.deny_connection(addr, client_id, DenialReason::ShuttingDown);
+        }
+
+        if let Some(reason) = self.banned_players.get(&player_id) {
+            tracing::info!("Denying banned player {}: {}", player_id, reason);
+            return self.deny_connection(addr, client_id, DenialReason::Banned);
+        }
+
+        let connected_slot = self.player_to_slot.get(&player_id).copied();
+        if connected_slot.is_some() && self.duplicate_login == DuplicateLoginPolicy::RejectNew {
+            return self.deny_connection(addr, client_id, DenialReason::AlreadyConnected);
+        }
+
+        let Some(client_index) = self.clients.iter().position(|c| c.is_none()) else {
+            return self.deny_connection(addr, client_id, DenialReason::ServerFull);
+        };
+
+        // The old client is removed by the next `update_client`, its packets are ignored until then
+        if let Some(old) = connected_slot.and_then(|slot| self.clients[slot].as_mut()) {
+            tracing::info!(
+                "Player {} logged in again with Client {}, disconnecting Client {}",
+                player_id,
+                client_id,
+                old.client_id
+            );
+            old.state = ConnectionState::Disconnected;
+            old.disconnect_reason = Some(DisconnectReason::DuplicateLogin);
+        }
+
+        pending.state = ConnectionState::Connected;
+        pending.player_id = Some(player_id.clone());
         pending.last_packet_send_time = self.current_time;
 
+        let packet = Packet::KeepAlive {
+            client_identifier: client_id,
+        };
+        let len = pending.encode(&packet, &mut self.out)?;
+
+        self.insert_client(client_index, pending);
+
+        Ok(ServerResult::ClientConnected {
+            client_id,
+            addr,
+            player_id,
+            payload: &mut self.out[..len],
+        })
+    }
+
+    /// Returns the next authentication finished by the auth worker, to be passed to
+    /// [`TransportServer::complete_authentication`].
+    pub fn poll_auth_result(&mut self) -> Option<AuthResult> {
+        self.auth_worker.try_recv()
+    }
+
+    /// Returns the outcome of a finished authentication: the client connecting, or the packet
+    /// denying it.
+    pub fn complete_authentication(&mut self, result: AuthResult) -> ServerResult<'_, '_> {
+        // The client may have timed out or reconnected while it was authenticating
+        let pending = match self.pending_clients.get(&result.addr) {
+            Some(pending)
+                if pending.client_id == result.client_id
+                    && pending.state == ConnectionState::Authenticating =>
+            {
+                self.pending_clients.remove(&result.addr).unwrap()
+            }
+            _ => return ServerResult::None,
+        };
+
+        let server_result = if result.authenticated {
+            self.connect_pending(pending, result.player_id)
+        } else {
+            self.deny_connection(result.addr, result.client_id, DenialReason::AuthFailed)
+        };
+
+        match server_result {
+            Ok(server_result) => server_result,
+            Err(e) => {
+                tracing::error!("Failed to complete authentication: {}", e);
+                ServerResult::None
+            }
+        }
+    }
+
+    /// Returns a [`Packet::ConnectionDenied`] to be sent back to a connecting client.
+    fn deny_connection<'a>(
+        &mut self,
+        addr: SocketAddr,
+        client_identifier: u64,
+        reason: DenialReason,
+    ) -> Result<ServerResult<'a, '_>, TransportServerError> {
+        tracing::debug!(
+            "Connection denied for Client {} ({}): {}",
+            client_identifier,
+            addr,
+            reason
+        );
+
+        let packet = Packet::ConnectionDenied {
+            client_identifier,
+            reason,
+        };
+        let len = packet.encode(&mut self.out, None)?;
+
         Ok(ServerResult::PacketToSend {
             addr,
             payload: &mut self.out[..len],
@@ -218,12 +607,14 @@ impl TransportServer {
         client_identifier: u64,
         payload: &[u8],
     ) -> Result<(SocketAddr, &'s mut [u8]), TransportServerError> {
-        if let Some(client) = find_client_mut_by_id(&mut self.clients, client_identifier) {
+        if let Some(client) =
+            find_client_mut_by_id(&mut self.clients, &self.id_to_slot, client_identifier)
+        {
             let packet = Packet::Data {
                 client_identifier,
                 payload,
             };
-            let len = packet.encode(&mut self.out)?;
+            let len = client.encode(&packet, &mut self.out)?;
 
             client.last_packet_send_time = self.current_time;
 
@@ -240,7 +631,19 @@ impl TransportServer {
         addr: SocketAddr,
         buffer: &'a mut [u8],
     ) -> ServerResult<'a, 's> {
+        // The source address can be spoofed, so it must not throttle or block a connected
+        // client, whose packets are only charged to it once they decode
+        if !self.addr_to_slot.contains_key(&addr)
+            && !self.rate_limiter.allow_ip(addr.ip(), self.current_time)
+        {
+            return ServerResult::None;
+        }
+
         match self.process_packet_internal(addr, buffer) {
+            Err(TransportServerError::CryptoError | TransportServerError::DuplicatedSequence) => {
+                tracing::trace!("Dropped packet from {} that failed verification", addr);
+                ServerResult::None
+            }
             Err(e) => {
                 tracing::error!("Failed to process packet: {}", e);
                 ServerResult::None
@@ -254,23 +657,54 @@ impl TransportServer {
         addr: SocketAddr,
         buffer: &'a mut [u8],
     ) -> Result<ServerResult<'a, 's>, TransportServerError> {
+        let packet_len = buffer.len();
+
         // Handle connected client
-        if let Some((slot, client)) = find_client_mut_by_addr(&mut self.clients, addr) {
-            let packet = Packet::decode(buffer)?;
+        let slot = match self.addr_to_slot.get(&addr) {
+            Some(&slot) => Some(slot),
+            None if !self.pending_clients.contains_key(&addr) => self.migrating_client(buffer),
+            None => None,
+        };
+        if let Some(slot) = slot {
+            let client = self.clients[slot].as_mut().unwrap();
+            let packet = Packet::decode(
+                buffer,
+                Some((&client.keys.receive_key, &mut client.replay_protection)),
+            )?;
+            // Anyone can send a packet with the client address or id, so packets are only
+            // charged to the client once they decode with its keys
+            if !self
+                .rate_limiter
+                .allow_client(client.client_id, self.current_time)
+            {
+                return Ok(ServerResult::None);
+            }
+
+            // The packet was sealed with the client keys, so the client is the one that moved
+            if client.addr != addr {
+                tracing::info!(
+                    "Client {} moved from {} to {}",
+                    client.client_id,
+                    client.addr,
+                    addr
+                );
+                self.addr_to_slot.remove(&client.addr);
+                self.addr_to_slot.insert(addr, slot);
+                client.addr = addr;
+            }
 
             client.last_packet_received_time = self.current_time;
             match client.state {
                 ConnectionState::Connected => match packet {
-                    Packet::Disconnect {
-                        client_identifier: _,
-                    } => {
+                    Packet::Disconnect { .. } => {
                         client.state = ConnectionState::Disconnected;
                         let client_id = client.client_id;
-                        self.clients[slot] = None;
+                        self.remove_client(slot);
                         tracing::trace!("Client {} requested to disconnect", client_id);
                         return Ok(ServerResult::ClientDisconnected {
                             client_id,
                             addr,
+                            reason: DisconnectReason::DisconnectedByClient,
                             payload: None,
                         });
                     }
@@ -302,7 +736,10 @@ impl TransportServer {
 
         // Handle pending client
         if let Some(pending) = self.pending_clients.get_mut(&addr) {
-            let packet = Packet::decode(buffer)?;
+            let packet = Packet::decode(
+                buffer,
+                Some((&pending.keys.receive_key, &mut pending.replay_protection)),
+            )?;
 
             pending.last_packet_received_time = self.current_time;
             tracing::trace!(
@@ -313,18 +750,30 @@ impl TransportServer {
             match packet {
                 Packet::ConnectionRequest {
                     connection_prefix,
-                    connection_side_id,
+                    connection_side_id: 1,
+                    client_identifier,
+                    connect_token,
+                } if packet_len >= TRANSPORT_MIN_CONNECTION_REQUEST_BYTES => {
+                    return self.handle_connection_request(
+                        addr,
+                        client_identifier,
+                        connection_prefix,
+                        connect_token,
+                    );
+                }
+                Packet::ConnectionResponse {
                     client_identifier,
+                    cookie,
+                    public_key,
+                    connect_token,
                 } => {
-                    if (connection_side_id) == 1 {
-                        return self.handle_connection_request(
-                            addr,
-                            connection_prefix,
-                            client_identifier,
-                        );
-                    } else {
-                        return Ok(ServerResult::None);
-                    }
+                    return self.handle_connection_response(
+                        addr,
+                        client_identifier,
+                        cookie,
+                        public_key,
+                        connect_token,
+                    );
                 }
                 // If its Data from pending client it has to be the application level connection request in the payload
                 Packet::Data {
@@ -334,135 +783,96 @@ impl TransportServer {
                     let mut pending = self.pending_clients.remove(&addr).unwrap();
 
                     match pending.state {
-                        ConnectionState::Authenticating => {
-                            let is_authenticated = pending.is_authenticated.lock().unwrap();
-                            if is_authenticated.0 {
-                                if find_client_slot_by_id(&self.clients, client_identifier)
-                                    .is_some()
-                                {
-                                    tracing::debug!(
-                                        "Ignored connection response for Client {}, already connected.",
-                                        client_identifier
-                                    );
-                                    return Ok(ServerResult::None);
-                                }
-
-                                match self.clients.iter().position(|c| c.is_none()) {
-                                    None => {
-                                        let packet = Packet::Disconnect { client_identifier };
-                                        let len = packet.encode(&mut self.out)?;
-                                        pending.state = ConnectionState::Disconnected;
-
-                                        pending.last_packet_send_time = self.current_time;
-                                        return Ok(ServerResult::PacketToSend {
-                                            addr,
-                                            payload: &mut self.out[..len],
-                                        });
-                                    }
-                                    Some(client_index) => {
-                                        pending.state = ConnectionState::Connected;
-                                        pending.last_packet_send_time = self.current_time;
-
-                                        let packet = Packet::KeepAlive { client_identifier };
-                                        let len = packet.encode(&mut self.out)?;
-
-                                        let client_id: u64 = pending.client_id;
-
-                                        self.clients[client_index] = Some(pending.clone());
-
-                                        let player_id = is_authenticated.1.clone();
-
-                                        return Ok(ServerResult::ClientConnected {
-                                            client_id,
-                                            addr,
-                                            player_id,
-                                            payload: &mut self.out[..len],
-                                        });
-                                    }
-                                }
-                            } else {
-                                self.pending_clients.insert(addr, pending.clone());
-                            }
-                            return Ok(ServerResult::None);
-                        }
                         ConnectionState::PendingResponse => {
-                            pending.state = ConnectionState::Authenticating;
-
-                            let bytes = payload.to_vec();
-                            let channel_id = bytes[0];
-                            let messages_len = bytes[1];
-                            let message_type = bytes[5];
-
-                            if bytes.len() < 17
-                                || channel_id != 0
-                                || messages_len != 1
-                                || message_type != 0
-                            {
-                                return Err(TransportServerError::InvalidPacketType);
+                            // Clients with a connect token are already authenticated
+                            if let Some(player_id) = pending.player_id.clone() {
+                                return self.connect_pending(pending, player_id);
                             }
 
-                            let (player_id_bytes, session_ticket_bytes) = bytes[6..].split_at(16);
-
-                            let player_id = String::from_utf8(player_id_bytes.to_vec())
-                                .map_err(|_| TransportServerError::InvalidPlayerId)?
-                                .trim_end_matches(char::from(0))
-                                .to_string();
+                            let (player_id, session_ticket) = match parse_auth_payload(payload) {
+                                Ok(auth) => auth,
+                                Err(e) => {
+                                    tracing::debug!(
+                                        "Invalid authentication payload from Client {}: {}",
+                                        client_identifier,
+                                        e
+                                    );
+                                    return self.deny_connection(
+                                        addr,
+                                        client_identifier,
+                                        DenialReason::AuthFailed,
+                                    );
+                                }
+                            };
 
                             tracing::trace!("Authenticating: {:?}", player_id);
 
-                            let session_ticket = String::from_utf8(session_ticket_bytes.to_vec())
-                                .map_err(|_| TransportServerError::InvalidSessionTicket)?
-                                .trim_end_matches(char::from(0))
-                                .to_string();
-
-                            let is_authenticated = pending.is_authenticated.clone();
-
-                            std::thread::spawn(move || {
-                                let rt = tokio::runtime::Runtime::new().unwrap();
-                                rt.block_on(async move {
-                                    authenticate_player(
-                                        player_id,
-                                        session_ticket,
-                                        is_authenticated,
-                                    )
-                                    .await;
-                                });
-                            });
+                            let request = AuthRequest {
+                                addr,
+                                client_id: pending.client_id,
+                                player_id,
+                                session_ticket,
+                            };
+                            if self.auth_worker.submit(request).is_err() {
+                                tracing::warn!(
+                                    "Authentication queue is full, denying Client {}",
+                                    client_identifier
+                                );
+                                return self.deny_connection(
+                                    addr,
+                                    client_identifier,
+                                    DenialReason::ServerFull,
+                                );
+                            }
 
+                            pending.state = ConnectionState::Authenticating;
                             pending.last_packet_send_time = self.current_time;
                             let packet = Packet::KeepAlive { client_identifier };
-                            let len = packet.encode(&mut self.out)?;
+                            let len = pending.encode(&packet, &mut self.out)?;
 
                             self.pending_clients.insert(addr, pending);
-                            return Ok(ServerResult::PacketToSend {
+                            Ok(ServerResult::PacketToSend {
                                 addr,
                                 payload: &mut self.out[..len],
-                            });
+                            })
+                        }
+                        // Still waiting on the auth worker
+                        _ => {
+                            self.pending_clients.insert(addr, pending);
+                            Ok(ServerResult::None)
                         }
-                        _ => return Ok(ServerResult::None),
                     }
                 }
                 _ => return Ok(ServerResult::None),
             }
         } else {
             // Handle new client
-            let packet = Packet::decode(buffer)?;
+            let packet = Packet::decode(buffer, None)?;
             match packet {
                 Packet::ConnectionRequest {
                     connection_prefix,
-                    connection_side_id,
+                    connection_side_id: 1,
                     client_identifier,
-                } => {
-                    if (connection_side_id) == 1 {
-                        return self.handle_connection_request(
-                            addr,
-                            connection_prefix,
-                            client_identifier,
-                        );
-                    } else {
-                        return Ok(ServerResult::None);
-                    }
-                }
+                    connect_token,
+                } if packet_len >= TRANSPORT_MIN_CONNECTION_REQUEST_BYTES => self
+                    .handle_connection_request(
+                        addr,
+                        client_identifier,
+                        connection_prefix,
+                        connect_token,
+                    ),
+                Packet::ConnectionResponse {
+                    client_identifier,
+                    cookie,
+                    public_key,
+                    connect_token,
+                } => self.handle_connection_response(
+                    addr,
+                    client_identifier,
+                    cookie,
+                    public_key,
+                    connect_token,
+                ),
                 _ => Ok(ServerResult::None),
             }
         }
@@ -501,14 +911,53 @@ impl TransportServer {
         self.max_clients = max_clients;
     }
 
+    /// Returns the slot of the connected client that sent a packet from an unknown address,
+    /// when its NAT mapping changed. Only packets newer than any received from the client can
+    /// move it, so replayed or late packets can not pull the connection back.
+    fn migrating_client(&self, buffer: &[u8]) -> Option<usize> {
+        let (client_id, sequence) = Packet::sealed_header(buffer)?;
+        let slot = *self.id_to_slot.get(&client_id)?;
+        let client = self.clients[slot].as_ref()?;
+
+        (client.state == ConnectionState::Connected
+            && sequence > client.replay_protection.most_recent_sequence())
+        .then_some(slot)
+    }
+
     /// Returns current number of clients connected.
     pub fn connected_clients(&self) -> usize {
-        self.clients.iter().filter(|slot| slot.is_some()).count()
+        self.id_to_slot.len()
+    }
+
+    fn insert_client(&mut self, slot: usize, client: Connection) {
+        self.addr_to_slot.insert(client.addr, slot);
+        self.id_to_slot.insert(client.client_id, slot);
+        if let Some(player_id) = &client.player_id {
+            self.player_to_slot.insert(player_id.clone(), slot);
+        }
+        self.clients[slot] = Some(client);
+    }
+
+    fn remove_client(&mut self, slot: usize) -> Option<Connection> {
+        let client = self.clients[slot].take()?;
+        // A kicked client may share its player id with the client that replaced it
+        if self.addr_to_slot.get(&client.addr) == Some(&slot) {
+            self.addr_to_slot.remove(&client.addr);
+        }
+        if let Some(player_id) = &client.player_id {
+            if self.player_to_slot.get(player_id) == Some(&slot) {
+                self.player_to_slot.remove(player_id);
+            }
+        }
+        self.id_to_slot.remove(&client.client_id);
+        self.rate_limiter.remove_client(client.client_id);
+        Some(client)
     }
 
     /// Advance the server current time, and remove any pending connections that have expired.
     pub fn update(&mut self, duration: Duration) {
         self.current_time += duration;
+        self.rate_limiter.update(self.current_time);
 
         for client in self.pending_clients.values_mut() {
             if self.current_time.as_secs() > client.expire_timestamp {
@@ -525,16 +974,14 @@ impl TransportServer {
     }
 
     pub fn update_client(&mut self, client_id: u64) -> ServerResult<'_, '_> {
-        let slot = match find_client_slot_by_id(&self.clients, client_id) {
+        let slot = match self.id_to_slot.get(&client_id).copied() {
             None => return ServerResult::None,
             Some(slot) => slot,
         };
 
         if let Some(client) = &mut self.clients[slot] {
-            let connection_timed_out = client.timeout_seconds > 0
-                && (client.last_packet_received_time
-                    + Duration::from_secs(client.timeout_seconds as u64)
-                    < self.current_time);
+            let connection_timed_out = !client.timeout.is_zero()
+                && (client.last_packet_received_time + client.timeout < self.current_time);
             if connection_timed_out {
                 tracing::debug!(
                     "Client {} disconnected, connection timed out",
@@ -546,17 +993,23 @@ impl TransportServer {
             if client.state == ConnectionState::Disconnected {
                 let packet = Packet::Disconnect {
                     client_identifier: client_id,
+                    reason: &[],
                 };
 
                 let addr = client.addr;
-                self.clients[slot] = None;
+                let reason = client
+                    .disconnect_reason
+                    .unwrap_or(DisconnectReason::Transport);
+                let encoded = client.encode(&packet, &mut self.out);
+                self.remove_client(slot);
 
-                let len = match packet.encode(&mut self.out) {
+                let len = match encoded {
                     Err(e) => {
                         tracing::error!("Failed to encode disconnect packet: {}", e);
                         return ServerResult::ClientDisconnected {
                             client_id,
                             addr,
+                            reason,
                             payload: None,
                         };
                     }
@@ -566,16 +1019,17 @@ impl TransportServer {
                 return ServerResult::ClientDisconnected {
                     client_id,
                     addr,
+                    reason,
                     payload: Some(&mut self.out[..len]),
                 };
             }
 
-            if client.last_packet_send_time + TRANSPORT_SEND_RATE <= self.current_time {
+            if client.last_packet_send_time + self.keep_alive_interval <= self.current_time {
                 let packet = Packet::KeepAlive {
                     client_identifier: client_id as u64,
                 };
 
-                let len = match packet.encode(&mut self.out) {
+                let len = match client.encode(&packet, &mut self.out) {
                     Err(e) => {
                         tracing::error!("Failed to encode keep alive packet: {}", e);
                         return ServerResult::None;
@@ -595,7 +1049,7 @@ impl TransportServer {
     }
 
     pub fn is_client_connected(&self, client_id: u64) -> bool {
-        find_client_slot_by_id(&self.clients, client_id).is_some()
+        self.id_to_slot.contains_key(&client_id)
     }
 
     /// Disconnect an client and returns its address and a disconnect packet to be sent to them.
@@ -603,18 +1057,35 @@ impl TransportServer {
     //       but the library user would need to be aware that he has to run
     //       the same code as Result::ClientDisconnected
     pub fn disconnect(&mut self, client_id: u64) -> ServerResult<'_, '_> {
-        if let Some(slot) = find_client_slot_by_id(&self.clients, client_id) {
-            let client = self.clients[slot].take().unwrap();
+        self.disconnect_with_reason(client_id, DisconnectReason::DisconnectedByServer, "")
+    }
+
+    /// Same as [`TransportServer::disconnect`], with the reason passed to the session and the
+    /// message shown to the player, cut to fit in the disconnect packet.
+    pub fn disconnect_with_reason(
+        &mut self,
+        client_id: u64,
+        reason: DisconnectReason,
+        message: &str,
+    ) -> ServerResult<'_, '_> {
+        if let Some(slot) = self.id_to_slot.get(&client_id).copied() {
+            let mut client = self.remove_client(slot).unwrap();
+            let mut message_len = message.len().min(TRANSPORT_MAX_PAYLOAD_BYTES);
+            while !message.is_char_boundary(message_len) {
+                message_len -= 1;
+            }
             let packet = Packet::Disconnect {
                 client_identifier: client_id,
+                reason: &message.as_bytes()[..message_len],
             };
 
-            let len = match packet.encode(&mut self.out) {
+            let len = match client.encode(&packet, &mut self.out) {
                 Err(e) => {
                     tracing::error!("Failed to encode disconnect packet: {}", e);
                     return ServerResult::ClientDisconnected {
                         client_id,
                         addr: client.addr,
+                        reason,
                         payload: None,
                     };
                 }
@@ -623,6 +1094,7 @@ impl TransportServer {
             return ServerResult::ClientDisconnected {
                 client_id,
                 addr: client.addr,
+                reason,
                 payload: Some(&mut self.out[..len]),
             };
         }
@@ -631,75 +1103,344 @@ impl TransportServer {
     }
 }
 
-fn find_client_mut_by_id(
-    clients: &mut [Option<Connection>],
+fn find_client_mut_by_id<'c>(
+    clients: &'c mut [Option<Connection>],
+    id_to_slot: &HashMap<u64, usize>,
     client_id: u64,
-) -> Option<&mut Connection> {
-    clients
-        .iter_mut()
-        .flatten()
-        .find(|c| c.client_id == client_id)
+) -> Option<&'c mut Connection> {
+    clients[*id_to_slot.get(&client_id)?].as_mut()
 }
 
-fn find_client_by_id(clients: &[Option<Connection>], client_id: u64) -> Option<&Connection> {
-    clients.iter().flatten().find(|c| c.client_id == client_id)
+fn find_client_by_id<'c>(
+    clients: &'c [Option<Connection>],
+    id_to_slot: &HashMap<u64, usize>,
+    client_id: u64,
+) -> Option<&'c Connection> {
+    clients[*id_to_slot.get(&client_id)?].as_ref()
 }
 
-fn find_client_slot_by_id(clients: &[Option<Connection>], client_id: u64) -> Option<usize> {
-    clients.iter().enumerate().find_map(|(i, c)| match c {
-        Some(c) if c.client_id == client_id => Some(i),
-        _ => None,
-    })
+/// Maps a connect token validation error to the reason sent to the client.
+fn token_denial_reason(error: &TransportServerError) -> DenialReason {
+    match error {
+        TransportServerError::Expired => DenialReason::TokenExpired,
+        _ => DenialReason::AuthFailed,
+    }
 }
 
-fn find_client_mut_by_addr(
-    clients: &mut [Option<Connection>],
-    addr: SocketAddr,
-) -> Option<(usize, &mut Connection)> {
-    clients.iter_mut().enumerate().find_map(|(i, c)| match c {
-        Some(c) if c.addr == addr => Some((i, c)),
-        _ => None,
-    })
+/// Reads the player id and session ticket from the application level connection request,
+/// the first message a client sends on the unreliable channel.
+fn parse_auth_payload(payload: &[u8]) -> Result<(String, String), TransportServerError> {
+    if payload.len() < 22 {
+        return Err(TransportServerError::PacketTooSmall);
+    }
+
+    let channel_id = payload[0];
+    let messages_len = payload[1];
+    let message_type = payload[5];
+    if channel_id != 0 || messages_len != 1 || message_type != 0 {
+        return Err(TransportServerError::InvalidPacketType);
+    }
+
+    let (player_id_bytes, session_ticket_bytes) = payload[6..].split_at(16);
+
+    let player_id = String::from_utf8(player_id_bytes.to_vec())
+        .map_err(|_| TransportServerError::InvalidPlayerId)?
+        .trim_end_matches(char::from(0))
+        .to_string();
+
+    let session_ticket = String::from_utf8(session_ticket_bytes.to_vec())
+        .map_err(|_| TransportServerError::InvalidSessionTicket)?
+        .trim_end_matches(char::from(0))
+        .to_string();
+
+    Ok((player_id, session_ticket))
 }
 
-async fn authenticate_player(
-    player_id: String,
-    session_ticket: String,
-    is_authenticated: Arc<Mutex<(bool, String)>>,
-) {
-    let client = reqwest::Client::new();
-    let playfab_api_key = std::env::var("PLAYFAB_API_KEY").unwrap();
-    let playfab_api_url = std::env::var("PLAYFAB_API_URL").unwrap();
-    let response = client
-        .post(format!(
-            "{}/Server/AuthenticateSessionTicket", // /Server/AuthenticateSessionTicket
-            playfab_api_url
-        ))
-        .header("X-SecretKey", playfab_api_key)
-        .json(&serde_json::json!({
-            "SessionTicket": session_ticket,
-        }))
-        .send()
-        .await;
-    match response {
-        Ok(response) => {
-            if response.status().is_success() {
-                match response.json::<serde_json::Value>().await {
-                    Ok(response_body) => {
-                        let mut is_authenticated = is_authenticated.lock().unwrap();
-                        is_authenticated.0 = true;
-                        is_authenticated.1 = player_id;
-                    }
-                    Err(e) => {
-                        tracing::error!("Failed to authenticate player: {}", e);
-                    }
-                }
-            } else {
-                tracing::error!("Failed to authenticate player: {}", response.status());
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use crate::{
+        constants::{TRANSPORT_CLIENT_TIMEOUT, TRANSPORT_USER_DATA_BYTES},
+        server::transport::simulated_client::test_server_config,
+    };
+
+    use super::*;
+
+    /// Adds a connected client to the server, returning the keys the client encrypts with.
+    fn connect_client(server: &mut TransportServer, client_id: u64) -> ConnectionKeys {
+        let server_exchange = KeyExchange::new(&mut rand::thread_rng());
+        let client_exchange = KeyExchange::new(&mut rand::thread_rng());
+        let server_public_key = server_exchange.public_key();
+        let keys = server_exchange
+            .server_keys(&client_exchange.public_key())
+            .unwrap();
+        let client_keys = client_exchange.client_keys(&server_public_key).unwrap();
+
+        let addr = SocketAddr::new("10.0.0.1".parse().unwrap(), client_id as u16);
+        let slot = server.clients.iter().position(|c| c.is_none()).unwrap();
+        server.insert_client(
+            slot,
+            Connection {
+                confirmed: true,
+                client_id,
+                state: ConnectionState::Connected,
+                player_id: Some(format!("player{client_id}")),
+                disconnect_reason: None,
+                addr,
+                keys,
+                server_public_key,
+                sequence: 0,
+                replay_protection: ReplayProtection::default(),
+                last_packet_received_time: server.current_time,
+                last_packet_send_time: server.current_time,
+                timeout: TRANSPORT_CLIENT_TIMEOUT,
+                expire_timestamp: 0,
+            },
+        );
+
+        client_keys
+    }
+
+    #[test]
+    fn indexes_follow_connects_and_disconnects() {
+        let server_addr = "127.0.0.1:5000".parse().unwrap();
+        let mut server = TransportServer::new(test_server_config(server_addr, 4));
+        for client_id in 1..=4 {
+            connect_client(&mut server, client_id);
+        }
+
+        server.disconnect(2);
+        connect_client(&mut server, 5);
+
+        assert_eq!(server.connected_clients(), 4);
+        assert!(!server.is_client_connected(2));
+        for client_id in [1, 3, 4, 5] {
+            let addr = server.client_addr(client_id).unwrap();
+            let slot = server.id_to_slot[&client_id];
+            assert_eq!(server.addr_to_slot[&addr], slot);
+            assert_eq!(server.clients[slot].as_ref().unwrap().client_id, client_id);
+        }
+    }
+
+    fn data_packet(client_id: u64, sequence: u64, keys: &ConnectionKeys) -> Vec<u8> {
+        let packet = Packet::Data {
+            client_identifier: client_id,
+            payload: &[0; 64],
+        };
+        let mut buffer = [0u8; TRANSPORT_MAX_PACKET_BYTES];
+        let len = packet
+            .encode(&mut buffer, Some((sequence, &keys.send_key)))
+            .unwrap();
+        buffer[..len].to_vec()
+    }
+
+    #[test]
+    fn migrates_client_to_new_address() {
+        let server_addr = "127.0.0.1:5000".parse().unwrap();
+        let mut server = TransportServer::new(test_server_config(server_addr, 4));
+        let keys = connect_client(&mut server, 1);
+        let other_keys = connect_client(&mut server, 2);
+        let old_addr = server.client_addr(1).unwrap();
+        let new_addr: SocketAddr = "10.0.0.1:40000".parse().unwrap();
+        let spoofed_addr: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+
+        // Sealed with another client keys
+        let mut packet = data_packet(1, 5, &other_keys);
+        assert_eq!(
+            server.process_packet(new_addr, &mut packet),
+            ServerResult::None
+        );
+        assert_eq!(server.client_addr(1), Some(old_addr));
+
+        let mut packet = data_packet(1, 5, &keys);
+        let replayed = packet.clone();
+        assert!(matches!(
+            server.process_packet(new_addr, &mut packet),
+            ServerResult::Payload { client_id: 1, .. }
+        ));
+        assert_eq!(server.client_addr(1), Some(new_addr));
+        assert!(!server.addr_to_slot.contains_key(&old_addr));
+
+        // Late packets from the old address, and replayed ones, do not move it back
+        let mut packet = data_packet(1, 3, &keys);
+        assert_eq!(
+            server.process_packet(old_addr, &mut packet),
+            ServerResult::None
+        );
+        let mut packet = replayed;
+        assert_eq!(
+            server.process_packet(spoofed_addr, &mut packet),
+            ServerResult::None
+        );
+        assert_eq!(server.client_addr(1), Some(new_addr));
+        assert_eq!(server.connected_clients(), 2);
+    }
+
+    #[test]
+    fn forged_migrations_do_not_drain_the_client_budget() {
+        let server_addr = "127.0.0.1:5000".parse().unwrap();
+        let mut server = TransportServer::new(test_server_config(server_addr, 4));
+        server.rate_limiter = RateLimiter::new(RateLimitConfig {
+            client_burst: 2.0,
+            ..Default::default()
+        });
+        let keys = connect_client(&mut server, 1);
+        let other_keys = connect_client(&mut server, 2);
+        let addr = server.client_addr(1).unwrap();
+
+        let spoofed_addr: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+        for sequence in 100..108 {
+            let mut packet = data_packet(1, sequence, &other_keys);
+            assert_eq!(
+                server.process_packet(spoofed_addr, &mut packet),
+                ServerResult::None
+            );
+        }
+
+        for sequence in 0..2 {
+            let mut packet = data_packet(1, sequence, &keys);
+            assert!(matches!(
+                server.process_packet(addr, &mut packet),
+                ServerResult::Payload { client_id: 1, .. }
+            ));
+        }
+        assert_eq!(server.client_addr(1), Some(addr));
+    }
+
+    #[test]
+    fn spoofed_floods_do_not_block_connected_clients() {
+        let server_addr = "127.0.0.1:5000".parse().unwrap();
+        let mut server = TransportServer::new(test_server_config(server_addr, 4));
+        server.rate_limiter = RateLimiter::new(RateLimitConfig {
+            ip_burst: 2.0,
+            block_after: 4,
+            ..Default::default()
+        });
+        let keys = connect_client(&mut server, 1);
+        let other_keys = connect_client(&mut server, 2);
+        let addr = server.client_addr(1).unwrap();
+        let neighbour_addr = SocketAddr::new(addr.ip(), 40000);
+
+        // Forged packets from the client address and from another port of its IP
+        for sequence in 100..120 {
+            for from in [addr, neighbour_addr] {
+                let mut packet = data_packet(1, sequence, &other_keys);
+                assert_eq!(server.process_packet(from, &mut packet), ServerResult::None);
             }
         }
-        Err(e) => {
-            tracing::error!("Failed to authenticate player: {}", e);
+        assert_eq!(server.rate_limit_stats().blocks, 1);
+
+        for sequence in 0..4 {
+            let mut packet = data_packet(1, sequence, &keys);
+            assert!(matches!(
+                server.process_packet(addr, &mut packet),
+                ServerResult::Payload { client_id: 1, .. }
+            ));
+        }
+        assert_eq!(server.client_addr(1), Some(addr));
+        assert!(server.is_client_connected(1));
+    }
+
+    #[test]
+    fn connect_tokens_are_bound_to_the_client_key() {
+        let server_addr = "127.0.0.1:5000".parse().unwrap();
+        let mut server = TransportServer::new(test_server_config(server_addr, 4));
+        let key = [7u8; TRANSPORT_KEY_BYTES];
+        server.connect_token_key = Some(key);
+
+        let client_exchange = KeyExchange::new(&mut rand::thread_rng());
+        let token = ConnectToken {
+            client_id: 1,
+            player_id: "player1".to_string(),
+            expire_timestamp: u64::MAX,
+            client_public_key: client_exchange.public_key(),
+            server_addresses: vec![server_addr],
+            user_data: [0; TRANSPORT_USER_DATA_BYTES],
+        }
+        .generate(&key)
+        .unwrap();
+
+        // Someone who copied the token connects with their own key
+        let attacker_addr: SocketAddr = "10.0.0.2:4000".parse().unwrap();
+        let attacker_key = KeyExchange::new(&mut rand::thread_rng()).public_key();
+        let cookie = server
+            .cookies
+            .generate(attacker_addr, 1, server.current_time);
+        server
+            .handle_connection_response(attacker_addr, 1, cookie, attacker_key, &token)
+            .unwrap();
+        assert!(!server.pending_clients.contains_key(&attacker_addr));
+
+        let addr: SocketAddr = "10.0.0.1:4000".parse().unwrap();
+        let cookie = server.cookies.generate(addr, 1, server.current_time);
+        server
+            .handle_connection_response(addr, 1, cookie, client_exchange.public_key(), &token)
+            .unwrap();
+        assert_eq!(
+            server.pending_clients[&addr].player_id.as_deref(),
+            Some("player1")
+        );
+    }
+
+    #[test]
+    fn routes_packets_of_the_last_client_of_a_full_server() {
+        let server_addr = "127.0.0.1:5000".parse().unwrap();
+        let mut server =
+            TransportServer::new(test_server_config(server_addr, TRANSPORT_MAX_CLIENTS));
+        let mut last_keys = None;
+        for client_id in 1..=TRANSPORT_MAX_CLIENTS as u64 {
+            last_keys = Some(connect_client(&mut server, client_id));
+        }
+        // A scan would reach the last client after every other slot
+        let keys = last_keys.unwrap();
+        let client_id = TRANSPORT_MAX_CLIENTS as u64;
+        let addr = server.client_addr(client_id).unwrap();
+
+        for sequence in 0..4 {
+            let mut packet = data_packet(client_id, sequence, &keys);
+            let result = server.process_packet(addr, &mut packet);
+            assert!(
+                matches!(result, ServerResult::Payload { client_id: id, .. } if id == client_id)
+            );
+        }
+    }
+    /// Run with `cargo test --release -- --ignored --nocapture process_packet_cost`.
+    #[test]
+    #[ignore]
+    fn process_packet_cost_by_client_count() {
+        const PACKETS: usize = 20_000;
+        let server_addr = "127.0.0.1:5000".parse().unwrap();
+
+        let mut costs = vec![];
+        for client_count in [1, 16, 256, TRANSPORT_MAX_CLIENTS] {
+            let mut server = TransportServer::new(test_server_config(server_addr, client_count));
+            server.rate_limiter = RateLimiter::new(RateLimitConfig {
+                client_burst: PACKETS as f64,
+                ..Default::default()
+            });
+            let mut last_keys = None;
+            for client_id in 1..=client_count as u64 {
+                last_keys = Some(connect_client(&mut server, client_id));
+            }
+            let keys = last_keys.unwrap();
+            let client_id = client_count as u64;
+            let addr = server.client_addr(client_id).unwrap();
+
+            let mut packets: Vec<Vec<u8>> = (0..PACKETS as u64)
+                .map(|sequence| data_packet(client_id, sequence, &keys))
+                .collect();
+
+            let start = Instant::now();
+            for packet in packets.iter_mut() {
+                let result = server.process_packet(addr, packet);
+                assert!(matches!(result, ServerResult::Payload { .. }));
+            }
+            let cost = start.elapsed() / PACKETS as u32;
+            println!("{client_count} clients: {cost:?} per packet");
+            costs.push(cost);
         }
+
+        assert!(costs.last().unwrap() < &(costs[0] * 2));
     }
 }
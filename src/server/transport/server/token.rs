@@ -0,0 +1,188 @@
+use std::{
+    io::{self, Cursor, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::constants::{
+    TRANSPORT_CONNECT_TOKEN_MAX_SERVERS, TRANSPORT_KEY_BYTES, TRANSPORT_USER_DATA_BYTES,
+};
+
+use super::{error::TransportServerError, serialize::*};
+
+type HmacSha256 = Hmac<Sha256>;
+
+const TOKEN_MAC_BYTES: usize = 32;
+
+/// A token issued by the backend that admits a player to the servers listed in it,
+/// without the server having to ask the identity provider about the player.
+///
+/// Tokens travel in plaintext, so they are bound to the public key the client uses in the
+/// handshake: a copied token is useless without the private key that goes with it.
+///
+/// Encoded as, all integers little endian:
+/// - `client_id`: u64
+/// - `expire_timestamp`: u64, seconds since the unix epoch
+/// - `player_id`: 16 bytes, zero padded
+/// - `client_public_key`: the key exchange public key of the client
+/// - number of server addresses: u8, followed by each address as
+///   its ip version (4 or 6), the ip octets and the port as u16
+/// - `user_data`: 256 bytes
+/// - HMAC-SHA256 of all the previous bytes, keyed with the key shared by the backend and the servers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectToken {
+    pub client_id: u64,
+    pub player_id: String,
+    pub expire_timestamp: u64,
+    pub client_public_key: [u8; TRANSPORT_KEY_BYTES],
+    pub server_addresses: Vec<SocketAddr>,
+    pub user_data: [u8; TRANSPORT_USER_DATA_BYTES],
+}
+
+impl ConnectToken {
+    /// Encodes and signs the token, this is what the backend sends to the client.
+    #[allow(dead_code)] // Tokens are issued by the backend, the server only verifies them
+    pub fn generate(&self, key: &[u8; TRANSPORT_KEY_BYTES]) -> Result<Vec<u8>, io::Error> {
+        if self.server_addresses.len() > TRANSPORT_CONNECT_TOKEN_MAX_SERVERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many server addresses in connect token",
+            ));
+        }
+
+        let mut writer = Cursor::new(Vec::new());
+        writer.write_all(&self.client_id.to_le_bytes())?;
+        writer.write_all(&self.expire_timestamp.to_le_bytes())?;
+        writer.write_all(&normalize_player_id(&self.player_id))?;
+        writer.write_all(&self.client_public_key)?;
+        writer.write_all(&[self.server_addresses.len() as u8])?;
+        for addr in self.server_addresses.iter() {
+            match addr.ip() {
+                IpAddr::V4(ip) => {
+                    writer.write_all(&[4])?;
+                    writer.write_all(&ip.octets())?;
+                }
+                IpAddr::V6(ip) => {
+                    writer.write_all(&[6])?;
+                    writer.write_all(&ip.octets())?;
+                }
+            }
+            writer.write_all(&addr.port().to_le_bytes())?;
+        }
+        writer.write_all(&self.user_data)?;
+
+        let mut token = writer.into_inner();
+        let mac = mac(key).chain_update(&token).finalize().into_bytes();
+        token.extend_from_slice(&mac);
+
+        Ok(token)
+    }
+
+    /// Checks the token signature and decodes it. Expiration and host list are left to the caller.
+    pub fn verify(
+        token: &[u8],
+        key: &[u8; TRANSPORT_KEY_BYTES],
+    ) -> Result<Self, TransportServerError> {
+        if token.len() < TOKEN_MAC_BYTES {
+            return Err(TransportServerError::InvalidConnectToken);
+        }
+
+        let (data, signature) = token.split_at(token.len() - TOKEN_MAC_BYTES);
+        mac(key)
+            .chain_update(data)
+            .verify_slice(signature)
+            .map_err(|_| TransportServerError::InvalidConnectToken)?;
+
+        Self::read(data).map_err(|_| TransportServerError::InvalidConnectToken)
+    }
+
+    fn read(data: &[u8]) -> Result<Self, io::Error> {
+        let cursor = &mut Cursor::new(data);
+        let client_id = read_u64(cursor)?;
+        let expire_timestamp = read_u64(cursor)?;
+        let player_id: [u8; 16] = read_bytes(cursor)?;
+        let player_id = String::from_utf8(player_id.to_vec())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            .trim_end_matches(char::from(0))
+            .to_string();
+        let client_public_key = read_bytes(cursor)?;
+
+        let num_server_addresses = read_u8(cursor)? as usize;
+        if num_server_addresses > TRANSPORT_CONNECT_TOKEN_MAX_SERVERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "too many server addresses in connect token",
+            ));
+        }
+
+        let mut server_addresses = Vec::with_capacity(num_server_addresses);
+        for _ in 0..num_server_addresses {
+            let ip = match read_u8(cursor)? {
+                4 => IpAddr::V4(Ipv4Addr::from(read_bytes::<4>(cursor)?)),
+                6 => IpAddr::V6(Ipv6Addr::from(read_bytes::<16>(cursor)?)),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid address type in connect token",
+                    ))
+                }
+            };
+            let port = read_u16(cursor)?;
+            server_addresses.push(SocketAddr::new(ip, port));
+        }
+
+        let user_data = read_bytes(cursor)?;
+
+        Ok(Self {
+            client_id,
+            player_id,
+            expire_timestamp,
+            client_public_key,
+            server_addresses,
+            user_data,
+        })
+    }
+}
+
+fn mac(key: &[u8; TRANSPORT_KEY_BYTES]) -> HmacSha256 {
+    HmacSha256::new_from_slice(key).expect("HMAC can take a key of any size")
+}
+
+#[allow(dead_code)]
+fn normalize_player_id(player_id: &str) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    let player_id_bytes = player_id.as_bytes();
+    let len = player_id_bytes.len().min(16);
+    bytes[..len].copy_from_slice(&player_id_bytes[..len]);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_roundtrip() {
+        let key = [3u8; TRANSPORT_KEY_BYTES];
+        let token = ConnectToken {
+            client_id: 42,
+            player_id: "player1".to_string(),
+            expire_timestamp: 1_700_000_000,
+            client_public_key: [5u8; TRANSPORT_KEY_BYTES],
+            server_addresses: vec![
+                "127.0.0.1:5000".parse().unwrap(),
+                "[::1]:5000".parse().unwrap(),
+            ],
+            user_data: [9u8; TRANSPORT_USER_DATA_BYTES],
+        };
+
+        let mut bytes = token.generate(&key).unwrap();
+        assert_eq!(ConnectToken::verify(&bytes, &key).unwrap(), token);
+        assert!(ConnectToken::verify(&bytes, &[4u8; TRANSPORT_KEY_BYTES]).is_err());
+
+        bytes[0] ^= 1;
+        assert!(ConnectToken::verify(&bytes, &key).is_err());
+    }
+}
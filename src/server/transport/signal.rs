@@ -0,0 +1,79 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal, Signal, SignalKind};
+
+/// Set once the process receives SIGINT or SIGTERM. A second signal exits right away, for when
+/// the graceful shutdown hangs.
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal {
+    received: Arc<AtomicBool>,
+}
+
+impl ShutdownSignal {
+    /// Listens for the signals on a thread of its own.
+    pub fn listen() -> io::Result<Self> {
+        let shutdown_signal = Self::default();
+        let received = shutdown_signal.received.clone();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .build()?;
+
+        #[cfg(unix)]
+        let terminate = {
+            let _guard = runtime.enter();
+            signal(SignalKind::terminate())?
+        };
+
+        std::thread::Builder::new()
+            .name("signals".to_string())
+            .spawn(move || {
+                #[cfg(unix)]
+                let result = runtime.block_on(wait_for_signals(received, terminate));
+                #[cfg(not(unix))]
+                let result = runtime.block_on(wait_for_signals(received));
+                if let Err(e) = result {
+                    tracing::error!("Stopped listening for signals: {e}");
+                }
+            })?;
+
+        Ok(shutdown_signal)
+    }
+
+    pub fn is_received(&self) -> bool {
+        self.received.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_signals(received: Arc<AtomicBool>, mut terminate: Signal) -> io::Result<()> {
+    loop {
+        let name = tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|_| "SIGINT")?,
+            _ = terminate.recv() => "SIGTERM",
+        };
+        on_signal(&received, name);
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signals(received: Arc<AtomicBool>) -> io::Result<()> {
+    loop {
+        tokio::signal::ctrl_c().await?;
+        on_signal(&received, "Ctrl-C");
+    }
+}
+
+fn on_signal(received: &AtomicBool, name: &str) {
+    if received.swap(true, Ordering::SeqCst) {
+        tracing::warn!("Received {name} again, exiting now");
+        std::process::exit(130);
+    }
+    tracing::info!("Received {name}, shutting down");
+}
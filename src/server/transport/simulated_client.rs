@@ -0,0 +1,225 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use crate::constants::{
+    TRANSPORT_CLIENT_TIMEOUT, TRANSPORT_COOKIE_BYTES, TRANSPORT_MAX_PACKET_BYTES,
+    TRANSPORT_MIN_CONNECTION_REQUEST_BYTES, TRANSPORT_PROTOCOL_PREFIX, TRANSPORT_SEND_RATE,
+};
+
+use super::{
+    server::{
+        auth::AlwaysAcceptAuth,
+        auth_worker::AuthWorkerConfig,
+        crypto::{ConnectionKeys, KeyExchange, ReplayProtection},
+        packet::{DenialReason, Packet},
+        rate_limit::RateLimitConfig,
+        server::{DuplicateLoginPolicy, ServerConfig},
+    },
+    socket::{InMemoryNetwork, InMemorySocket, TransportSocket},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SimulatedClientState {
+    SendingRequest,
+    SendingResponse([u8; TRANSPORT_COOKIE_BYTES]),
+    SendingAuthentication,
+    Connected,
+    Denied(DenialReason),
+    Disconnected,
+}
+
+/// A client that goes through the handshake the same way the game client does,
+/// used to drive a [`ServerTransport`] in tests.
+///
+/// [`ServerTransport`]: super::transport::ServerTransport
+#[derive(Debug)]
+pub struct SimulatedClient {
+    socket: InMemorySocket,
+    server_addr: SocketAddr,
+    client_id: u64,
+    player_id: String,
+    state: SimulatedClientState,
+    key_exchange: Option<KeyExchange>,
+    keys: Option<ConnectionKeys>,
+    sequence: u64,
+    replay_protection: ReplayProtection,
+    received_payloads: Vec<Vec<u8>>,
+    /// The reason sent by the server when it disconnected the client.
+    disconnect_reason: Option<String>,
+}
+
+/// Server config accepting every player, with the given public address.
+pub fn test_server_config(public_addr: SocketAddr, max_clients: usize) -> ServerConfig {
+    ServerConfig {
+        current_time: Duration::from_secs(1_000),
+        max_clients,
+        public_addresses: vec![public_addr],
+        connect_token_key: None,
+        auth_provider: Arc::new(AlwaysAcceptAuth),
+        auth_worker: AuthWorkerConfig::default(),
+        rate_limit: RateLimitConfig::default(),
+        duplicate_login: DuplicateLoginPolicy::default(),
+        keep_alive_interval: TRANSPORT_SEND_RATE,
+        client_timeout: TRANSPORT_CLIENT_TIMEOUT,
+        rng_seed: None,
+    }
+}
+
+impl SimulatedClient {
+    pub fn new(
+        network: &InMemoryNetwork,
+        addr: SocketAddr,
+        server_addr: SocketAddr,
+        client_id: u64,
+        player_id: &str,
+    ) -> Self {
+        Self {
+            socket: network.bind(addr),
+            server_addr,
+            client_id,
+            player_id: player_id.to_string(),
+            state: SimulatedClientState::SendingRequest,
+            key_exchange: None,
+            keys: None,
+            sequence: 0,
+            replay_protection: ReplayProtection::default(),
+            received_payloads: Vec::new(),
+            disconnect_reason: None,
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.state == SimulatedClientState::Connected
+    }
+
+    pub fn state(&self) -> SimulatedClientState {
+        self.state
+    }
+
+    #[allow(dead_code)]
+    pub fn disconnect_reason(&self) -> Option<&str> {
+        self.disconnect_reason.as_deref()
+    }
+
+    /// Payloads received since the last call.
+    #[allow(dead_code)]
+    pub fn take_payloads(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.received_payloads)
+    }
+
+    /// Processes the packets received from the server, then sends the packet for the current
+    /// handshake step again, so lost packets are eventually retried.
+    pub fn update(&mut self) {
+        let mut buffer = [0u8; TRANSPORT_MAX_PACKET_BYTES];
+        while let Ok((len, addr)) = self.socket.recv_from(&mut buffer) {
+            if addr == self.server_addr {
+                self.process_packet(&mut buffer[..len]);
+            }
+        }
+
+        match self.state {
+            SimulatedClientState::SendingRequest => {
+                let packet = Packet::ConnectionRequest {
+                    connection_prefix: TRANSPORT_PROTOCOL_PREFIX,
+                    connection_side_id: 1,
+                    client_identifier: self.client_id,
+                    connect_token: &[],
+                };
+                // Requests are padded so the server does not drop them
+                let mut buffer = [0u8; TRANSPORT_MAX_PACKET_BYTES];
+                let len = packet.encode(&mut buffer, None).unwrap();
+                self.send(&buffer[..len.max(TRANSPORT_MIN_CONNECTION_REQUEST_BYTES)]);
+            }
+            SimulatedClientState::SendingResponse(cookie) => {
+                let key_exchange = self
+                    .key_exchange
+                    .get_or_insert_with(|| KeyExchange::new(&mut rand::thread_rng()));
+                let packet = Packet::ConnectionResponse {
+                    client_identifier: self.client_id,
+                    cookie,
+                    public_key: key_exchange.public_key(),
+                    connect_token: &[],
+                };
+                self.send_packet(&packet);
+            }
+            SimulatedClientState::SendingAuthentication => {
+                let payload = self.auth_payload();
+                self.send_payload(&payload);
+            }
+            _ => {}
+        }
+    }
+
+    /// Sends a data packet, once the keys are exchanged.
+    pub fn send_payload(&mut self, payload: &[u8]) {
+        let packet = Packet::Data {
+            client_identifier: self.client_id,
+            payload,
+        };
+        self.send_packet(&packet);
+    }
+
+    fn process_packet(&mut self, buffer: &mut [u8]) {
+        let crypto = self
+            .keys
+            .as_ref()
+            .map(|keys| (&keys.receive_key, &mut self.replay_protection));
+        let Ok(packet) = Packet::decode(buffer, crypto) else {
+            return;
+        };
+
+        match (self.state, packet) {
+            (_, Packet::ConnectionDenied { reason, .. }) if !self.is_connected() => {
+                self.state = SimulatedClientState::Denied(reason);
+            }
+            (SimulatedClientState::SendingRequest, Packet::ConnectionChallenge { cookie, .. }) => {
+                self.state = SimulatedClientState::SendingResponse(cookie);
+            }
+            (SimulatedClientState::SendingResponse(_), Packet::KeyExchange { public_key, .. }) => {
+                let key_exchange = self.key_exchange.take().unwrap();
+                self.keys = key_exchange.client_keys(&public_key);
+                self.state = SimulatedClientState::SendingAuthentication;
+            }
+            (SimulatedClientState::SendingAuthentication, Packet::KeepAlive { .. }) => {
+                self.state = SimulatedClientState::Connected;
+            }
+            (SimulatedClientState::Connected, Packet::Data { payload, .. }) => {
+                self.received_payloads.push(payload.to_vec());
+            }
+            (_, Packet::Disconnect { reason, .. }) => {
+                self.state = SimulatedClientState::Disconnected;
+                self.disconnect_reason = Some(String::from_utf8_lossy(reason).into_owned());
+            }
+            _ => {}
+        }
+    }
+
+    /// The application level connection request: one message on channel 0 with the player id
+    /// and session ticket.
+    fn auth_payload(&self) -> Vec<u8> {
+        let mut payload = vec![0, 1, 0, 0, 0, 0];
+        let mut player_id = [0u8; 16];
+        player_id[..self.player_id.len()].copy_from_slice(self.player_id.as_bytes());
+        payload.extend_from_slice(&player_id);
+        payload.extend_from_slice(b"ticket");
+        payload
+    }
+
+    fn send_packet(&mut self, packet: &Packet) {
+        let mut buffer = [0u8; TRANSPORT_MAX_PACKET_BYTES];
+        let len = match &self.keys {
+            Some(keys) if packet.packet_type().is_sealed() => {
+                let len = packet
+                    .encode(&mut buffer, Some((self.sequence, &keys.send_key)))
+                    .unwrap();
+                self.sequence += 1;
+                len
+            }
+            _ => packet.encode(&mut buffer, None).unwrap(),
+        };
+        self.send(&buffer[..len]);
+    }
+
+    fn send(&mut self, packet: &[u8]) {
+        self.socket.send_to(packet, self.server_addr).unwrap();
+    }
+}
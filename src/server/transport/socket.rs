@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    fmt, io,
+    net::{SocketAddr, UdpSocket},
+    sync::{Arc, Mutex},
+};
+
+use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
+
+/// The datagram socket [`ServerTransport`] sends and receives packets with.
+///
+/// [`ServerTransport`]: super::transport::ServerTransport
+pub trait TransportSocket: fmt::Debug + Send + Sync {
+    /// Receives a single packet. Fails with [`io::ErrorKind::WouldBlock`] when there is none.
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)>;
+    fn send_to(&mut self, packet: &[u8], addr: SocketAddr) -> io::Result<usize>;
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+/// Non blocking UDP socket.
+#[derive(Debug)]
+pub struct UdpTransportSocket {
+    socket: UdpSocket,
+}
+
+impl UdpTransportSocket {
+    pub fn new(socket: UdpSocket) -> io::Result<Self> {
+        socket.set_nonblocking(true)?;
+
+        Ok(Self { socket })
+    }
+}
+
+impl TransportSocket for UdpTransportSocket {
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.socket.recv_from(buffer)
+    }
+
+    fn send_to(&mut self, packet: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.socket.send_to(packet, addr)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
+impl TransportSocket for Box<dyn TransportSocket> {
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.as_mut().recv_from(buffer)
+    }
+
+    fn send_to(&mut self, packet: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.as_mut().send_to(packet, addr)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.as_ref().local_addr()
+    }
+}
+
+type Datagram = (SocketAddr, Vec<u8>);
+
+/// Delivers packets between [`InMemorySocket`]s in the same process.
+/// Like UDP, packets sent to an address without a socket are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryNetwork {
+    sockets: Arc<Mutex<HashMap<SocketAddr, Sender<Datagram>>>>,
+}
+
+/// A socket bound to an address of an [`InMemoryNetwork`].
+#[derive(Debug)]
+pub struct InMemorySocket {
+    addr: SocketAddr,
+    network: InMemoryNetwork,
+    receiver: Receiver<Datagram>,
+}
+
+impl InMemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds a socket to the address, replacing any socket already bound to it.
+    /// Packets sent to a dropped socket are lost.
+    pub fn bind(&self, addr: SocketAddr) -> InMemorySocket {
+        let (sender, receiver) = unbounded();
+        self.sockets.lock().unwrap().insert(addr, sender);
+
+        InMemorySocket {
+            addr,
+            network: self.clone(),
+            receiver,
+        }
+    }
+
+    fn deliver(&self, from: SocketAddr, to: SocketAddr, packet: &[u8]) {
+        if let Some(sender) = self.sockets.lock().unwrap().get(&to) {
+            let _ = sender.send((from, packet.to_vec()));
+        }
+    }
+}
+
+impl TransportSocket for InMemorySocket {
+    fn recv_from(&mut self, buffer: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        match self.receiver.try_recv() {
+            Ok((from, packet)) => {
+                // Datagrams that do not fit the buffer are truncated, like UDP does
+                let len = packet.len().min(buffer.len());
+                buffer[..len].copy_from_slice(&packet[..len]);
+                Ok((len, from))
+            }
+            Err(TryRecvError::Empty) => Err(io::ErrorKind::WouldBlock.into()),
+            Err(TryRecvError::Disconnected) => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn send_to(&mut self, packet: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.network.deliver(self.addr, addr, packet);
+        Ok(packet.len())
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
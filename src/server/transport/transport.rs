@@ -1,7 +1,8 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io,
-    net::{SocketAddr, UdpSocket},
+    net::SocketAddr,
+    thread::JoinHandle,
     time::{Duration, Instant},
 };
 
@@ -9,14 +10,27 @@ use bevy::prelude::Resource;
 use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
 
 use crate::{
-    constants::{MAIN_SESSION_ID, TRANSPORT_MAX_PACKET_BYTES},
-    server::server::ClientId,
-    sessions::new_session,
+    constants::{
+        MAIN_SESSION_ID, SESSION_IDLE_TIMEOUT, SESSION_MAX_CREATED_PER_PLAYER, SESSION_MAX_OPEN,
+        SESSION_MAX_PLAYERS, TRANSPORT_MAX_PACKET_BYTES,
+    },
+    server::{
+        connection::UnityClient,
+        error::DisconnectReason,
+        server::{ClientId, PlayerInfo},
+    },
+    sessions::{new_session, SessionConfig},
 };
 
 use super::{
+    capture::{CaptureEvent, CaptureHeader, CaptureSocket, PacketCapture},
     error::TransportError,
-    server::server::{ServerConfig, ServerResult, TransportServer},
+    server::{
+        auth_worker::AuthResult,
+        rate_limit::RateLimitStats,
+        server::{ServerConfig, ServerResult, TransportServer},
+    },
+    socket::TransportSocket,
 };
 
 pub enum ToDenariaServerMessage {
@@ -29,11 +43,56 @@ pub enum ToDenariaServerMessage {
     },
     ClientDisconnected {
         client_id: u64,
+        reason: DisconnectReason,
+    },
+    /// The client leaves the session for another one, the session hands its connection back
+    /// with `ClientMovedOut`.
+    MoveClient {
+        client_id: u64,
+        session_id: u32,
+    },
+    /// Sent instead of `ClientConnected` to the session a client moved to, with its connection
+    /// so the sequences of its channels go on.
+    ClientMoved {
+        client_id: u64,
+        player_id: String,
+        connection: Box<UnityClient>,
     },
     Payload {
         client_id: u64,
         payload: Vec<u8>,
     },
+    /// Sent after `ClientConnected`, to tell the client which session it is in.
+    SessionJoined {
+        client_id: u64,
+        session_id: u32,
+    },
+    SessionJoinFailed {
+        client_id: u64,
+        session_id: u32,
+    },
+    SessionCreateFailed {
+        client_id: u64,
+        reason: CreateSessionError,
+    },
+    SessionList {
+        client_id: u64,
+        sessions: Vec<SessionInfo>,
+    },
+    /// The server shuts down in the given seconds, sent to every client of the session.
+    ServerClosing {
+        seconds_left: u32,
+    },
+    /// Sent to every client of the session.
+    Announcement {
+        text: String,
+    },
+    /// The session sends the players it runs to `reply`.
+    PlayersInfo {
+        reply: Sender<Vec<PlayerInfo>>,
+    },
+    /// The session stops its app, its clients were moved or disconnected before.
+    Shutdown,
 }
 
 pub enum FromDenariaServerMessage {
@@ -41,52 +100,324 @@ pub enum FromDenariaServerMessage {
         client_id: u64,
         packets: Vec<Vec<u8>>,
     },
+    /// The client asked for a new session, it is moved to it once created.
+    CreateSession {
+        client_id: u64,
+    },
+    JoinSession {
+        client_id: u64,
+        session_id: u32,
+    },
+    /// The connection of a client leaving the session, `None` when the session did not have it.
+    ClientMovedOut {
+        client_id: u64,
+        session_id: u32,
+        connection: Option<Box<UnityClient>>,
+    },
+    ListSessions {
+        client_id: u64,
+    },
+    /// The session finished its setup and runs its game loop.
+    SessionStarted {
+        session_id: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// The session thread is setting up the level, clients routed to it wait for it.
+    Starting,
+    Running,
+    /// The session was asked to shut down, no client is routed to it anymore.
+    Draining,
+    /// The session thread finished and was joined.
+    Stopped,
+}
+
+/// Why a client could not create a session, sent to the client as a byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateSessionError {
+    /// The server runs as many sessions as it is allowed to.
+    TooManySessions = 0,
+    /// The player already created as many of the open sessions as it is allowed to.
+    TooManyCreated = 1,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    pub id: u32,
+    pub state: SessionState,
+    pub players: usize,
+}
+
+/// A game session running on its own thread, with the clients routed to it.
+#[derive(Debug)]
+struct Session {
+    tx: Sender<ToDenariaServerMessage>,
+    clients: HashSet<u64>,
+    state: SessionState,
+    thread: Option<JoinHandle<()>>,
+    /// When the last client left the session.
+    empty_since: Option<Duration>,
+    /// The player that asked for the session, `None` for the ones started by the server.
+    creator: Option<String>,
+}
+
+impl Session {
+    fn is_open(&self) -> bool {
+        matches!(self.state, SessionState::Starting | SessionState::Running)
+    }
+
+    fn has_room(&self, max_players: usize) -> bool {
+        self.is_open() && self.clients.len() < max_players
+    }
 }
 
 #[derive(Debug, Resource)]
 pub struct ServerTransport {
-    socket: UdpSocket,
+    socket: Box<dyn TransportSocket>,
     transport_server: TransportServer,
     buffer: [u8; TRANSPORT_MAX_PACKET_BYTES],
     from_denaria_server_rx: Receiver<FromDenariaServerMessage>,
     from_denaria_server_tx: Sender<FromDenariaServerMessage>,
+    /// The session holding the entity of each player, kept after they disconnect so they
+    /// reconnect to it.
     player_id_session_map: HashMap<String, u32>,
-    session_to_denaria_server_tx: HashMap<u32, Sender<ToDenariaServerMessage>>,
+    sessions: HashMap<u32, Session>,
+    next_session_id: u32,
+    /// Sessions other than the main one are shut down after being empty this long.
+    session_idle_timeout: Duration,
+    /// Connecting clients go to another session when theirs has this many players.
+    max_players_per_session: usize,
+    /// Clients can not create sessions once this many are open.
+    max_open_sessions: usize,
+    /// Open sessions a player can have created.
+    max_sessions_created_per_player: usize,
+    /// Settings of the sessions created from now on.
+    session_config: SessionConfig,
     client_id_to_server_tx_map: HashMap<u64, Sender<ToDenariaServerMessage>>,
+    /// Messages of the clients moving between sessions, held until their old session hands
+    /// their connection over.
+    moving_clients: HashMap<u64, Receiver<ToDenariaServerMessage>>,
+    capture: Option<PacketCapture>,
+    /// Sum of the update durations, to time the sessions.
+    current_time: Duration,
+    /// When the server shuts down, set by [`ServerTransport::begin_shutdown`].
+    shutdown_at: Option<Duration>,
+    /// The countdown last sent to the clients.
+    shutdown_seconds_left: Option<u32>,
 }
 
 impl ServerTransport {
-    pub fn new(server_config: ServerConfig, socket: UdpSocket) -> Result<Self, std::io::Error> {
-        socket.set_nonblocking(true)?;
-
+    pub fn new(
+        server_config: ServerConfig,
+        socket: impl TransportSocket + 'static,
+    ) -> Result<Self, std::io::Error> {
         let transport_server = TransportServer::new(server_config);
 
         let (from_denaria_server_tx, from_denaria_server_rx) =
             unbounded::<FromDenariaServerMessage>();
 
         Ok(Self {
-            socket,
+            socket: Box::new(socket),
             transport_server,
             buffer: [0; TRANSPORT_MAX_PACKET_BYTES],
             from_denaria_server_rx,
             from_denaria_server_tx,
             player_id_session_map: HashMap::new(),
-            session_to_denaria_server_tx: HashMap::new(),
+            sessions: HashMap::new(),
+            next_session_id: MAIN_SESSION_ID + 1,
+            session_idle_timeout: SESSION_IDLE_TIMEOUT,
+            max_players_per_session: SESSION_MAX_PLAYERS,
+            max_open_sessions: SESSION_MAX_OPEN,
+            max_sessions_created_per_player: SESSION_MAX_CREATED_PER_PLAYER,
+            session_config: SessionConfig::default(),
             client_id_to_server_tx_map: HashMap::new(),
+            moving_clients: HashMap::new(),
+            capture: None,
+            current_time: Duration::ZERO,
+            shutdown_at: None,
+            shutdown_seconds_left: None,
         })
     }
 
+    /// Same as [`ServerTransport::new`], also writing every datagram, update and finished
+    /// authentication to a capture that can be replayed with [`replay`].
+    ///
+    /// [`replay`]: super::replay::replay
+    pub fn with_capture(
+        mut server_config: ServerConfig,
+        socket: impl TransportSocket + 'static,
+        writer: impl io::Write + Send + 'static,
+    ) -> Result<Self, std::io::Error> {
+        // The replayed server must generate the same keys and cookies
+        let rng_seed = *server_config.rng_seed.get_or_insert_with(rand::random);
+        let capture = PacketCapture::new(
+            writer,
+            &CaptureHeader {
+                current_time: server_config.current_time,
+                max_clients: server_config.max_clients,
+                public_addresses: server_config.public_addresses.clone(),
+                rng_seed,
+            },
+        )?;
+
+        let mut transport = Self::new(server_config, CaptureSocket::new(socket, capture.clone()))?;
+        transport.capture = Some(capture);
+
+        Ok(transport)
+    }
+
     pub fn create_session(&mut self, id: u32) {
         // create bevy app in a new thread giving the channel receiver to the DenariaServer
         let (tx, rx) = unbounded::<ToDenariaServerMessage>();
 
         let from_denaria_server_tx = self.from_denaria_server_tx.clone();
+        let config = self.session_config.clone();
 
-        self.session_to_denaria_server_tx.insert(id, tx);
+        let thread = std::thread::Builder::new()
+            .name(format!("session-{id}"))
+            .spawn(move || {
+                new_session(id, config, from_denaria_server_tx, rx);
+            });
+        match thread {
+            Ok(thread) => self.add_session(id, tx, Some(thread)),
+            Err(e) => tracing::error!("Failed to spawn the thread of session {id}: {e}"),
+        }
+    }
 
-        std::thread::spawn(move || {
-            new_session(from_denaria_server_tx, rx);
-        });
+    fn add_session(
+        &mut self,
+        id: u32,
+        tx: Sender<ToDenariaServerMessage>,
+        thread: Option<JoinHandle<()>>,
+    ) {
+        self.next_session_id = self.next_session_id.max(id + 1);
+        self.sessions.insert(
+            id,
+            Session {
+                tx,
+                clients: HashSet::new(),
+                state: SessionState::Starting,
+                thread,
+                empty_since: Some(self.current_time),
+                creator: None,
+            },
+        );
+    }
+
+    /// Returns the sessions that are not stopped, ordered by id.
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = self
+            .sessions
+            .iter()
+            .map(|(id, session)| SessionInfo {
+                id: *id,
+                state: session.state,
+                players: session.clients.len(),
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.id);
+        sessions
+    }
+
+    pub fn set_session_idle_timeout(&mut self, timeout: Duration) {
+        self.session_idle_timeout = timeout;
+    }
+
+    pub fn set_max_players_per_session(&mut self, max_players: usize) {
+        self.max_players_per_session = max_players;
+    }
+
+    pub fn set_max_open_sessions(&mut self, max_sessions: usize) {
+        self.max_open_sessions = max_sessions;
+    }
+
+    pub fn set_max_sessions_created_per_player(&mut self, max_sessions: usize) {
+        self.max_sessions_created_per_player = max_sessions;
+    }
+
+    pub fn set_session_config(&mut self, config: SessionConfig) {
+        self.session_config = config;
+    }
+
+    /// Asks the session to stop, it is removed once its thread finished.
+    pub fn shutdown_session(&mut self, id: u32) {
+        let Some(session) = self.sessions.get_mut(&id) else {
+            return;
+        };
+        if !session.is_open() {
+            return;
+        }
+
+        tracing::info!("Shutting down session {id}");
+        session.state = SessionState::Draining;
+        if let Err(e) = session.tx.send(ToDenariaServerMessage::Shutdown) {
+            tracing::error!("Failed to send shutdown to session {id}: {e}");
+        }
+    }
+
+    /// Shuts down the idle sessions and joins the threads of the finished ones.
+    fn update_sessions(&mut self) {
+        let idle: Vec<u32> = self
+            .sessions
+            .iter_mut()
+            .filter(|(id, _)| **id != MAIN_SESSION_ID)
+            .filter_map(|(id, session)| {
+                if !session.clients.is_empty() {
+                    session.empty_since = None;
+                    return None;
+                }
+                let empty_since = *session.empty_since.get_or_insert(self.current_time);
+                let idle =
+                    self.current_time.saturating_sub(empty_since) >= self.session_idle_timeout;
+                (idle && session.is_open()).then_some(*id)
+            })
+            .collect();
+        for id in idle {
+            tracing::info!("Session {id} is empty");
+            self.shutdown_session(id);
+        }
+
+        let mut stopped = vec![];
+        for (id, session) in self.sessions.iter_mut() {
+            if !session
+                .thread
+                .as_ref()
+                .is_none_or(|thread| thread.is_finished())
+            {
+                continue;
+            }
+            // A session without a thread is never started, it stops once drained
+            if session.thread.is_none() && session.state != SessionState::Draining {
+                continue;
+            }
+            if let Some(thread) = session.thread.take() {
+                if thread.join().is_err() {
+                    tracing::error!("Session {id} panicked");
+                }
+            }
+            tracing::info!("Session {id} stopped");
+            session.state = SessionState::Stopped;
+            stopped.extend(session.clients.iter().copied());
+        }
+        self.sessions
+            .retain(|_, session| session.state != SessionState::Stopped);
+        self.player_id_session_map
+            .retain(|_, session_id| self.sessions.contains_key(session_id));
+
+        // Clients of a session that stopped on its own can not play anymore
+        for client_id in stopped {
+            let server_result = self.transport_server.disconnect(client_id);
+            handle_server_result(
+                server_result,
+                self.socket.as_mut(),
+                &mut self.player_id_session_map,
+                &mut self.sessions,
+                &mut self.client_id_to_server_tx_map,
+                self.max_players_per_session,
+            );
+        }
     }
 
     /// Returns the server public address
@@ -104,6 +435,11 @@ impl ServerTransport {
         self.transport_server.connected_clients()
     }
 
+    /// Returns how many packets and connections were dropped by the rate limiter.
+    pub fn rate_limit_stats(&self) -> RateLimitStats {
+        self.transport_server.rate_limit_stats()
+    }
+
     /// Returns the client address if connected.
     pub fn client_addr(&self, client_id: ClientId) -> Option<SocketAddr> {
         self.transport_server.client_addr(client_id.raw())
@@ -114,24 +450,149 @@ impl ServerTransport {
     pub fn disconnect_all(&mut self) {
         for client_id in self.transport_server.clients_id() {
             let server_result = self.transport_server.disconnect(client_id);
-            // get tx map by client id and send disconnect message
-            if let Some(sender) = self.client_id_to_server_tx_map.get_mut(&client_id) {
-                if let Err(e) =
-                    sender.send(ToDenariaServerMessage::ClientDisconnected { client_id })
-                {
-                    tracing::error!("Failed to send disconnect message to client {client_id}: {e}");
-                }
-            }
             handle_server_result(
                 server_result,
-                &self.socket,
+                self.socket.as_mut(),
                 &mut self.player_id_session_map,
-                &self.session_to_denaria_server_tx,
+                &mut self.sessions,
                 &mut self.client_id_to_server_tx_map,
+                self.max_players_per_session,
             );
         }
     }
 
+    /// Disconnects the client playing as the player, sending it the message. Returns whether it
+    /// was connected.
+    pub fn kick_player(
+        &mut self,
+        player_id: &str,
+        reason: DisconnectReason,
+        message: &str,
+    ) -> bool {
+        let Some(client_id) = self.transport_server.client_id_by_player_id(player_id) else {
+            return false;
+        };
+
+        let server_result = self
+            .transport_server
+            .disconnect_with_reason(client_id, reason, message);
+        handle_server_result(
+            server_result,
+            self.socket.as_mut(),
+            &mut self.player_id_session_map,
+            &mut self.sessions,
+            &mut self.client_id_to_server_tx_map,
+            self.max_players_per_session,
+        );
+        true
+    }
+
+    /// Disconnects the player and denies it from connecting again.
+    pub fn ban_player(&mut self, player_id: &str, reason: String) {
+        self.transport_server
+            .ban_player(player_id.to_string(), reason.clone());
+        self.kick_player(player_id, DisconnectReason::Banned, &reason);
+    }
+
+    /// Returns whether the player was banned.
+    pub fn unban_player(&mut self, player_id: &str) -> bool {
+        self.transport_server.unban_player(player_id)
+    }
+
+    /// Sends the text to every player, in every session.
+    pub fn announce(&self, text: &str) {
+        self.send_to_open_sessions("announcement", || ToDenariaServerMessage::Announcement {
+            text: text.to_string(),
+        });
+    }
+
+    /// Asks every session for its players. The sessions answer on the returned channel, which
+    /// disconnects once they all did.
+    pub fn players_info(&self) -> Receiver<Vec<PlayerInfo>> {
+        let (tx, rx) = unbounded();
+        self.send_to_open_sessions("players request", || ToDenariaServerMessage::PlayersInfo {
+            reply: tx.clone(),
+        });
+        rx
+    }
+
+    /// Stops accepting clients and counts down to the shutdown with the connected ones. They
+    /// are disconnected by [`ServerTransport::shutdown`], once [`ServerTransport::is_drained`].
+    pub fn begin_shutdown(&mut self, drain_period: Duration) {
+        if self.shutdown_at.is_some() {
+            return;
+        }
+
+        tracing::info!("Shutting down in {:?}", drain_period);
+        self.transport_server.stop_accepting();
+        self.shutdown_at = Some(self.current_time + drain_period);
+        self.send_shutdown_countdown();
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_at.is_some()
+    }
+
+    /// Returns whether the drain period started by [`ServerTransport::begin_shutdown`] is over.
+    pub fn is_drained(&self) -> bool {
+        self.shutdown_at
+            .is_some_and(|shutdown_at| self.current_time >= shutdown_at)
+    }
+
+    /// Tells the clients every second how long is left before the shutdown.
+    fn send_shutdown_countdown(&mut self) {
+        let Some(shutdown_at) = self.shutdown_at else {
+            return;
+        };
+        let seconds_left = shutdown_at
+            .saturating_sub(self.current_time)
+            .as_secs_f64()
+            .ceil() as u32;
+        if self.shutdown_seconds_left == Some(seconds_left) {
+            return;
+        }
+
+        self.shutdown_seconds_left = Some(seconds_left);
+        self.send_to_open_sessions("shutdown countdown", || {
+            ToDenariaServerMessage::ServerClosing { seconds_left }
+        });
+    }
+
+    /// Sends a message made by `message` to every open session.
+    fn send_to_open_sessions(&self, name: &str, message: impl Fn() -> ToDenariaServerMessage) {
+        for (id, session) in self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.is_open())
+        {
+            if let Err(e) = session.tx.send(message()) {
+                tracing::error!("Failed to send {name} to session {id}: {e}");
+            }
+        }
+    }
+
+    /// Disconnects every client and stops the sessions, waiting for their threads to finish.
+    /// Returns whether every session stopped without panicking.
+    pub fn shutdown(&mut self) -> bool {
+        self.disconnect_all();
+        let ids: Vec<u32> = self.sessions.keys().copied().collect();
+        for id in ids {
+            self.shutdown_session(id);
+        }
+
+        let mut clean = true;
+        for (id, session) in self.sessions.drain() {
+            if let Some(thread) = session.thread {
+                if thread.join().is_err() {
+                    tracing::error!("Session {id} panicked");
+                    clean = false;
+                }
+            }
+        }
+        self.player_id_session_map.clear();
+        clean
+    }
+
     /// Returns the duration since the connected client last received a packet.
     /// Usefull to detect users that are timing out.
     pub fn time_since_last_received_packet(&self, client_id: ClientId) -> Option<Duration> {
@@ -141,6 +602,29 @@ impl ServerTransport {
 
     /// Advances the transport by the duration, and receive packets from the network.
     pub fn update(&mut self, duration: Duration) -> Result<(), TransportError> {
+        self.update_with(duration, TransportServer::poll_auth_result)
+    }
+
+    /// Same as [`ServerTransport::update`], completing the captured authentications instead of
+    /// the ones finished by the auth worker.
+    pub fn replay_update(
+        &mut self,
+        duration: Duration,
+        auth_results: Vec<AuthResult>,
+    ) -> Result<(), TransportError> {
+        let mut auth_results = auth_results.into_iter();
+        self.update_with(duration, |_| auth_results.next())
+    }
+
+    fn update_with(
+        &mut self,
+        duration: Duration,
+        mut next_auth_result: impl FnMut(&mut TransportServer) -> Option<AuthResult>,
+    ) -> Result<(), TransportError> {
+        if let Some(capture) = &self.capture {
+            capture.record(&CaptureEvent::Tick { duration });
+        }
+        self.current_time += duration;
         self.transport_server.update(duration);
 
         loop {
@@ -150,14 +634,16 @@ impl ServerTransport {
                         .transport_server
                         .process_packet(addr, &mut self.buffer[..len]);
 
-                    if let Some(new_session_details) = handle_server_result(
+                    let unrouted = handle_server_result(
                         server_result,
-                        &self.socket,
+                        self.socket.as_mut(),
                         &mut self.player_id_session_map,
-                        &self.session_to_denaria_server_tx,
+                        &mut self.sessions,
                         &mut self.client_id_to_server_tx_map,
-                    ) {
-                        self.create_session(new_session_details.id);
+                        self.max_players_per_session,
+                    );
+                    if let Some(message) = unrouted {
+                        self.connect_to_new_session(message);
                     }
                 }
                 Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
@@ -167,14 +653,33 @@ impl ServerTransport {
             };
         }
 
+        while let Some(auth_result) = next_auth_result(&mut self.transport_server) {
+            if let Some(capture) = &self.capture {
+                capture.record(&CaptureEvent::Authenticated(auth_result.clone()));
+            }
+            let server_result = self.transport_server.complete_authentication(auth_result);
+            let unrouted = handle_server_result(
+                server_result,
+                self.socket.as_mut(),
+                &mut self.player_id_session_map,
+                &mut self.sessions,
+                &mut self.client_id_to_server_tx_map,
+                self.max_players_per_session,
+            );
+            if let Some(message) = unrouted {
+                self.connect_to_new_session(message);
+            }
+        }
+
         for client_id in self.transport_server.clients_id() {
             let server_result = self.transport_server.update_client(client_id);
             handle_server_result(
                 server_result,
-                &self.socket,
+                self.socket.as_mut(),
                 &mut self.player_id_session_map,
-                &self.session_to_denaria_server_tx,
+                &mut self.sessions,
                 &mut self.client_id_to_server_tx_map,
+                self.max_players_per_session,
             );
         }
         // for disconnection_id in server.disconnections_id() {
@@ -182,6 +687,13 @@ impl ServerTransport {
         //     handle_server_result(server_result, &self.socket);
         // }
 
+        self.send_shutdown_countdown();
+        self.update_sessions();
+
+        if let Some(capture) = &self.capture {
+            capture.flush();
+        }
+
         Ok(())
     }
 
@@ -221,7 +733,6 @@ impl ServerTransport {
                                     "Failed to send packet to client {client_id} ({addr}): {e}"
                                 );
                             }
-                            break;
                         }
                         Err(e) => {
                             tracing::error!(
@@ -232,23 +743,338 @@ impl ServerTransport {
                     }
                 }
             }
+            FromDenariaServerMessage::CreateSession { client_id } => {
+                let Some(player_id) = self.transport_server.client_player_id(client_id) else {
+                    return;
+                };
+                let player_id = player_id.to_string();
+                if let Err(reason) = self.can_create_session(&player_id) {
+                    tracing::info!("Client {client_id} can not create a session: {reason:?}");
+                    self.send_to_client_session(
+                        client_id,
+                        ToDenariaServerMessage::SessionCreateFailed { client_id, reason },
+                    );
+                    return;
+                }
+
+                let session_id = self.next_session_id;
+                tracing::info!("Client {client_id} created session {session_id}");
+                self.create_session(session_id);
+                if let Some(session) = self.sessions.get_mut(&session_id) {
+                    session.creator = Some(player_id);
+                }
+                self.move_client(client_id, session_id);
+            }
+            FromDenariaServerMessage::JoinSession {
+                client_id,
+                session_id,
+            } => self.move_client(client_id, session_id),
+            FromDenariaServerMessage::ClientMovedOut {
+                client_id,
+                session_id,
+                connection,
+            } => self.move_client_in(client_id, session_id, connection),
+            FromDenariaServerMessage::SessionStarted { session_id } => {
+                if let Some(session) = self.sessions.get_mut(&session_id) {
+                    if session.state == SessionState::Starting {
+                        tracing::info!("Session {session_id} is running");
+                        session.state = SessionState::Running;
+                    }
+                }
+            }
+            FromDenariaServerMessage::ListSessions { client_id } => {
+                let sessions = self
+                    .sessions()
+                    .into_iter()
+                    .filter(|session| session.state != SessionState::Draining)
+                    .collect();
+                self.send_to_client_session(
+                    client_id,
+                    ToDenariaServerMessage::SessionList {
+                        client_id,
+                        sessions,
+                    },
+                );
+            }
+        }
+    }
+
+    fn can_create_session(&self, player_id: &str) -> Result<(), CreateSessionError> {
+        let open = || self.sessions.values().filter(|session| session.is_open());
+        if open().count() >= self.max_open_sessions {
+            return Err(CreateSessionError::TooManySessions);
+        }
+        if open()
+            .filter(|session| session.creator.as_deref() == Some(player_id))
+            .count()
+            >= self.max_sessions_created_per_player
+        {
+            return Err(CreateSessionError::TooManyCreated);
+        }
+        Ok(())
+    }
+
+    /// Moves a connected client from its session to another one. Its entity in the old
+    /// session is removed, like when it disconnects, and its connection is handed over.
+    fn move_client(&mut self, client_id: u64, session_id: u32) {
+        let (Some(addr), Some(player_id)) = (
+            self.transport_server.client_addr(client_id),
+            self.transport_server.client_player_id(client_id),
+        ) else {
+            return;
+        };
+        let player_id = player_id.to_string();
+
+        let current_session_id = self.player_id_session_map.get(&player_id).copied();
+        if current_session_id == Some(session_id) {
+            self.send_to_client_session(
+                client_id,
+                ToDenariaServerMessage::SessionJoined {
+                    client_id,
+                    session_id,
+                },
+            );
+            return;
+        }
+        if !self
+            .sessions
+            .get(&session_id)
+            .is_some_and(|session| session.has_room(self.max_players_per_session))
+        {
+            self.send_to_client_session(
+                client_id,
+                ToDenariaServerMessage::SessionJoinFailed {
+                    client_id,
+                    session_id,
+                },
+            );
+            return;
+        }
+
+        if let Some(old_session) = current_session_id.and_then(|id| self.sessions.get_mut(&id)) {
+            old_session.clients.remove(&client_id);
+            match old_session.tx.send(ToDenariaServerMessage::MoveClient {
+                client_id,
+                session_id,
+            }) {
+                Ok(()) => {
+                    // The client holds its place in the session until its connection is back,
+                    // its payloads wait for it there
+                    self.player_id_session_map.insert(player_id, session_id);
+                    if let Some(session) = self.sessions.get_mut(&session_id) {
+                        session.clients.insert(client_id);
+                    }
+                    let (tx, rx) = unbounded();
+                    self.client_id_to_server_tx_map.insert(client_id, tx);
+                    self.moving_clients.insert(client_id, rx);
+                    return;
+                }
+                Err(e) => {
+                    tracing::error!("Failed to move client {client_id} out of its session: {e}")
+                }
+            }
+        }
+
+        tracing::info!("Client {client_id} moved to session {session_id}");
+        connect_to_session(
+            &mut self.player_id_session_map,
+            &mut self.sessions,
+            &mut self.client_id_to_server_tx_map,
+            session_id,
+            ToDenariaServerMessage::ClientConnected {
+                client_id,
+                addr,
+                payload: vec![],
+                player_id,
+            },
+        );
+    }
+
+    /// Routes a client to the session it is moving to, once its old session handed over the
+    /// connection.
+    fn move_client_in(
+        &mut self,
+        client_id: u64,
+        session_id: u32,
+        connection: Option<Box<UnityClient>>,
+    ) {
+        let held = self.moving_clients.remove(&client_id);
+        // Disconnected while moving
+        let (Some(addr), Some(player_id)) = (
+            self.transport_server.client_addr(client_id),
+            self.transport_server.client_player_id(client_id),
+        ) else {
+            return;
+        };
+        let player_id = player_id.to_string();
+
+        if !self
+            .sessions
+            .get(&session_id)
+            .is_some_and(|session| session.is_open())
+        {
+            tracing::error!("Session {session_id} closed while client {client_id} moved to it");
+            let server_result = self.transport_server.disconnect(client_id);
+            handle_server_result(
+                server_result,
+                self.socket.as_mut(),
+                &mut self.player_id_session_map,
+                &mut self.sessions,
+                &mut self.client_id_to_server_tx_map,
+                self.max_players_per_session,
+            );
+            return;
+        }
+
+        let message = match connection {
+            Some(connection) => ToDenariaServerMessage::ClientMoved {
+                client_id,
+                player_id,
+                connection,
+            },
+            None => ToDenariaServerMessage::ClientConnected {
+                client_id,
+                addr,
+                payload: vec![],
+                player_id,
+            },
+        };
+        tracing::info!("Client {client_id} moved to session {session_id}");
+        connect_to_session(
+            &mut self.player_id_session_map,
+            &mut self.sessions,
+            &mut self.client_id_to_server_tx_map,
+            session_id,
+            message,
+        );
+        for message in held.iter().flat_map(Receiver::try_iter) {
+            self.send_to_client_session(client_id, message);
+        }
+    }
+
+    /// Starts a session for a connecting client that did not fit in the running ones, unless
+    /// as many sessions as allowed are open.
+    fn connect_to_new_session(&mut self, message: ToDenariaServerMessage) {
+        let ToDenariaServerMessage::ClientConnected { client_id, .. } = message else {
+            return;
+        };
+        let open_sessions = self
+            .sessions
+            .values()
+            .filter(|session| session.is_open())
+            .count();
+        let session_id = self.next_session_id;
+        if open_sessions < self.max_open_sessions {
+            self.create_session(session_id);
+        }
+        if !self.sessions.contains_key(&session_id) {
+            tracing::warn!("No session for client {client_id}, disconnecting it");
+            let server_result = self.transport_server.disconnect_with_reason(
+                client_id,
+                DisconnectReason::DisconnectedByServer,
+                "every session is full",
+            );
+            handle_server_result(
+                server_result,
+                self.socket.as_mut(),
+                &mut self.player_id_session_map,
+                &mut self.sessions,
+                &mut self.client_id_to_server_tx_map,
+                self.max_players_per_session,
+            );
+            return;
+        }
+
+        tracing::info!("Sessions are full, started session {session_id}");
+        connect_to_session(
+            &mut self.player_id_session_map,
+            &mut self.sessions,
+            &mut self.client_id_to_server_tx_map,
+            session_id,
+            message,
+        );
+    }
+
+    fn send_to_client_session(&self, client_id: u64, message: ToDenariaServerMessage) {
+        if let Some(sender) = self.client_id_to_server_tx_map.get(&client_id) {
+            if let Err(e) = sender.send(message) {
+                tracing::error!("Failed to send message to the session of client {client_id}: {e}");
+            }
         }
     }
 }
 
-struct NewSessionDetails {
-    id: u32,
-    player_ids: Vec<String>,
+/// Returns the session a connecting player goes to: the one it played in while it is open,
+/// otherwise the first open session with room, `None` when they are all full.
+fn allocate_session(
+    sessions: &HashMap<u32, Session>,
+    max_players: usize,
+    previous_session_id: Option<u32>,
+) -> Option<u32> {
+    // A link-dead player keeps its entity there, even if others took the room it left
+    if let Some(session_id) =
+        previous_session_id.filter(|id| sessions.get(id).is_some_and(Session::is_open))
+    {
+        return Some(session_id);
+    }
+
+    sessions
+        .iter()
+        .filter(|(_, session)| session.has_room(max_players))
+        .map(|(id, _)| *id)
+        .min()
+}
+
+/// Routes a connecting client to the session and tells the client, the message must be a
+/// `ClientConnected` or a `ClientMoved`.
+fn connect_to_session(
+    player_id_session_map: &mut HashMap<String, u32>,
+    sessions: &mut HashMap<u32, Session>,
+    client_id_to_server_tx_map: &mut HashMap<u64, Sender<ToDenariaServerMessage>>,
+    session_id: u32,
+    message: ToDenariaServerMessage,
+) {
+    let (ToDenariaServerMessage::ClientConnected {
+        client_id,
+        ref player_id,
+        ..
+    }
+    | ToDenariaServerMessage::ClientMoved {
+        client_id,
+        ref player_id,
+        ..
+    }) = message
+    else {
+        return;
+    };
+    let Some(session) = sessions.get_mut(&session_id) else {
+        return;
+    };
+
+    player_id_session_map.insert(player_id.clone(), session_id);
+    client_id_to_server_tx_map.insert(client_id, session.tx.clone());
+    session.clients.insert(client_id);
+    if let Err(e) = session.tx.send(message) {
+        tracing::error!("Failed to send client connected message to client {client_id}: {e}");
+    }
+    if let Err(e) = session.tx.send(ToDenariaServerMessage::SessionJoined {
+        client_id,
+        session_id,
+    }) {
+        tracing::error!("Failed to send session joined message to client {client_id}: {e}");
+    }
 }
 
+/// Returns the `ClientConnected` message of a connecting client when every session is full.
 fn handle_server_result(
     server_result: ServerResult,
-    socket: &UdpSocket,
+    socket: &mut dyn TransportSocket,
     player_id_session_map: &mut HashMap<String, u32>,
-    session_to_denaria_server_tx: &HashMap<u32, Sender<ToDenariaServerMessage>>,
+    sessions: &mut HashMap<u32, Session>,
     client_id_to_server_tx_map: &mut HashMap<u64, Sender<ToDenariaServerMessage>>,
-) -> Option<NewSessionDetails> {
-    let send_packet = |packet: &[u8], addr: SocketAddr| {
+    max_players_per_session: usize,
+) -> Option<ToDenariaServerMessage> {
+    let mut send_packet = |packet: &[u8], addr: SocketAddr| {
         if let Err(err) = socket.send_to(packet, addr) {
             tracing::error!("Failed to send packet to {addr}: {err}");
         }
@@ -280,30 +1106,41 @@ fn handle_server_result(
             payload,
             player_id,
         } => {
-            if let Some(sender) = session_to_denaria_server_tx.get(&MAIN_SESSION_ID) {
-                if let Err(e) = sender.send(ToDenariaServerMessage::ClientConnected {
-                    client_id,
-                    addr,
-                    payload: payload.to_vec(),
-                    player_id: player_id.clone(),
-                }) {
-                    tracing::error!(
-                        "Failed to send client connected message to client {client_id}: {e}"
-                    );
-                }
-                player_id_session_map.insert(player_id, MAIN_SESSION_ID);
-                client_id_to_server_tx_map.insert(client_id, sender.clone());
-            }
             send_packet(payload, addr);
+            // A player reconnecting goes back to its session, where its entity may still be
+            let previous_session_id = player_id_session_map.get(&player_id).copied();
+            let message = ToDenariaServerMessage::ClientConnected {
+                client_id,
+                addr,
+                payload: payload.to_vec(),
+                player_id,
+            };
+            let Some(session_id) =
+                allocate_session(sessions, max_players_per_session, previous_session_id)
+            else {
+                // Without any session clients are only connected, like when replaying captures
+                return (!sessions.is_empty()).then_some(message);
+            };
+            connect_to_session(
+                player_id_session_map,
+                sessions,
+                client_id_to_server_tx_map,
+                session_id,
+                message,
+            );
         }
         ServerResult::ClientDisconnected {
             client_id,
             addr,
+            reason,
             payload,
         } => {
-            if let Some(sender) = client_id_to_server_tx_map.get(&client_id) {
+            for session in sessions.values_mut() {
+                session.clients.remove(&client_id);
+            }
+            if let Some(sender) = client_id_to_server_tx_map.remove(&client_id) {
                 if let Err(e) =
-                    sender.send(ToDenariaServerMessage::ClientDisconnected { client_id })
+                    sender.send(ToDenariaServerMessage::ClientDisconnected { client_id, reason })
                 {
                     tracing::error!(
                         "Failed to send client disconnected message to client {client_id}: {e}"
@@ -315,5 +1152,536 @@ fn handle_server_result(
             }
         }
     }
+
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use crate::{
+        constants::TICK_DELTA,
+        server::transport::{
+            conditioner::{ConditionedSocket, NetworkConditions},
+            server::{packet::DenialReason, server::DuplicateLoginPolicy},
+            simulated_client::{test_server_config, SimulatedClient, SimulatedClientState},
+            socket::InMemoryNetwork,
+        },
+    };
+
+    use super::*;
+
+    fn simulated_clients(
+        network: &InMemoryNetwork,
+        server_addr: SocketAddr,
+        count: u16,
+    ) -> Vec<SimulatedClient> {
+        (0..count)
+            .map(|i| {
+                // Every client has its own IP address, like players do
+                let addr =
+                    SocketAddr::new(Ipv4Addr::new(10, 0, (i >> 8) as u8, i as u8).into(), 10_000);
+                SimulatedClient::new(
+                    network,
+                    addr,
+                    server_addr,
+                    i as u64 + 1,
+                    &format!("player{i}"),
+                )
+            })
+            .collect()
+    }
+
+    fn connect_all(transport: &mut ServerTransport, clients: &mut [SimulatedClient]) {
+        for _ in 0..500 {
+            clients.iter_mut().for_each(|client| client.update());
+            transport.update(TICK_DELTA).unwrap();
+            if transport.connected_clients() == clients.len() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn connects_many_clients_over_in_memory_network() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut transport = ServerTransport::new(
+            test_server_config(server_addr, 64),
+            network.bind(server_addr),
+        )
+        .unwrap();
+
+        let mut clients = simulated_clients(&network, server_addr, 50);
+        connect_all(&mut transport, &mut clients);
+
+        assert_eq!(transport.connected_clients(), clients.len());
+        clients.iter_mut().for_each(|client| client.update());
+        assert!(clients.iter().all(|client| client.is_connected()));
+    }
+
+    #[test]
+    fn sends_every_packet_of_a_batch() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut transport = ServerTransport::new(
+            test_server_config(server_addr, 8),
+            network.bind(server_addr),
+        )
+        .unwrap();
+
+        let mut clients = simulated_clients(&network, server_addr, 1);
+        connect_all(&mut transport, &mut clients);
+        clients[0].update();
+        assert!(clients[0].is_connected());
+        clients[0].take_payloads();
+
+        let packets: Vec<Vec<u8>> = (0..5).map(|i| vec![i; 10 + i as usize]).collect();
+        transport
+            .from_denaria_server_tx
+            .send(FromDenariaServerMessage::SendPacket {
+                client_id: 1,
+                packets: packets.clone(),
+            })
+            .unwrap();
+        transport.send_packets();
+        clients[0].update();
+
+        assert_eq!(clients[0].take_payloads(), packets);
+    }
+
+    #[test]
+    fn connects_clients_over_bad_network() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let socket = ConditionedSocket::with_seed(
+            network.bind(server_addr),
+            Some(NetworkConditions {
+                loss: 20.,
+                duplicate: 10.,
+                reorder: 10.,
+                ..Default::default()
+            }),
+            7,
+        );
+        let mut transport =
+            ServerTransport::new(test_server_config(server_addr, 64), socket).unwrap();
+
+        let mut clients = simulated_clients(&network, server_addr, 10);
+        connect_all(&mut transport, &mut clients);
+
+        assert_eq!(transport.connected_clients(), clients.len());
+    }
+
+    /// Connects two clients with the same player id, one after the other.
+    fn login_twice(
+        policy: DuplicateLoginPolicy,
+    ) -> (
+        ServerTransport,
+        Receiver<ToDenariaServerMessage>,
+        Vec<SimulatedClient>,
+    ) {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut config = test_server_config(server_addr, 8);
+        config.duplicate_login = policy;
+        let mut transport = ServerTransport::new(config, network.bind(server_addr)).unwrap();
+        let (session_tx, session_rx) = unbounded();
+        transport.add_session(MAIN_SESSION_ID, session_tx, None);
+
+        let mut clients: Vec<SimulatedClient> = (0..2)
+            .map(|i| {
+                let addr = SocketAddr::new(Ipv4Addr::new(10, 0, 0, i + 1).into(), 10_000);
+                SimulatedClient::new(&network, addr, server_addr, i as u64 + 1, "player")
+            })
+            .collect();
+        connect_all(&mut transport, &mut clients[..1]);
+        for _ in 0..100 {
+            clients.iter_mut().for_each(|client| client.update());
+            transport.update(TICK_DELTA).unwrap();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        (transport, session_rx, clients)
+    }
+
+    #[test]
+    fn duplicate_logins_follow_policy() {
+        let (transport, session_rx, clients) = login_twice(DuplicateLoginPolicy::KickOld);
+        assert_eq!(clients[0].state(), SimulatedClientState::Disconnected);
+        assert!(clients[1].is_connected());
+        assert_eq!(transport.connected_clients(), 1);
+        assert!(transport.client_id_to_server_tx_map.keys().eq([&2]));
+        assert_eq!(
+            transport.player_id_session_map.get("player"),
+            Some(&MAIN_SESSION_ID)
+        );
+
+        let events: Vec<_> = session_rx
+            .try_iter()
+            .filter_map(|message| match message {
+                ToDenariaServerMessage::ClientConnected { client_id, .. } => {
+                    Some((client_id, None))
+                }
+                ToDenariaServerMessage::ClientDisconnected { client_id, reason } => {
+                    Some((client_id, Some(reason)))
+                }
+                ToDenariaServerMessage::SessionJoined { .. } => None,
+                _ => panic!("unexpected session message"),
+            })
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (1, None),
+                (2, None),
+                (1, Some(DisconnectReason::DuplicateLogin))
+            ]
+        );
+
+        let (transport, session_rx, clients) = login_twice(DuplicateLoginPolicy::RejectNew);
+        assert!(clients[0].is_connected());
+        assert_eq!(transport.connected_clients(), 1);
+        assert!(transport.client_id_to_server_tx_map.keys().eq([&1]));
+        assert_eq!(
+            session_rx
+                .try_iter()
+                .filter(|message| matches!(message, ToDenariaServerMessage::ClientConnected { .. }))
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn moves_clients_between_sessions() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut transport = ServerTransport::new(
+            test_server_config(server_addr, 8),
+            network.bind(server_addr),
+        )
+        .unwrap();
+        let (main_tx, main_rx) = unbounded();
+        let (other_tx, other_rx) = unbounded();
+        transport.add_session(MAIN_SESSION_ID, main_tx, None);
+        transport.add_session(7, other_tx, None);
+
+        let mut clients = simulated_clients(&network, server_addr, 2);
+        connect_all(&mut transport, &mut clients);
+        // Connected and joined messages of both clients
+        assert_eq!(main_rx.try_iter().count(), 4);
+
+        let to_transport = transport.from_denaria_server_tx.clone();
+        to_transport
+            .send(FromDenariaServerMessage::JoinSession {
+                client_id: 1,
+                session_id: 7,
+            })
+            .unwrap();
+        to_transport
+            .send(FromDenariaServerMessage::ListSessions { client_id: 2 })
+            .unwrap();
+        transport.send_packets();
+
+        assert!(matches!(
+            main_rx.try_recv(),
+            Ok(ToDenariaServerMessage::MoveClient {
+                client_id: 1,
+                session_id: 7
+            })
+        ));
+        let sessions = vec![
+            SessionInfo {
+                id: MAIN_SESSION_ID,
+                state: SessionState::Starting,
+                players: 1,
+            },
+            SessionInfo {
+                id: 7,
+                state: SessionState::Starting,
+                players: 1,
+            },
+        ];
+        assert!(matches!(
+            main_rx.try_recv(),
+            Ok(ToDenariaServerMessage::SessionList { client_id: 2, sessions: list }) if list == sessions
+        ));
+
+        // Payloads sent during the hand-over wait for the connection in the new session
+        clients[0].send_payload(&[42]);
+        transport.update(TICK_DELTA).unwrap();
+        assert!(main_rx.try_recv().is_err());
+        assert!(other_rx.try_recv().is_err());
+
+        // The main session hands the connection over
+        let mut connection = UnityClient::new_from_server(Default::default());
+        connection.set_connected("player0".to_string());
+        to_transport
+            .send(FromDenariaServerMessage::ClientMovedOut {
+                client_id: 1,
+                session_id: 7,
+                connection: Some(Box::new(connection)),
+            })
+            .unwrap();
+        to_transport
+            .send(FromDenariaServerMessage::JoinSession {
+                client_id: 1,
+                session_id: 8,
+            })
+            .unwrap();
+        transport.send_packets();
+
+        assert!(matches!(
+            other_rx.try_recv(),
+            Ok(ToDenariaServerMessage::ClientMoved { client_id: 1, .. })
+        ));
+        assert!(matches!(
+            other_rx.try_recv(),
+            Ok(ToDenariaServerMessage::SessionJoined {
+                client_id: 1,
+                session_id: 7
+            })
+        ));
+        assert!(matches!(
+            other_rx.try_recv(),
+            Ok(ToDenariaServerMessage::Payload { client_id: 1, payload }) if payload == [42]
+        ));
+        assert!(matches!(
+            other_rx.try_recv(),
+            Ok(ToDenariaServerMessage::SessionJoinFailed {
+                client_id: 1,
+                session_id: 8
+            })
+        ));
+        assert_eq!(transport.player_id_session_map.get("player0"), Some(&7));
+    }
+
+    #[test]
+    fn limits_session_creation() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut transport = ServerTransport::new(
+            test_server_config(server_addr, 8),
+            network.bind(server_addr),
+        )
+        .unwrap();
+        let (main_tx, main_rx) = unbounded();
+        let (other_tx, _other_rx) = unbounded();
+        transport.add_session(MAIN_SESSION_ID, main_tx, None);
+        transport.add_session(7, other_tx, None);
+        transport.sessions.get_mut(&7).unwrap().creator = Some("player0".to_string());
+        transport.set_max_open_sessions(2);
+
+        let mut clients = simulated_clients(&network, server_addr, 1);
+        connect_all(&mut transport, &mut clients);
+        main_rx.try_iter().for_each(drop);
+
+        let create = |transport: &mut ServerTransport| {
+            transport
+                .from_denaria_server_tx
+                .send(FromDenariaServerMessage::CreateSession { client_id: 1 })
+                .unwrap();
+            transport.send_packets();
+        };
+        create(&mut transport);
+        assert!(matches!(
+            main_rx.try_recv(),
+            Ok(ToDenariaServerMessage::SessionCreateFailed {
+                client_id: 1,
+                reason: CreateSessionError::TooManySessions
+            })
+        ));
+
+        transport.set_max_open_sessions(3);
+        create(&mut transport);
+        assert!(matches!(
+            main_rx.try_recv(),
+            Ok(ToDenariaServerMessage::SessionCreateFailed {
+                client_id: 1,
+                reason: CreateSessionError::TooManyCreated
+            })
+        ));
+        assert_eq!(transport.sessions().len(), 2);
+    }
+
+    #[test]
+    fn shuts_down_idle_sessions() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut transport = ServerTransport::new(
+            test_server_config(server_addr, 8),
+            network.bind(server_addr),
+        )
+        .unwrap();
+        transport.set_session_idle_timeout(Duration::from_secs(10));
+        let (main_tx, _main_rx) = unbounded();
+        transport.add_session(MAIN_SESSION_ID, main_tx, None);
+
+        // Stands in for the session app, running until it is shut down
+        let (session_tx, session_rx) = unbounded();
+        let thread = std::thread::spawn(move || {
+            while let Ok(message) = session_rx.recv() {
+                if let ToDenariaServerMessage::Shutdown = message {
+                    break;
+                }
+            }
+        });
+        transport.add_session(7, session_tx, Some(thread));
+        transport
+            .from_denaria_server_tx
+            .send(FromDenariaServerMessage::SessionStarted { session_id: 7 })
+            .unwrap();
+        transport.send_packets();
+
+        transport.update(Duration::from_secs(5)).unwrap();
+        assert_eq!(transport.sessions()[1].state, SessionState::Running);
+
+        transport.update(Duration::from_secs(5)).unwrap();
+        for _ in 0..100 {
+            if transport.sessions().len() == 1 {
+                break;
+            }
+            assert_eq!(transport.sessions()[1].state, SessionState::Draining);
+            std::thread::sleep(Duration::from_millis(1));
+            transport.update(TICK_DELTA).unwrap();
+        }
+
+        // The main session is kept even when empty
+        assert_eq!(transport.sessions().len(), 1);
+        assert_eq!(transport.sessions()[0].id, MAIN_SESSION_ID);
+    }
+
+    #[test]
+    fn shards_full_sessions() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut transport = ServerTransport::new(
+            test_server_config(server_addr, 8),
+            network.bind(server_addr),
+        )
+        .unwrap();
+        transport.set_max_players_per_session(2);
+        let (main_tx, main_rx) = unbounded();
+        let (other_tx, other_rx) = unbounded();
+        transport.add_session(MAIN_SESSION_ID, main_tx, None);
+        transport.add_session(7, other_tx, None);
+
+        let mut clients = simulated_clients(&network, server_addr, 4);
+        connect_all(&mut transport, &mut clients);
+
+        let joined = |rx: &Receiver<ToDenariaServerMessage>| -> Vec<u32> {
+            rx.try_iter()
+                .filter_map(|message| match message {
+                    ToDenariaServerMessage::SessionJoined { session_id, .. } => Some(session_id),
+                    _ => None,
+                })
+                .collect()
+        };
+        assert_eq!(joined(&main_rx), [MAIN_SESSION_ID, MAIN_SESSION_ID]);
+        assert_eq!(joined(&other_rx), [7, 7]);
+        assert!(transport
+            .sessions()
+            .iter()
+            .all(|session| session.players == 2));
+
+        // Full sessions can not be joined either
+        let client_id = *transport.sessions[&7].clients.iter().next().unwrap();
+        transport
+            .from_denaria_server_tx
+            .send(FromDenariaServerMessage::JoinSession {
+                client_id,
+                session_id: MAIN_SESSION_ID,
+            })
+            .unwrap();
+        transport.send_packets();
+        assert!(matches!(
+            other_rx.try_recv(),
+            Ok(ToDenariaServerMessage::SessionJoinFailed { session_id, .. })
+                if session_id == MAIN_SESSION_ID
+        ));
+    }
+
+    #[test]
+    fn reconnects_to_full_sessions_and_caps_overflow_sessions() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut transport = ServerTransport::new(
+            test_server_config(server_addr, 8),
+            network.bind(server_addr),
+        )
+        .unwrap();
+        transport.set_max_players_per_session(1);
+        transport.set_max_open_sessions(1);
+        let (main_tx, _main_rx) = unbounded();
+        transport.add_session(MAIN_SESSION_ID, main_tx, None);
+        // player1 dropped and is link-dead in the main session
+        transport
+            .player_id_session_map
+            .insert("player1".to_string(), MAIN_SESSION_ID);
+
+        let mut clients = simulated_clients(&network, server_addr, 3);
+        connect_all(&mut transport, &mut clients[..1]);
+        connect_all(&mut transport, &mut clients[..2]);
+        assert_eq!(transport.sessions[&MAIN_SESSION_ID].clients.len(), 2);
+
+        connect_all(&mut transport, &mut clients);
+        clients[2].update();
+        assert_eq!(
+            clients[2].disconnect_reason(),
+            Some("every session is full")
+        );
+        assert_eq!(transport.connected_clients(), 2);
+        assert_eq!(transport.sessions.len(), 1);
+    }
+
+    #[test]
+    fn drains_clients_before_shutting_down() {
+        let network = InMemoryNetwork::new();
+        let server_addr: SocketAddr = "127.0.0.1:5000".parse().unwrap();
+        let mut transport = ServerTransport::new(
+            test_server_config(server_addr, 8),
+            network.bind(server_addr),
+        )
+        .unwrap();
+        let (main_tx, main_rx) = unbounded();
+        transport.add_session(MAIN_SESSION_ID, main_tx, None);
+
+        let mut clients = simulated_clients(&network, server_addr, 2);
+        connect_all(&mut transport, &mut clients[..1]);
+        assert_eq!(transport.connected_clients(), 1);
+
+        transport.begin_shutdown(Duration::from_secs(3));
+        while !transport.is_drained() {
+            transport.update(Duration::from_secs(1)).unwrap();
+        }
+        let countdown: Vec<u32> = main_rx
+            .try_iter()
+            .filter_map(|message| match message {
+                ToDenariaServerMessage::ServerClosing { seconds_left } => Some(seconds_left),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(countdown, [3, 2, 1, 0]);
+
+        // New clients are turned away while draining
+        for _ in 0..100 {
+            clients[1].update();
+            transport.update(TICK_DELTA).unwrap();
+        }
+        assert_eq!(
+            clients[1].state(),
+            SimulatedClientState::Denied(DenialReason::ShuttingDown)
+        );
+
+        assert!(transport.shutdown());
+        clients[0].update();
+        assert_eq!(clients[0].state(), SimulatedClientState::Disconnected);
+        assert!(transport.sessions().is_empty());
+        assert!(matches!(
+            main_rx.try_iter().last(),
+            Some(ToDenariaServerMessage::Shutdown)
+        ));
+    }
+}
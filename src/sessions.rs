@@ -16,18 +16,25 @@ use crossbeam::channel::{Receiver, Sender};
 use iyes_perf_ui::PerfUiPlugin;
 
 use crate::{
-    ecs::systems::{
-        debug::{
-            look_debug_camera, move_debug_camera, set_debug_3d_render_camera, set_debug_metrics,
-            set_debug_metrics_cam,
+    constants::SESSION_TICK_RATE,
+    ecs::{
+        components::{MovementSettings, ReconnectGracePeriod},
+        systems::{
+            debug::{
+                look_debug_camera, move_debug_camera, set_debug_3d_render_camera,
+                set_debug_metrics, set_debug_metrics_cam,
+            },
+            handle_events::{
+                despawn_link_dead_players, handle_character_movement, handle_connect_events,
+                handle_disconnect_events, handle_look_events, handle_spawn_events,
+            },
+            handle_server::{
+                exit_on_shutdown, handle_outgoing_messages, handle_server_events,
+                handle_server_messages, notify_session_started,
+            },
+            on_change::{on_spawn_change, on_transform_change},
+            setup::{setup, setup_level},
         },
-        handle_events::{
-            handle_character_movement, handle_disconnect_events, handle_look_events,
-            handle_spawn_events,
-        },
-        handle_server::{handle_outgoing_messages, handle_server_events, handle_server_messages},
-        on_change::{on_spawn_change, on_transform_change},
-        setup::{setup, setup_level},
     },
     server::{
         connection::ConnectionConfig,
@@ -36,14 +43,39 @@ use crate::{
     },
 };
 
+/// Settings every session is created with.
+#[derive(Debug, Clone)]
+pub struct SessionConfig {
+    /// Time between two updates of the session.
+    pub tick: Duration,
+    /// Channels and bandwidth of the connections of the players.
+    pub connection: ConnectionConfig,
+    pub reconnect_grace_period: ReconnectGracePeriod,
+    pub movement: MovementSettings,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            tick: Duration::from_secs(1) / SESSION_TICK_RATE,
+            connection: ConnectionConfig::default(),
+            reconnect_grace_period: ReconnectGracePeriod::default(),
+            movement: MovementSettings::default(),
+        }
+    }
+}
+
 pub fn new_session(
+    session_id: u32,
+    config: SessionConfig,
     to_transport_server_tx: Sender<FromDenariaServerMessage>,
     from_transport_server_rx: Receiver<ToDenariaServerMessage>,
 ) {
-    tracing::info!("Creating new session");
+    tracing::info!("Creating session {session_id}");
 
     let server = DenariaServer::new(
-        ConnectionConfig::default(),
+        session_id,
+        config.connection,
         from_transport_server_rx,
         to_transport_server_tx,
     );
@@ -51,6 +83,8 @@ pub fn new_session(
     let mut app = App::new();
 
     app.insert_resource(server);
+    app.insert_resource(config.reconnect_grace_period);
+    app.insert_resource(config.movement);
 
     let enable_debug_metrics =
         std::env::var("ENABLE_DEBUG_METRICS").is_ok_and(|v| v.to_lowercase() == "true");
@@ -58,9 +92,7 @@ pub fn new_session(
         std::env::var("ENABLE_DEBUG_CAM").is_ok_and(|v| v.to_lowercase() == "true");
 
     if !enable_debug_metrics && !enable_debug_cam {
-        app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(
-            Duration::from_secs_f64(1.0 / 30.0),
-        )));
+        app.add_plugins(MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(config.tick)));
     } else {
         app.add_plugins(DefaultPlugins)
             .add_plugins(FrameTimeDiagnosticsPlugin)
@@ -79,12 +111,15 @@ pub fn new_session(
     }
 
     app.add_plugins(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_systems(Startup, (setup, setup_level).chain())
+        .add_systems(
+            Startup,
+            (setup, setup_level, notify_session_started).chain(),
+        )
         .add_systems(
             PreUpdate,
             (handle_server_events, handle_server_messages).chain(),
         )
-        .add_systems(PostUpdate, handle_outgoing_messages)
+        .add_systems(PostUpdate, (handle_outgoing_messages, exit_on_shutdown))
         .add_systems(
             Update,
             (
@@ -92,7 +127,10 @@ pub fn new_session(
                     handle_character_movement,
                     handle_look_events,
                     handle_spawn_events,
+                    handle_connect_events,
                     handle_disconnect_events,
+                    // Players reconnecting in this tick are not despawned
+                    despawn_link_dead_players.after(handle_connect_events),
                 )
                     .in_set(MySet::HandleGameEvents),
                 (on_spawn_change, on_transform_change).after(MySet::HandleGameEvents),
@@ -100,6 +138,7 @@ pub fn new_session(
         );
 
     app.run();
+    tracing::info!("Session {session_id} stopped");
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash, SystemSet)]